@@ -0,0 +1,386 @@
+//! 为结构体、枚举自动实现pi_bon的Encode、Decode
+//!
+//! 结构体写为一个容器，容器类型为根据类型名和字段名计算的4字节hash，字段按声明顺序依次写入；
+//! 枚举同样写为一个容器，先写变体的序号（u32），再依次写变体的字段。
+//! 泛型参数会自动加上Encode或Decode约束。
+//!
+//! 用#[bon(version = N)]指定版本后，容器体先写版本号（u32），再写字段，类型hash只由类型名计算，不随字段变化；
+//! 解码时版本号不等于N，调用#[bon(migrate = f)]指定的迁移函数（可以是函数路径或闭包），
+//! 签名为fn(u32, &mut ReadBuffer) -> Result<Self, ReadBonErr>，参数为版本号和版本号之后的容器体。
+//!
+//! 无负载的枚举（C风格枚举）可以用#[bon(repr = "int")]只写判别值（u32），不使用容器，与pi_bon::impl_int_enum!的编码相同。
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Expr, Fields, GenericParam, Ident, Index,
+    LitInt, LitStr,
+};
+
+#[proc_macro_derive(Encode, attributes(bon))]
+pub fn derive_encode(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    let attrs = match parse_attrs(&input) {
+        Ok(attrs) => attrs,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    add_bound(&mut input, quote!(::pi_bon::Encode));
+    let name = &input.ident;
+    if attrs.repr_int {
+        let variants = match int_variants(&input) {
+            Ok(variants) => variants,
+            Err(e) => return e.to_compile_error().into(),
+        };
+        // 写入的是声明的判别值，不能超出u32的范围，否则不同的判别值可能写成相同的值
+        return quote! {
+            const _: () = {
+                #(assert!(
+                    #name::#variants as i128 >= 0 && #name::#variants as i128 <= u32::MAX as i128,
+                    concat!("discriminant of ", stringify!(#name::#variants), " out of u32 range")
+                );)*
+            };
+
+            impl ::pi_bon::Encode for #name {
+                fn encode(&self, bb: &mut ::pi_bon::WriteBuffer) {
+                    match self {
+                        #(#name::#variants => bb.write_u32(#name::#variants as u32),)*
+                    }
+                }
+            }
+        }
+        .into();
+    }
+    let hash = type_hash(&input, &attrs);
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let writes = data.fields.iter().enumerate().map(|(i, f)| match &f.ident {
+                Some(ident) => quote!(::pi_bon::Encode::encode(&v.#ident, w);),
+                None => {
+                    let index = Index::from(i);
+                    quote!(::pi_bon::Encode::encode(&v.#index, w);)
+                }
+            });
+            quote!(#(#writes)*)
+        }
+        Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate().map(|(i, var)| {
+                let index = i as u32;
+                let ident = &var.ident;
+                let names = binding_names(&var.fields);
+                let pattern = fields_pattern(&var.fields, &names);
+                quote! {
+                    #name::#ident #pattern => {
+                        w.write_u32(#index);
+                        #(::pi_bon::Encode::encode(#names, w);)*
+                    }
+                }
+            });
+            quote! {
+                match v {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return syn::Error::new(Span::call_site(), "Encode can not derive for union")
+                .to_compile_error()
+                .into()
+        }
+    };
+    let version = attrs.version.map(|v| quote!(w.write_u32(#v);));
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::pi_bon::Encode for #name #ty_generics #where_clause {
+            fn encode(&self, bb: &mut ::pi_bon::WriteBuffer) {
+                bb.write_container_typed(
+                    #hash,
+                    self,
+                    |w, v| {
+                        #version
+                        #body
+                    },
+                    None,
+                );
+            }
+        }
+    }
+    .into()
+}
+
+#[proc_macro_derive(Decode, attributes(bon))]
+pub fn derive_decode(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    let attrs = match parse_attrs(&input) {
+        Ok(attrs) => attrs,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    add_bound(&mut input, quote!(::pi_bon::Decode));
+    let name = &input.ident;
+    if attrs.repr_int {
+        let variants = match int_variants(&input) {
+            Ok(variants) => variants,
+            Err(e) => return e.to_compile_error().into(),
+        };
+        return quote! {
+            impl ::pi_bon::Decode for #name {
+                fn decode(bb: &mut ::pi_bon::ReadBuffer) -> Result<Self, ::pi_bon::ReadBonErr> {
+                    let v = bb.read_u32()?;
+                    #(if v == #name::#variants as u32 {
+                        return Ok(#name::#variants);
+                    })*
+                    Err(::pi_bon::ReadBonErr::Other(format!(
+                        "unknown discriminant {} for {}",
+                        v,
+                        stringify!(#name)
+                    )))
+                }
+            }
+        }
+        .into();
+    }
+    let hash = type_hash(&input, &attrs);
+    let body = match &input.data {
+        Data::Struct(data) => construct(quote!(#name), &data.fields),
+        Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate().map(|(i, var)| {
+                let index = i as u32;
+                let ident = &var.ident;
+                let value = construct(quote!(#name::#ident), &var.fields);
+                quote!(#index => #value,)
+            });
+            quote! {
+                match r.read_u32()? {
+                    #(#arms)*
+                    i => {
+                        return Err(::pi_bon::ReadBonErr::Other(format!(
+                            "unknown variant {} of {}",
+                            i,
+                            stringify!(#name)
+                        )))
+                    }
+                }
+            }
+        }
+        Data::Union(_) => {
+            return syn::Error::new(Span::call_site(), "Decode can not derive for union")
+                .to_compile_error()
+                .into()
+        }
+    };
+    let body = match attrs.version {
+        Some(version) => {
+            let migrate = match &attrs.migrate {
+                Some(migrate) => quote! {{
+                    let migrate: fn(u32, &mut ::pi_bon::ReadBuffer) -> Result<Self, ::pi_bon::ReadBonErr> = #migrate;
+                    migrate(version, r)?
+                }},
+                None => quote! {
+                    return Err(::pi_bon::ReadBonErr::Other(format!(
+                        "unsupported version {} of {}, expect {}",
+                        version,
+                        stringify!(#name),
+                        #version
+                    )))
+                },
+            };
+            quote! {
+                match r.read_u32()? {
+                    #version => #body,
+                    version => #migrate,
+                }
+            }
+        }
+        None => body,
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::pi_bon::Decode for #name #ty_generics #where_clause {
+            fn decode(bb: &mut ::pi_bon::ReadBuffer) -> Result<Self, ::pi_bon::ReadBonErr> {
+                let mut c = bb.read_container_scoped()?;
+                if c.type_id() != #hash {
+                    return Err(::pi_bon::ReadBonErr::Other(format!(
+                        "type hash of {} mismatch, expect {}, but it's {}",
+                        stringify!(#name),
+                        #hash,
+                        c.type_id()
+                    )));
+                }
+                let r = c.body();
+                let v = #body;
+                c.finish();
+                Ok(v)
+            }
+        }
+    }
+    .into()
+}
+
+// #[bon(...)]属性
+#[derive(Default)]
+struct Attrs {
+    // 版本号
+    version: Option<u32>,
+    // 旧版本的迁移函数
+    migrate: Option<Expr>,
+    // 只写判别值，repr = "int"
+    repr_int: bool,
+}
+
+fn parse_attrs(input: &DeriveInput) -> syn::Result<Attrs> {
+    let mut attrs = Attrs::default();
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("bon")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("version") {
+                attrs.version = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            } else if meta.path.is_ident("migrate") {
+                attrs.migrate = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("repr") {
+                let repr: LitStr = meta.value()?.parse()?;
+                if repr.value() != "int" {
+                    return Err(syn::Error::new(
+                        repr.span(),
+                        "bon(repr) only supports \"int\"",
+                    ));
+                }
+                attrs.repr_int = true;
+            } else {
+                return Err(meta.error("unsupported bon attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    if attrs.migrate.is_some() && attrs.version.is_none() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "bon(migrate) requires bon(version)",
+        ));
+    }
+    if attrs.repr_int && attrs.version.is_some() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "bon(repr = \"int\") can not be used with bon(version)",
+        ));
+    }
+    Ok(attrs)
+}
+
+// repr = "int"的枚举的各变体，只支持无负载、无泛型的枚举
+fn int_variants(input: &DeriveInput) -> syn::Result<Vec<&Ident>> {
+    let data = match &input.data {
+        Data::Enum(data) if input.generics.params.is_empty() => data,
+        _ => {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "bon(repr = \"int\") only supports enums without generics",
+            ))
+        }
+    };
+    data.variants
+        .iter()
+        .map(|var| match var.fields {
+            Fields::Unit => Ok(&var.ident),
+            _ => Err(syn::Error::new_spanned(
+                var,
+                "bon(repr = \"int\") only supports variants without fields",
+            )),
+        })
+        .collect()
+}
+
+// 为每个泛型参数加上约束
+fn add_bound(input: &mut DeriveInput, bound: TokenStream2) {
+    for param in input.generics.params.iter_mut() {
+        if let GenericParam::Type(t) = param {
+            t.bounds.push(parse_quote!(#bound));
+        }
+    }
+}
+
+// 根据类型名和字段名生成容器类型的常量表达式，由pi_bon::type_hash计算，与serde序列化的规则相同
+fn type_hash(input: &DeriveInput, attrs: &Attrs) -> TokenStream2 {
+    let desc = type_desc(input, attrs);
+    quote!({
+        const HASH: u32 = ::pi_bon::type_hash(#desc);
+        HASH
+    })
+}
+
+// 类型的描述：类型名加字段名；指定了版本时，只使用类型名，以便不同版本的数据可以互相识别
+fn type_desc(input: &DeriveInput, attrs: &Attrs) -> String {
+    let mut desc = input.ident.to_string();
+    match &input.data {
+        _ if attrs.version.is_some() => (),
+        Data::Struct(data) => desc.push_str(&fields_desc(&data.fields)),
+        Data::Enum(data) => {
+            desc.push('{');
+            for var in data.variants.iter() {
+                desc.push_str(&var.ident.to_string());
+                desc.push_str(&fields_desc(&var.fields));
+                desc.push(',');
+            }
+            desc.push('}');
+        }
+        Data::Union(_) => (),
+    }
+    desc
+}
+
+// 字段的描述，命名字段为{a,b}，匿名字段为(2)，单元为空
+fn fields_desc(fields: &Fields) -> String {
+    match fields {
+        Fields::Named(named) => {
+            let names: Vec<String> = named
+                .named
+                .iter()
+                .map(|f| f.ident.as_ref().unwrap().to_string())
+                .collect();
+            format!("{{{}}}", names.join(","))
+        }
+        Fields::Unnamed(unnamed) => format!("({})", unnamed.unnamed.len()),
+        Fields::Unit => String::new(),
+    }
+}
+
+// 枚举变体中各字段绑定的变量名
+fn binding_names(fields: &Fields) -> Vec<Ident> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| match &f.ident {
+            Some(ident) => format_ident!("f_{}", ident),
+            None => format_ident!("f_{}", i),
+        })
+        .collect()
+}
+
+// 匹配枚举变体的模式
+fn fields_pattern(fields: &Fields, names: &[Ident]) -> TokenStream2 {
+    match fields {
+        Fields::Named(named) => {
+            let idents = named.named.iter().map(|f| f.ident.as_ref().unwrap());
+            quote!({ #(#idents: #names),* })
+        }
+        Fields::Unnamed(_) => quote!(( #(#names),* )),
+        Fields::Unit => quote!(),
+    }
+}
+
+// 依次读出各字段，构造结构体或枚举变体
+fn construct(path: TokenStream2, fields: &Fields) -> TokenStream2 {
+    let reads = fields
+        .iter()
+        .map(|f| {
+            let ty = &f.ty;
+            quote!(<#ty as ::pi_bon::Decode>::decode(r)?)
+        })
+        .collect::<Vec<_>>();
+    match fields {
+        Fields::Named(named) => {
+            let idents = named.named.iter().map(|f| f.ident.as_ref().unwrap());
+            quote!(#path { #(#idents: #reads),* })
+        }
+        Fields::Unnamed(_) => quote!(#path ( #(#reads),* )),
+        Fields::Unit => quote!(#path),
+    }
+}
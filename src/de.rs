@@ -0,0 +1,362 @@
+//! 为ReadBuffer实现serde::Deserializer，需开启serde特性，与ser模块的映射规则对应
+//!
+//! 协议是自描述的，deserialize_any根据类型值选择visitor：容器中，通用map（类型3）读为map，其它读为序列
+
+use std::fmt;
+
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+
+use crate::{ReadBonErr, ReadBuffer};
+
+impl de::Error for ReadBonErr {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ReadBonErr::other(msg.to_string())
+    }
+}
+
+/// serde反序列化器，从ReadBuffer中读取值
+///
+/// ```ignore
+/// let mut rb = ReadBuffer::new(bin, 0);
+/// let v = MyStruct::deserialize(&mut BonDeserializer::new(&mut rb))?;
+/// ```
+pub struct BonDeserializer<'a, 'b> {
+    rb: &'b mut ReadBuffer<'a>,
+}
+
+impl<'a, 'b> BonDeserializer<'a, 'b> {
+    pub fn new(rb: &'b mut ReadBuffer<'a>) -> Self {
+        BonDeserializer { rb }
+    }
+
+    // 读一个容器，容器体交给visit处理，处理完后跳过整个容器
+    fn container<V, F>(&mut self, visit: F) -> Result<V, ReadBonErr>
+    where
+        F: FnOnce(u32, Option<u32>, ReadBuffer<'a>) -> Result<V, ReadBonErr>,
+    {
+        let c = self.rb.read_container_scoped()?;
+        let r = visit(c.type_id, c.count, c.body.clone())?;
+        c.finish();
+        Ok(r)
+    }
+}
+
+impl<'de, 'b> de::Deserializer<'de> for &mut BonDeserializer<'de, 'b> {
+    type Error = ReadBonErr;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        let t = self.rb.get_type_chunk()?;
+        match t {
+            0 => {
+                self.rb.skip_bytes(1);
+                visitor.visit_unit()
+            }
+            1 | 2 => visitor.visit_bool(self.rb.read_bool()?),
+            3..=6 => visitor.visit_f32(self.rb.read_f32()?),
+            7 => visitor.visit_f64(self.rb.read_f64()?),
+            9..=41 => match self.rb.read_any_int()? {
+                v if v >= 0 && v <= u64::MAX as i128 => visitor.visit_u64(v as u64),
+                v if v < 0 && v >= i64::MIN as i128 => visitor.visit_i64(v as i64),
+                v if v >= 0 => visitor.visit_u128(v as u128),
+                v => visitor.visit_i128(v),
+            },
+            42..=110 => self.deserialize_str(visitor),
+            111..=179 => visitor.visit_byte_buf(self.rb.read_bin()?),
+            180..=248 => self.container(|type_id, count, body| match type_id {
+                3 => visitor.visit_map(Elements { body, count }),
+                _ => visitor.visit_seq(Elements { body, count }),
+            }),
+            _ => Err(ReadBonErr::type_no_match(
+                "serde value".to_string(),
+                t,
+                self.rb.head,
+            )),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_bool(self.rb.read_bool()?)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_i8(self.rb.read_i8()?)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_i16(self.rb.read_i16()?)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_i32(self.rb.read_i32()?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_i64(self.rb.read_i64()?)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_i128(self.rb.read_i128()?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_u8(self.rb.read_u8()?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_u16(self.rb.read_u16()?)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_u32(self.rb.read_u32()?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_u64(self.rb.read_u64()?)
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_u128(self.rb.read_u128()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_f32(self.rb.read_f32()?)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_f64(self.rb.read_f64()?)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        let s = self.rb.read_utf8()?;
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(ReadBonErr::other(format!(
+                "expect a char, but it's {:?}",
+                s
+            ))),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        let bytes = self.rb.read_str_bytes()?;
+        match std::str::from_utf8(bytes) {
+            Ok(s) => visitor.visit_borrowed_str(s),
+            Err(_) => visitor.visit_string(String::from_utf8_lossy(bytes).to_string()),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_byte_buf(self.rb.read_bin()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_byte_buf(self.rb.read_bin()?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        match self.rb.is_nil()? {
+            true => visitor.visit_none(),
+            false => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        match self.rb.is_nil()? {
+            true => visitor.visit_unit(),
+            false => Err(ReadBonErr::type_no_match(
+                "unit".to_string(),
+                self.rb.get_type_chunk()?,
+                self.rb.head,
+            )),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ReadBonErr> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        self.container(|_, count, body| visitor.visit_seq(Elements { body, count }))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ReadBonErr> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ReadBonErr> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        self.container(|_, count, body| visitor.visit_map(Elements { body, count }))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ReadBonErr> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ReadBonErr> {
+        match self.rb.get_type_chunk()? {
+            // 单元枚举值只有序号
+            9..=41 => visitor.visit_enum(self.rb.read_u32()?.into_deserializer()),
+            _ => self.container(|_, _, mut body| {
+                let index = body.read_u32()?;
+                visitor.visit_enum(Variant { index, body })
+            }),
+        }
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReadBonErr> {
+        self.rb.read_raw()?;
+        visitor.visit_unit()
+    }
+}
+
+// 依次访问容器体中的元素，用于序列和map
+struct Elements<'a> {
+    body: ReadBuffer<'a>,
+    count: Option<u32>,
+}
+
+impl<'de> Elements<'de> {
+    fn next<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, ReadBonErr> {
+        if self.body.bytes.is_empty() {
+            return Ok(None);
+        }
+        seed.deserialize(&mut BonDeserializer::new(&mut self.body))
+            .map(Some)
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for Elements<'de> {
+    type Error = ReadBonErr;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, ReadBonErr> {
+        self.next(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.count.map(|c| c as usize)
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Elements<'de> {
+    type Error = ReadBonErr;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ReadBonErr> {
+        self.next(seed)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ReadBonErr> {
+        seed.deserialize(&mut BonDeserializer::new(&mut self.body))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.count.map(|c| c as usize)
+    }
+}
+
+// 带数据的枚举值，body为序号之后的字段
+struct Variant<'a> {
+    index: u32,
+    body: ReadBuffer<'a>,
+}
+
+impl<'de> de::EnumAccess<'de> for Variant<'de> {
+    type Error = ReadBonErr;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), ReadBonErr> {
+        let v = seed.deserialize(self.index.into_deserializer())?;
+        Ok((v, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant<'de> {
+    type Error = ReadBonErr;
+
+    fn unit_variant(self) -> Result<(), ReadBonErr> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        mut self,
+        seed: T,
+    ) -> Result<T::Value, ReadBonErr> {
+        seed.deserialize(&mut BonDeserializer::new(&mut self.body))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_seq(Elements {
+            body: self.body,
+            count: None,
+        })
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ReadBonErr> {
+        visitor.visit_seq(Elements {
+            body: self.body,
+            count: None,
+        })
+    }
+}
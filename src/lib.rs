@@ -8,7 +8,11 @@
 //!
 //! 提供读的接口，将满足协议的二进制反序列化为数据。
 //!
-//! TODO 为本协议实现Serializer、Deserializer
+//! 开启serde特性时，提供serde的序列化器BonSerializer（见ser模块）和反序列化器BonDeserializer（见de模块）
+//!
+//! 开启derive特性时，可以用#[derive(Encode, Decode)]为结构体、枚举实现Encode、Decode（见pi_bon_derive）
+//!
+//! 开启half特性时，可以用half::f16读写16位浮点数；未开启时，读f32、f64及通用的read也会将16位浮点数扩展读出
 //!
 //! 协议内容：
 //!
@@ -18,6 +22,7 @@
 //! 1=false
 //! 2=true
 //! 3=浮点数0.0，4=浮点数1.0，5=16位浮点数，6=32位浮点数，7=64位浮点数，8=128位浮点数;
+//! 3和4不记录精度，f32和f64的0.0、1.0写入后相同，通用的read总是将其读为F32
 //! 9=8位负整数，10=16位负整数，11=32位负整数，12=48位负整数，13=64位负整数，14=128位负整数
 //! 15~35= -1~19
 //! 36=8位正整数，37=16位正整数，38=32位正整数，39=48位正整数，40=64位正整数，41=128位正整数
@@ -32,34 +37,83 @@
 //! 245=8位长度的容器，246=16位长度的容器，247=32位长度的容器，248=48位长度的容器
 //! 之后的一个4字节的整数表示类型。
 //! 类型：
-//! 	0 表示忽略
+//! 	0 表示忽略，也用于给任意值包装一个可跳过的大小描述（见WriteBuffer::write_sized），读时解包为内部的值
 //! 	1 通用对象
 //! 	2 通用数组
 //! 	3 通用map
+//! 	4 有符号整数标记，容器体为一个整数，5 无符号整数标记，同上（见WriteBuffer::write_tagged_int）
+//! 	6 稀疏数组，容器体为数组总长度，及依次排列的非默认值元素的（序号，值）（见WriteBuffer::write_sparse_array）
+//! 	0xFFFF0000~0xFFFFFFFF 保留给扩展类型（见CodecRegistry）
 
 //! 如果是通用对象、数组、map，后面会有一个动态长度的整数，表示元素的数量。
+//! 动态长度：0xxxxxxx为1字节，10xxxxxx xxxxxxxx为2字节，110xxxxx xxxxxxxx xxxxxxxx xxxxxxxx为4字节，
+//! 多字节时为大端序，以保证标志位在第一个字节（0.2.6及之前按本机字节序写入，小端机器上写出的多字节长度无法读出，见CHANGELOG）
+
+//! 251=小map，之后的1个字节表示map体的总大小，没有类型，map体为依次排列的键值对，
+//! 键值的类型由使用者约定
+
+//! 249=256位正整数，250=256位负整数，之后为32字节的小端数据（绝对值）
 
-//! 容器，由于有总大小的描述，从而可以只对感兴趣的部分作反序列化
-//! TODO 定义一个全类型的枚举 enum BonType<T>， ReadNext WriteNext 的 T 应该为BonType。提供一个 read(&self) -> BonType<T>
+//! 252=引用，之后的4字节整数表示被引用的值相对于引用区域起始处的偏移（见RefWriter）
+
+//! 253~255保留，读到时返回ReadBonErr::ReservedType
+
+//! 容器，由于有总大小的描述，从而可以只对感兴趣的部分作反序列化（见BonType、ReadBuffer::read_typed）
 
 #![allow(warnings)]
-#![feature(exclusive_range_pattern)]
-#![feature(test)]
-#[warn(unconditional_recursion)]
+#![cfg_attr(all(test, feature = "bench"), feature(test))]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+#![warn(unconditional_recursion)]
 // extern crate pi_data_view;
+#[cfg(feature = "allocator_api")]
+use std::alloc::Allocator;
+use std::any::{Any, TypeId};
+use std::cell::{Cell, RefCell};
 use std::cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
 use std::error::Error;
 use std::fmt;
 use std::hash::Hash;
 use std::marker::Sized;
 use std::ops::Deref;
-use std::ops::Range;
-use std::sync::Arc;
+use std::ops::{Range, RangeInclusive};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
 use bytes::{Buf, BufMut, Bytes};
 use num_bigint::{BigInt, Sign};
 
+// 使派生宏生成的::pi_bon路径在本crate内也可用
+extern crate self as pi_bon;
+
+#[cfg(feature = "derive")]
+pub use pi_bon_derive::{Decode, Encode};
+#[cfg(feature = "serde")]
+pub mod de;
+#[cfg(feature = "serde")]
+pub mod ser;
+#[cfg(feature = "serde")]
+pub use de::BonDeserializer;
+#[cfg(feature = "serde")]
+pub use ser::{BonSerializer, SerializeErr};
+
+// 整数类型（9~14， 36~41）对应的数值字节数
+const INT_WIDTH: [usize; 6] = [1, 2, 4, 6, 8, 16];
+
+/// 整数、浮点数数值的字节序
+///
+/// 协议规定使用小端，大端仅用于与要求大端的旧系统互通，不是标准的bon格式：类型、长度、容器类型等仍为小端，
+/// 只有整数、浮点数的数值部分按大端读写，且比较等其它接口均按小端处理
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
 /// ReadBuffer，用于将二进制反序列化为对应数据
 #[derive(Default, Clone, Debug)]
 pub struct ReadBuffer<'a> {
@@ -67,8 +121,21 @@ pub struct ReadBuffer<'a> {
     pub bytes: &'a [u8],
     // 头部指针
     pub head: usize,
+    // 严格模式，开启时会拒绝一些可以容忍的不规范数据，如map中重复的键
+    strict: bool,
+    // 结构体字段名表，读取结构体容器时，根据其hash附加字段名
+    schemas: Option<Arc<SchemaRegistry>>,
+    // 剩余的可分配字节数，为None时不限制，子ReadBuffer共享同一个预算
+    alloc_budget: Option<Arc<AtomicUsize>>,
+    // 数值的字节序
+    endian: Endian,
+    // 是否跳过WriteBuffer::set_debug_align写入的填充
+    debug_padding: bool,
 }
 
+/// 结构体字段名表，键为结构体hash，值为按顺序排列的字段名
+pub type SchemaRegistry = HashMap<u32, Vec<String>>;
+
 /// 定义读时的错误
 #[derive(Clone, Debug)]
 pub enum ReadBonErr {
@@ -83,6 +150,8 @@ pub enum ReadBonErr {
     },
     Other(String),
     IsContainer(u8),
+    /// 类型值为尚未分配的保留值（253~255），可能是更新版本的协议写入的数据，而不是数据损坏
+    ReservedType(u8),
 }
 
 impl fmt::Display for ReadBonErr {
@@ -104,6 +173,7 @@ impl fmt::Display for ReadBonErr {
             ),
             ReadBonErr::Other(s) => write!(f, "ReadBonError Other other = {:?}", s),
             ReadBonErr::IsContainer(_) => write!(f, "IsContainer!"),
+            ReadBonErr::ReservedType(t) => write!(f, "ReadBonError ReservedType type = {:?}", t),
         }
     }
 }
@@ -126,14 +196,16 @@ impl ReadBonErr {
             2 => "true".to_string(),
             3 => "0.0".to_string(),
             4 => "1.0".to_string(),
-            5..9 => "float".to_string(),
-            9..15 => "int".to_string(),
+            5..=8 => "float".to_string(),
+            9..=14 => "int".to_string(),
             15 => "-1".to_string(),
-            16..36 => (type_code - 16).to_string(),
-            36..42 => "uint".to_string(),
-            42..111 => "string".to_string(),
-            111..180 => "bin".to_string(),
-            180..249 => "container".to_string(),
+            16..=35 => (type_code - 16).to_string(),
+            36..=41 => "uint".to_string(),
+            42..=110 => "string".to_string(),
+            111..=179 => "bin".to_string(),
+            180..=248 => "container".to_string(),
+            251 => "small map".to_string(),
+            252 => "reference".to_string(),
             _ => "invalid type".to_string(),
         };
 
@@ -147,54 +219,90 @@ impl ReadBonErr {
     fn other(message: String) -> ReadBonErr {
         ReadBonErr::Other(message)
     }
+
+    /// 如果错误是IsContainer，返回容器的类型值
+    ///
+    /// 通用的read已经可以读容器，不再返回IsContainer，保留此方法以兼容旧代码
+    pub fn is_container(&self) -> Option<u8> {
+        match self {
+            ReadBonErr::IsContainer(t) => Some(*t),
+            _ => None,
+        }
+    }
 }
 
 impl<'a> PartialOrd for ReadBuffer<'a> {
     fn partial_cmp(&self, other: &ReadBuffer<'a>) -> Option<Ordering> {
-        let mut b1 = ReadBuffer::new(self.bytes, 0);
-        let mut b2 = ReadBuffer::new(other.bytes, 0);
-        let b1_type = b1.get_type_chunk().unwrap();
-        let b2_type = b2.get_type_chunk().unwrap();
+        self.try_cmp(other).ok()
+    }
+}
+
+impl<'a> ReadBuffer<'a> {
+    /// 按协议比较两个ReadBuffer中的全部值，与partial_cmp相同，但无法比较时返回具体的原因（值不完整、类型值无效、128位浮点数等）
+    pub fn try_cmp(&self, other: &ReadBuffer<'a>) -> Result<Ordering, ReadBonErr> {
+        let mut b1 = ReadBuffer::new(self.bytes, 0).with_endianness(self.endian);
+        let mut b2 = ReadBuffer::new(other.bytes, 0).with_endianness(other.endian);
+        let b1_type = b1.get_type_chunk()?;
+        let b2_type = b2.get_type_chunk()?;
 
         let is_b1_container = b1_type >= 180 && b1_type < 249;
         let is_b2_container = b2_type >= 180 && b2_type < 249;
 
         if is_b1_container && is_b2_container {
+            // 容器头不完整或容器体不足4字节类型时无法比较
+            for b in [&b1, &b2] {
+                let (header_len, body_len) = container_header(b.bytes)?;
+                b.probe_border(header_len + body_len)?;
+                if body_len < 4 {
+                    return Err(ReadBonErr::other(format!(
+                        "container body too short, len = {}",
+                        body_len
+                    )));
+                }
+            }
             match b1_type {
-                180..246 => b1.head += 1 + 1 + 4, // 1字节类型 + "可变长度"占用的字节 + 4字节哈希
+                180..=244 => b1.head += 1 + 4, // 1字节类型 + "可变长度"占用的字节 + 4字节哈希
+                245 => b1.head += 1 + 1 + 4,
                 246 => b1.head += 1 + 2 + 4,
                 247 => b1.head += 1 + 4 + 4,
                 248 => b1.head += 1 + 6 + 4,
-                _ => panic!("unknown container type {:?}", b1_type),
+                _ => unreachable!(),
             }
             b1.bytes.advance(b1.head);
             match b2_type {
-                180..246 => b2.head += 1 + 1 + 4,
+                180..=244 => b2.head += 1 + 4,
+                245 => b2.head += 1 + 1 + 4,
                 246 => b2.head += 1 + 2 + 4,
                 247 => b2.head += 1 + 4 + 4,
                 248 => b2.head += 1 + 6 + 4,
-                _ => panic!("unknown container type {:?}", b2_type),
+                _ => unreachable!(),
             }
             b2.bytes.advance(b2.head);
         }
 
         loop {
-            match partial_cmp(&mut b1, &mut b2) {
-                None => return None,
-                Some(Ordering::Equal) => {
+            match try_partial_cmp(&mut b1, &mut b2)? {
+                Ordering::Equal => {
                     if b1.len() == 0 {
-                        return Some(Ordering::Equal);
+                        return Ok(Ordering::Equal);
                     }
                 }
-                Some(Ordering::Greater) => return Some(Ordering::Greater),
-                Some(Ordering::Less) => return Some(Ordering::Less),
+                r => return Ok(r),
             }
         }
     }
 }
 
+/// 按协议比较是否相等
+///
+/// 字节完全相同时直接返回true，即使内容无法解析（此时partial_cmp返回None），以保证Eq要求的自反性；
+/// 字节不同且无法比较时返回false
 impl<'a> PartialEq for ReadBuffer<'a> {
     fn eq(&self, other: &ReadBuffer<'a>) -> bool {
+        // 字节完全相同时一定相等，不需要按协议比较
+        if self.bytes == other.bytes {
+            return true;
+        }
         match self.partial_cmp(other) {
             Some(Ordering::Equal) => return true,
             _ => return false,
@@ -206,20 +314,146 @@ impl<'a> Eq for ReadBuffer<'a> {}
 
 impl<'a> Ord for ReadBuffer<'a> {
     fn cmp(&self, other: &ReadBuffer<'a>) -> Ordering {
-        match self.partial_cmp(other) {
-            Some(v) => v,
-            None => panic!("partial_cmp fail"),
+        match self.try_cmp(other) {
+            Ok(v) => v,
+            Err(e) => panic!("partial_cmp fail: {}", e),
         }
     }
 }
 
 impl<'a> ReadBuffer<'a> {
-    /// 创建ReadBuffer, buf必须符合bon协议， 否则当调用其partial_cmp会直接panic
+    /// 创建ReadBuffer, buf必须符合bon协议， 否则partial_cmp返回None，cmp会直接panic
     /// head指定反序列化开始的位置
     pub fn new(buf: &[u8], head: usize) -> ReadBuffer {
         ReadBuffer {
             bytes: buf,
             head: head,
+            strict: false,
+            schemas: None,
+            alloc_budget: None,
+            endian: Endian::Little,
+            debug_padding: false,
+        }
+    }
+
+    /// 设置是否开启严格模式，严格模式下，反序列化map时遇到重复的键、反序列化set时遇到重复的元素会返回Err（默认后者覆盖前者）；
+    /// 读容器时，元素没有恰好填满容器体（有尾部填充）也返回Err
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// 是否为严格模式
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// 设置结构体字段名表，read读取结构体容器时，将按表中的字段名填充StructValue
+    pub fn with_schema_registry(mut self, registry: Arc<SchemaRegistry>) -> Self {
+        self.schemas = Some(registry);
+        self
+    }
+
+    /// 设置整个消息反序列化时，字符串、二进制、Vec、HashMap、HashSet累计分配的字节数上限，超过时读取返回Err
+    pub fn with_total_alloc_budget(mut self, bytes: usize) -> Self {
+        self.alloc_budget = Some(Arc::new(AtomicUsize::new(bytes)));
+        self
+    }
+
+    /// 读取开启了WriteBuffer::set_debug_align的数据，各个读取方法会先跳过其中的填充容器
+    ///
+    /// 默认关闭：类型为0、容器体全为0的容器同样可以是write_sized写入的值（如多个nil），关闭时按普通的值读取
+    pub fn with_debug_padding(mut self) -> Self {
+        self.debug_padding = true;
+        self
+    }
+
+    /// 设置整数、浮点数数值的字节序，仅用于互通，见Endian
+    pub fn with_endianness(mut self, endian: Endian) -> Self {
+        self.endian = endian;
+        self
+    }
+
+    // 按字节序读数值
+    #[inline]
+    fn payload_u16(&mut self) -> u16 {
+        match self.endian {
+            Endian::Little => self.bytes.get_u16_le(),
+            Endian::Big => self.bytes.get_u16(),
+        }
+    }
+
+    #[inline]
+    fn payload_u32(&mut self) -> u32 {
+        match self.endian {
+            Endian::Little => self.bytes.get_u32_le(),
+            Endian::Big => self.bytes.get_u32(),
+        }
+    }
+
+    #[inline]
+    fn payload_u48(&mut self) -> u64 {
+        match self.endian {
+            Endian::Little => {
+                self.bytes.get_u16_le() as u64 + ((self.bytes.get_u32_le() as u64) << 16)
+            }
+            Endian::Big => ((self.bytes.get_u32() as u64) << 16) + self.bytes.get_u16() as u64,
+        }
+    }
+
+    #[inline]
+    fn payload_u64(&mut self) -> u64 {
+        match self.endian {
+            Endian::Little => self.bytes.get_u64_le(),
+            Endian::Big => self.bytes.get_u64(),
+        }
+    }
+
+    #[inline]
+    fn payload_u128(&mut self) -> u128 {
+        match self.endian {
+            Endian::Little => self.bytes.get_u128_le(),
+            Endian::Big => self.bytes.get_u128(),
+        }
+    }
+
+    #[inline]
+    fn payload_f32(&mut self) -> f32 {
+        f32::from_bits(self.payload_u32())
+    }
+
+    #[inline]
+    fn payload_f64(&mut self) -> f64 {
+        f64::from_bits(self.payload_u64())
+    }
+
+    // 从分配预算中扣除len个字节，预算不足时返回Err
+    fn charge_alloc(&self, len: usize) -> Result<(), ReadBonErr> {
+        if let Some(budget) = &self.alloc_budget {
+            if budget
+                .fetch_update(AtomicOrdering::Relaxed, AtomicOrdering::Relaxed, |v| {
+                    v.checked_sub(len)
+                })
+                .is_err()
+            {
+                return Err(ReadBonErr::other(format!(
+                    "alloc budget exceeded, try alloc {}",
+                    len
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // 创建一个读取bytes的ReadBuffer，继承当前ReadBuffer的设置
+    fn child(&self, bytes: &'a [u8], head: usize) -> ReadBuffer<'a> {
+        ReadBuffer {
+            bytes: bytes,
+            head: head,
+            strict: self.strict,
+            schemas: self.schemas.clone(),
+            alloc_budget: self.alloc_budget.clone(),
+            endian: self.endian,
+            debug_padding: self.debug_padding,
         }
     }
 
@@ -235,9 +469,37 @@ impl<'a> ReadBuffer<'a> {
         self.bytes.len()
     }
 
+    /// 读绝对位置（与head的计数方式相同）offset开始的len个字节，不移动读指针；只能读取尚未读过的部分，越界时返回Err
+    pub fn read_at(&self, offset: usize, len: usize) -> Result<&'a [u8], ReadBonErr> {
+        let start = offset.checked_sub(self.head).ok_or_else(|| {
+            ReadBonErr::other(format!("offset {} is before head {}", offset, self.head))
+        })?;
+        match start.checked_add(len) {
+            Some(end) if end <= self.bytes.len() => Ok(&self.bytes[start..end]),
+            _ => Err(ReadBonErr::overflow(
+                start.saturating_add(len),
+                self.bytes.len(),
+            )),
+        }
+    }
+
+    /// 读二进制末尾4字节的定长整数（按字节序设置，不是bon编码的值），用于读取附加在末尾的尾部信息，不移动读指针
+    pub fn read_u32_at_end(&self) -> Result<u32, ReadBonErr> {
+        let len = self.bytes.len();
+        if len < 4 {
+            return Err(ReadBonErr::overflow(4, len));
+        }
+        let mut b = &self.bytes[len - 4..];
+        Ok(match self.endian {
+            Endian::Little => b.get_u32_le(),
+            Endian::Big => b.get_u32(),
+        })
+    }
+
     /// 获取接下来要反序列化的数据的类型
     #[inline]
     pub fn get_type(&mut self) -> Result<u8, ReadBonErr> {
+        self.skip_debug_padding();
         self.probe_border(1)?;
         Ok(self.bytes.get_u8())
     }
@@ -245,13 +507,108 @@ impl<'a> ReadBuffer<'a> {
     /// 获取接下来要反序列化的数据的类型(不改变bytes偏移)
     #[inline]
     pub fn get_type_chunk(&mut self) -> Result<u8, ReadBonErr> {
+        self.skip_debug_padding();
         self.probe_border(1)?;
         Ok(self.bytes.chunk()[0])
     }
 
+    /// 读下一个值为BonType，与read不同，遇到容器时不报错，而是返回容器头的信息和容器体，由调用者继续读取其中的元素
+    ///
+    /// 128位浮点数、不合法的UTF-8字符串返回Err
+    pub fn read_typed(&mut self) -> Result<BonType<'a>, ReadBonErr> {
+        Ok(match self.get_type_chunk()? {
+            0 => {
+                self.skip_bytes(1);
+                BonType::Null
+            }
+            1 | 2 => BonType::Bool(self.read_bool()?),
+            3..=8 => BonType::Float(self.read_f64()?),
+            9..=41 => match self.read_int_parts()? {
+                (true, m) if m <= i128::MAX as u128 + 1 => BonType::Int((m as i128).wrapping_neg()),
+                (false, m) if m <= i128::MAX as u128 => BonType::Int(m as i128),
+                (false, m) => BonType::UInt(m),
+                (true, _) => {
+                    return Err(ReadBonErr::other(format!(
+                        "integer out of i128 range, head = {}",
+                        self.head
+                    )))
+                }
+            },
+            42..=110 => BonType::Str(self.read_str()?),
+            111..=179 => BonType::Bin(self.read_bin_ref()?),
+            180..=248 => {
+                let (_, len) = container_header(self.bytes)?;
+                let c = self.read_container_scoped()?;
+                let (type_id, count) = (c.type_id, c.count);
+                let body = c.body.clone();
+                c.finish();
+                BonType::Container {
+                    type_id,
+                    count,
+                    len,
+                    body,
+                }
+            }
+            _ => {
+                let (t, bytes) = self.read_raw()?;
+                BonType::Other(t, bytes)
+            }
+        })
+    }
+
+    /// 查看接下来的值的类型（与read读出的EnumValue对应），不移动读指针
+    ///
+    /// 字符串、二进制会给出数据长度，通用数组、map会给出元素数量和容器大小；128位浮点数、BigInt等read不支持的类型返回Err
+    pub fn peek_type(&mut self) -> Result<EnumType, ReadBonErr> {
+        let t = self.get_type_chunk()?;
+        let ty = match t {
+            0 => EnumType::Void,
+            1 | 2 => EnumType::Bool,
+            3..=6 => EnumType::F32,
+            7 | 12 | 39 => EnumType::F64,
+            9 => EnumType::I16,
+            10 => EnumType::I32,
+            11 | 13 => EnumType::I64,
+            14 => EnumType::I128,
+            15 => EnumType::I8,
+            16..=36 => EnumType::U8,
+            37 => EnumType::U16,
+            38 => EnumType::U32,
+            40 => EnumType::U64,
+            41 => EnumType::U128,
+            42..=179 => {
+                // 字符串与二进制的长度描述方式相同
+                let header = match (t - 42) % 69 {
+                    0..=64 => 1,
+                    65 => 2,
+                    66 => 3,
+                    67 => 5,
+                    _ => 7,
+                };
+                let len = (value_len(self.bytes)? - header) as u64;
+                match t < 111 {
+                    true => EnumType::Str(len),
+                    false => EnumType::Bin(len),
+                }
+            }
+            180..=248 => {
+                let mut rb = self.child(self.bytes, self.head);
+                let c = rb.read_container_scoped()?;
+                match (c.type_id(), c.count()) {
+                    (2, Some(count)) => EnumType::Arr(count, c.size as u64),
+                    (3, Some(count)) => EnumType::Map(count, c.size as u64),
+                    (type_id, _) => EnumType::Struct(type_id as u64),
+                }
+            }
+            _ => return Err(ReadBonErr::type_no_match("value".to_string(), t, self.head)),
+        };
+        Ok(ty)
+    }
+
     /// 读一个布尔类型，如果二进制当前的值不是布尔类型，返回Err
     #[inline]
     pub fn read_bool(&mut self) -> Result<bool, ReadBonErr> {
+        self.skip_debug_padding();
         self.probe_border(1)?;
         let t = self.bytes.get_u8();
         self.head += 1;
@@ -286,6 +643,25 @@ impl<'a> ReadBuffer<'a> {
         self.read_integer::<u32>()
     }
 
+    /// 严格读一个u32类型，值以负整数的类型（9~15）存储时，即使能转换也返回TypeNoMatch，值超出u32范围时同样返回Err
+    ///
+    /// 无符号字段读到负数编码的值，说明协议两端的符号不一致；出错时不消耗数据，可改用其它方法读取
+    pub fn read_u32_strict(&mut self) -> Result<u32, ReadBonErr> {
+        self.skip_debug_padding();
+        self.probe_border(1)?;
+        let t = self.bytes[0];
+        if t >= 9 && t <= 15 {
+            return Err(ReadBonErr::type_no_match("u32".to_string(), t, self.head));
+        }
+        let (bytes, head) = (self.bytes, self.head);
+        let v = self.read_integer::<u64>()?;
+        u32::try_from(v).map_err(|_| {
+            self.bytes = bytes;
+            self.head = head;
+            ReadBonErr::type_no_match("u32".to_string(), t, head)
+        })
+    }
+
     /// 读一个u64类型，如果二进制当前的值不是u64类型，返回Err
     #[inline]
     pub fn read_u64(&mut self) -> Result<u64, ReadBonErr> {
@@ -325,6 +701,20 @@ impl<'a> ReadBuffer<'a> {
         self.read_integer::<i32>()
     }
 
+    /// 严格读一个i32类型，负整数和正整数的类型都接受，值超出i32范围时返回TypeNoMatch，而不是截断；出错时不消耗数据
+    pub fn read_i32_strict(&mut self) -> Result<i32, ReadBonErr> {
+        self.skip_debug_padding();
+        self.probe_border(1)?;
+        let t = self.bytes[0];
+        let (bytes, head) = (self.bytes, self.head);
+        let v = self.read_integer::<i128>()?;
+        i32::try_from(v).map_err(|_| {
+            self.bytes = bytes;
+            self.head = head;
+            ReadBonErr::type_no_match("i32".to_string(), t, head)
+        })
+    }
+
     /// 读一个i61类型，如果二进制当前的值不是i64类型，返回Err
     #[inline]
     pub fn read_i64(&mut self) -> Result<i64, ReadBonErr> {
@@ -344,19 +734,76 @@ impl<'a> ReadBuffer<'a> {
         self.read_integer::<i128>()
     }
 
+    /// 读一个任意宽度的整数，统一返回i128，如果二进制当前的值不是整数类型，或超出i128的范围，返回Err
+    pub fn read_any_int(&mut self) -> Result<i128, ReadBonErr> {
+        let (bytes, head) = (self.bytes, self.head);
+        let (neg, v) = self.read_int_parts()?;
+        if neg && v <= i128::MAX as u128 + 1 {
+            Ok((v as i128).wrapping_neg())
+        } else if !neg && v <= i128::MAX as u128 {
+            Ok(v as i128)
+        } else {
+            self.bytes = bytes;
+            self.head = head;
+            Err(ReadBonErr::other(format!(
+                "integer out of i128 range, head = {}",
+                head
+            )))
+        }
+    }
+
+    // 读一个任意宽度的整数，返回（是否为负数， 绝对值），不会超出范围
+    fn read_int_parts(&mut self) -> Result<(bool, u128), ReadBonErr> {
+        let t = self.get_type_chunk()?;
+        let (neg, n) = match t {
+            15 => {
+                self.skip_bytes(1);
+                return Ok((true, 1));
+            }
+            16..=35 => {
+                self.skip_bytes(1);
+                return Ok((false, (t - 16) as u128));
+            }
+            9..=14 => (true, INT_WIDTH[(t - 9) as usize]),
+            36..=41 => (false, INT_WIDTH[(t - 36) as usize]),
+            _ => {
+                return Err(ReadBonErr::type_no_match(
+                    "integer".to_string(),
+                    t,
+                    self.head,
+                ))
+            }
+        };
+        self.probe_border(1 + n)?;
+        let payload = &self.bytes[1..=n];
+        let mut m: u128 = 0;
+        match self.endian {
+            Endian::Little => payload.iter().rev().for_each(|b| m = (m << 8) | *b as u128),
+            Endian::Big => payload.iter().for_each(|b| m = (m << 8) | *b as u128),
+        }
+        self.skip_bytes(1 + n);
+        Ok((neg, m))
+    }
+
     /// 读一个f32类型，如果二进制当前的值不是f32类型，返回Err
     #[inline]
     pub fn read_f32(&mut self) -> Result<f32, ReadBonErr> {
+        self.skip_debug_padding();
         self.probe_border(1)?;
         let t = self.bytes.get_u8();
         self.head += 1;
         match t {
             3 => Ok(0.0),
             4 => Ok(1.0),
-            5..7 => {
+            5 => {
+                self.probe_border(2)?;
+                self.head += 2;
+                Ok(f16_to_f32(self.payload_u16()))
+            }
+            6 => {
                 self.probe_border(4)?;
                 self.head += 4;
-                Ok(self.bytes.get_f32_le())
+                Ok(self.payload_f32())
             }
             _ => {
                 self.head -= 1;
@@ -372,23 +819,54 @@ impl<'a> ReadBuffer<'a> {
         }
     }
 
-    /// 读一个f64类型，如果二进制当前的值不是f64类型，返回Err
+    /// 读一个16位浮点数，如果二进制当前的值不是16位浮点数（或0.0、1.0），返回Err
+    #[cfg(feature = "half")]
+    pub fn read_f16(&mut self) -> Result<half::f16, ReadBonErr> {
+        self.skip_debug_padding();
+        self.probe_border(1)?;
+        match self.bytes[0] {
+            3 => {
+                self.bytes.advance(1);
+                self.head += 1;
+                Ok(half::f16::ZERO)
+            }
+            4 => {
+                self.bytes.advance(1);
+                self.head += 1;
+                Ok(half::f16::ONE)
+            }
+            5 => {
+                self.probe_border(3)?;
+                self.bytes.advance(1);
+                self.head += 3;
+                Ok(half::f16::from_bits(self.payload_u16()))
+            }
+            t => Err(ReadBonErr::type_no_match("f16".to_string(), t, self.head)),
+        }
+    }
+
     pub fn read_f64(&mut self) -> Result<f64, ReadBonErr> {
+        self.skip_debug_padding();
         self.probe_border(1)?;
         let t = self.bytes.get_u8();
         self.head += 1;
         match t {
             3 => Ok(0.0),
             4 => Ok(1.0),
+            5 => {
+                self.probe_border(2)?;
+                self.head += 2;
+                Ok(f16_to_f32(self.payload_u16()) as f64)
+            }
             6 => {
                 self.probe_border(4)?;
                 self.head += 4;
-                Ok(self.bytes.get_f32_le() as f64)
+                Ok(self.payload_f32() as f64)
             }
             7 => {
                 self.probe_border(8)?;
                 self.head += 8;
-                Ok(self.bytes.get_f64_le())
+                Ok(self.payload_f64())
             }
             _ => {
                 self.head -= 1;
@@ -408,18 +886,21 @@ impl<'a> ReadBuffer<'a> {
     #[inline]
     pub fn read_lengthen(&mut self) -> Result<u32, ReadBonErr> {
         self.probe_border(1)?;
-        // let t = self.bytes.get_u8();
-        let t = self.get_type_chunk().unwrap();
+        // 长度不是值，不能跳过填充（长度的首字节可能与容器头相同）
+        let t = self.bytes[0];
         if t < 0x80 {
             self.head += 1;
             self.bytes.advance(1);
             Ok(t as u32)
         } else if t < 0xC0 {
+            // 多字节的动态长度为大端序，以保证标志位在第一个字节
+            self.probe_border(2)?;
             self.head += 2;
-            Ok(self.bytes.get_u16_ne() as u32 - 0x8000)
+            Ok(self.bytes.get_u16() as u32 - 0x8000)
         } else if t < 0xE0 {
+            self.probe_border(4)?;
             self.head += 4;
-            Ok(self.bytes.get_u32_ne() as u32 - 0xC0000000)
+            Ok(self.bytes.get_u32() - 0xC0000000)
         } else {
             return Err(ReadBonErr::type_no_match(
                 "lengthen".to_string(),
@@ -432,7 +913,8 @@ impl<'a> ReadBuffer<'a> {
     // 读一个二进制类型，如果二进制当前的值不是二进制类型，返回Err
     #[inline]
     pub fn read_bin(&mut self) -> Result<Vec<u8>, ReadBonErr> {
-        self.probe_border(1)?;
+        self.skip_debug_padding();
+        self.probe_value()?;
         let t = self.bytes.get_u8();
         self.head += 1;
         
@@ -440,6 +922,50 @@ impl<'a> ReadBuffer<'a> {
     }
 
     fn read_bin_inner(&mut self, t: u8)-> Result<Vec<u8>, ReadBonErr>{
+        let bin = self.read_bin_slice(t)?;
+        self.charge_alloc(bin.len())?;
+        Ok(bin.to_vec())
+    }
+
+    /// 读一个二进制数据，直接借用底层的二进制，不分配内存，如果二进制当前的值不是二进制数据，返回Err
+    pub fn read_bin_ref(&mut self) -> Result<&'a [u8], ReadBonErr> {
+        let t = self.get_type_chunk()?;
+        if t < 111 || t >= 180 {
+            return Err(ReadBonErr::type_no_match("bin".to_string(), t, self.head));
+        }
+        self.probe_value()?;
+        self.bytes.advance(1);
+        self.head += 1;
+        self.read_bin_slice(t)
+    }
+
+    /// 读一组由WriteBuffer::write_coords写入的坐标，坐标数量与二进制数据的长度不符时，返回Err
+    pub fn read_coords(&mut self) -> Result<Vec<[f64; 2]>, ReadBonErr> {
+        let count = self.read_usize()?;
+        let head = self.head;
+        let bin = self.read_bin_ref()?;
+        if Some(bin.len()) != count.checked_mul(16) {
+            return Err(ReadBonErr::other(format!(
+                "coords length mismatch, count: {}, len: {}, head: {}",
+                count,
+                bin.len(),
+                head
+            )));
+        }
+        self.charge_alloc(bin.len())?;
+        Ok(bin
+            .chunks_exact(16)
+            .map(|c| {
+                [
+                    f64::from_le_bytes(c[..8].try_into().unwrap()),
+                    f64::from_le_bytes(c[8..].try_into().unwrap()),
+                ]
+            })
+            .collect())
+    }
+
+    // 读二进制数据的长度（类型t已读出），返回借用的数据
+    fn read_bin_slice(&mut self, t: u8) -> Result<&'a [u8], ReadBonErr> {
         let len: usize;
         if t >= 111 && t <= 175 {
             len = (t as usize) - 111;
@@ -459,8 +985,7 @@ impl<'a> ReadBuffer<'a> {
                     self.head += len + 4;
                 }
                 179 => {
-                    len = self.bytes.get_u16_le() as usize
-                        + (self.bytes.get_u32_le() * 0x10000) as usize;
+                    len = self.read_u48_len()?;
                     self.head += len + 6;
                 }
                 _ => {
@@ -473,19 +998,108 @@ impl<'a> ReadBuffer<'a> {
             };
         }
 
-        let dst = self.bytes.copy_to_bytes(len).to_vec();
-        Ok(dst)
+        self.probe_border(len)?;
+        let bin = &self.bytes[..len];
+        self.bytes.advance(len);
+        Ok(bin)
+    }
+
+    /// 读一个二进制数据，并以常量时间与expected比较是否相等，用于比较MAC、令牌等秘密数据，如果二进制当前的值不是二进制数据，返回Err
+    ///
+    /// 比较时间只与长度有关，与内容无关；长度不同时直接返回false（长度不视为秘密）
+    #[cfg(feature = "subtle")]
+    pub fn read_bin_ct_eq(&mut self, expected: &[u8]) -> Result<bool, ReadBonErr> {
+        use subtle::ConstantTimeEq;
+
+        self.probe_border(1)?;
+        let t = self.bytes[0];
+        let header = match t {
+            111..=175 => 1,
+            176 => 2,
+            177 => 3,
+            178 => 5,
+            179 => 7,
+            _ => return Err(ReadBonErr::type_no_match("bin".to_string(), t, self.head)),
+        };
+        let len = value_len(self.bytes)?;
+        self.probe_border(len)?;
+        let eq = self.bytes[header..len].ct_eq(expected);
+        self.skip_bytes(len);
+        Ok(bool::from(eq))
     }
 
     /// 读一个utf8编码的字符串类型，如果二进制当前的值不是utf8编码的字符串类型类型，返回Err
+    ///
+    /// 不合法的UTF-8序列会被替换为U+FFFD，需要检查数据是否损坏时，使用read_utf8_strict
     #[inline]
     pub fn read_utf8(&mut self) -> Result<String, ReadBonErr> {
-        self.probe_border(1)?;
+        self.skip_debug_padding();
+        self.probe_value()?;
         let t = self.bytes.get_u8();
         self.head += 1;
         self.read_utf8_inner(t)
     }
 
+    /// 读一个utf8编码的字符串类型，不是合法的UTF-8时返回Err，且不移动读指针
+    pub fn read_utf8_strict(&mut self) -> Result<String, ReadBonErr> {
+        let (bytes, head) = (self.bytes, self.head);
+        let s = self.read_str()?;
+        if let Err(e) = self.charge_alloc(s.len()) {
+            self.bytes = bytes;
+            self.head = head;
+            return Err(e);
+        }
+        Ok(s.to_string())
+    }
+
+    /// 读一个字符串，返回其原始字节，不做UTF-8校验和转换
+    pub fn read_str_bytes(&mut self) -> Result<&'a [u8], ReadBonErr> {
+        let t = self.get_type_chunk()?;
+        let len_bytes = match t {
+            42..=106 => 0,
+            107..=110 => [1, 2, 4, 6][(t - 107) as usize],
+            _ => {
+                return Err(ReadBonErr::type_no_match(
+                    "string".to_string(),
+                    t,
+                    self.head,
+                ))
+            }
+        };
+        let (_, payload) = self.read_raw()?;
+        Ok(&payload[len_bytes..])
+    }
+
+    /// 读一个字符串，直接借用底层的二进制，不分配内存；不是合法的UTF-8时返回Err，且不移动读指针
+    pub fn read_str(&mut self) -> Result<&'a str, ReadBonErr> {
+        let (bytes, head) = (self.bytes, self.head);
+        let s = self.read_str_bytes()?;
+        std::str::from_utf8(s).map_err(|e| {
+            self.bytes = bytes;
+            self.head = head;
+            ReadBonErr::other(format!("invalid utf8 string: {}", e))
+        })
+    }
+
+    /// 读一个字符串，返回其UTF-16编码
+    pub fn read_utf16(&mut self) -> Result<Vec<u16>, ReadBonErr> {
+        Ok(self.read_utf8()?.encode_utf16().collect())
+    }
+
+    // 读6字节的长度（低16位在前），在u64中计算，避免u32乘法溢出
+    fn read_u48_len(&mut self) -> Result<usize, ReadBonErr> {
+        self.probe_border(6)?;
+        let low = self.bytes.get_u16_le() as u64;
+        let high = self.bytes.get_u32_le() as u64;
+        let len = high
+            .checked_mul(0x10000)
+            .and_then(|h| h.checked_add(low))
+            .ok_or_else(|| ReadBonErr::other(format!("length overflow, head = {}", self.head)))?;
+        usize::try_from(len).map_err(|_| {
+            ReadBonErr::other(format!("length {} too large, head = {}", len, self.head))
+        })
+    }
+
     fn read_utf8_inner(&mut self, t: u8) -> Result<String, ReadBonErr> {
         let len: usize;
         if t >= 42 && t <= 106 {
@@ -506,8 +1120,7 @@ impl<'a> ReadBuffer<'a> {
                     self.head += len + 4;
                 }
                 110 => {
-                    len = self.bytes.get_u16_le() as usize
-                        + (self.bytes.get_u32_le() * 0x10000) as usize;
+                    len = self.read_u48_len()?;
                     self.head += len + 6;
                 }
                 _ => {
@@ -520,16 +1133,50 @@ impl<'a> ReadBuffer<'a> {
             }
         }
 
+        self.charge_alloc(len)?;
         let dst = self.bytes.copy_to_bytes(len);
         Ok(String::from_utf8_lossy(&*dst).to_string())
     }
 
+    /// 读一个Vec（与Vec<T>的Decode一致），使用指定的分配器分配内存，需开启allocator_api特性（仅nightly）
+    #[cfg(feature = "allocator_api")]
+    pub fn read_vec_in<T: Decode, A: Allocator>(
+        &mut self,
+        alloc: A,
+    ) -> Result<Vec<T, A>, ReadBonErr> {
+        let count = usize::decode(self)?;
+        let mut vec = Vec::new_in(alloc);
+        for _ in 0..count {
+            self.charge_alloc(std::mem::size_of::<T>())?;
+            vec.push(T::decode(self)?);
+        }
+        Ok(vec)
+    }
+
+    /// 读一个序列（格式与Vec<T>的Encode一致：元素数量，之后依次为各元素），直接收集到任意实现了FromIterator的集合中，
+    /// 如Vec、HashSet、BTreeSet、VecDeque
+    pub fn read_collection<C: FromIterator<T>, T: Decode>(&mut self) -> Result<C, ReadBonErr> {
+        let count = usize::decode(self)?;
+        (0..count)
+            .map(|_| {
+                self.charge_alloc(std::mem::size_of::<T>())?;
+                T::decode(self)
+            })
+            .collect()
+    }
+
     /// 读一个容器类型，如果二进制当前的值不是容器类型，返回Err
     pub fn read_container<T, F>(&mut self, read_next: F) -> Result<T, ReadBonErr>
     where
         F: FnOnce(&mut ReadBuffer, u32, u64) -> Result<T, ReadBonErr>,
     {
+        self.skip_debug_padding();
         self.probe_border(1)?;
+        if let 180..=248 = self.bytes[0] {
+            // 先探测容器头和类型的边界，数据不完整时返回Overflow
+            let (header_len, _) = container_header(self.bytes)?;
+            self.probe_border(header_len + 4)?;
+        }
         let t = self.bytes.get_u8();
         self.head += 1;
         let len: u64;
@@ -551,8 +1198,7 @@ impl<'a> ReadBuffer<'a> {
                     self.head += 8;
                 }
                 248 => {
-                    len =
-                        self.bytes.get_u16_le() as u64 + (self.bytes.get_u32_le() * 0x10000) as u64;
+                    len = self.read_u48_len()? as u64;
                     self.head += 10;
                 }
                 _ => {
@@ -568,73 +1214,478 @@ impl<'a> ReadBuffer<'a> {
         read_next(self, tt, len)
     }
 
-    /// 下一个值是否为None
-    #[inline]
-    pub fn is_nil(&mut self) -> Result<bool, ReadBonErr> {
-        self.probe_border(1)?;
-        // let first = self.bytes.get_u8();
-        let first = self.get_type_chunk().unwrap();
-        if first == 0 {
-            self.head += 1;
-            self.bytes.advance(1);
-            Ok(true)
-        } else {
-            Ok(false)
+    /// 查看接下来的容器，返回（容器类型， 整个容器占用的字节数），不移动读指针
+    pub fn peek_container(&self) -> Result<(u32, usize), ReadBonErr> {
+        let (header_len, body_len) = container_header(self.bytes)?;
+        let type_id = read_uint_le(self.bytes, header_len, 4)? as u32;
+        Ok((type_id, header_len + body_len))
+    }
+
+    /// 读一个通用数组（容器类型2），返回依次读出每个元素的迭代器，迭代器在读完元素数量个元素后停止
+    ///
+    /// 迭代器drop时（无论是否读完），读指针移动到容器之后；不是通用数组时返回Err，且不移动读指针
+    pub fn array_iter(&mut self) -> Result<BonArrayIter<'_, 'a>, ReadBonErr> {
+        let head = self.head;
+        let c = self.read_container_scoped()?;
+        if c.type_id != 2 {
+            return Err(ReadBonErr::other(format!(
+                "container is not an array, type: {}, head: {}",
+                c.type_id, head
+            )));
         }
-    }
-
-    /// 读下一个数据，已经读到最后，返回Err。否则，返回下一个数据
-    pub fn read(&mut self) -> Result<EnumValue, ReadBonErr> {
-        self.probe_border(1)?;
-        let first = self.bytes.get_u8();
-        self.head += 1;
-        match first {
-            0 => Ok(EnumValue::Void),
-            1 => Ok(EnumValue::Bool(false)),
-            2 => Ok(EnumValue::Bool(true)),
-            3 => Ok(EnumValue::F32(0.0)),
-            4 => Ok(EnumValue::F32(1.0)),
-            5 => {
-                panic!("16 bit floating-point number temporarily unsupported");
-            }
-            6 => {
-                self.head += 4;
-                Ok(EnumValue::F32(self.bytes.get_f32_le()))
+        Ok(BonArrayIter {
+            remaining: c.count.unwrap_or(0),
+            c: Some(c),
+        })
+    }
+
+    /// 读一个通用map（容器类型3），返回依次读出每个键值对的迭代器，不需要在内存中构建整个map
+    ///
+    /// 迭代器drop时（无论是否读完），读指针移动到容器之后；不是通用map时返回Err，且不移动读指针
+    pub fn map_iter(&mut self) -> Result<BonMapIter<'_, 'a>, ReadBonErr> {
+        let head = self.head;
+        let c = self.read_container_scoped()?;
+        if c.type_id != 3 {
+            return Err(ReadBonErr::other(format!(
+                "container is not a map, type: {}, head: {}",
+                c.type_id, head
+            )));
+        }
+        Ok(BonMapIter {
+            remaining: c.count.unwrap_or(0),
+            c: Some(c),
+        })
+    }
+
+    /// 读一个通用map（容器类型3），返回每个键值对各自的ReadBuffer，不解析键值的内容
+    ///
+    /// 查找单个键时，可以只比较键的ReadBuffer，只解码匹配的值
+    pub fn map_entries(&mut self) -> Result<Vec<(ReadBuffer<'a>, ReadBuffer<'a>)>, ReadBonErr> {
+        let head = self.head;
+        let mut c = self.read_container_scoped()?;
+        if c.type_id() != 3 {
+            return Err(ReadBonErr::other(format!(
+                "container is not a map, type: {}, head: {}",
+                c.type_id(),
+                head
+            )));
+        }
+        let mut entries = Vec::with_capacity(c.count().unwrap_or(0) as usize);
+        while let Some(key) = c.next_element() {
+            let value = match c.next_element() {
+                Some(value) => value?,
+                None => {
+                    return Err(ReadBonErr::other(format!(
+                        "map value missing, head: {}",
+                        head
+                    )))
+                }
+            };
+            entries.push((key?, value));
+        }
+        c.finish();
+        Ok(entries)
+    }
+
+    /// 读一个容器的头部，返回容器读取器，容器读取器只能读到容器体内的数据
+    ///
+    /// 调用容器读取器的finish方法后，当前ReadBuffer会跳过整个容器（无论容器内的元素是否已全部读取）
+    pub fn read_container_scoped(&mut self) -> Result<ContainerReader<'_, 'a>, ReadBonErr> {
+        self.skip_debug_padding();
+        let (header_len, body_len) = container_header(self.bytes)?;
+        let size = header_len + body_len;
+        if size > self.bytes.len() {
+            return Err(ReadBonErr::overflow(size, self.bytes.len()));
+        }
+        if body_len < 4 {
+            return Err(ReadBonErr::other(format!(
+                "container body too short, len = {}",
+                body_len
+            )));
+        }
+        let mut body = self.child(&self.bytes[header_len..size], self.head + header_len);
+        let type_id = body.bytes.get_u32_le();
+        body.head += 4;
+        let count = match type_id {
+            1..=3 => Some(body.read_lengthen()?),
+            _ => None,
+        };
+        if self.strict {
+            check_container_padding(type_id, count, body.bytes)?;
+        }
+        Ok(ContainerReader {
+            parent: self,
+            body,
+            type_id,
+            count,
+            size,
+        })
+    }
+
+    /// 检查接下来的数据是否符合schema描述的结构，只检查类型，不反序列化值
+    ///
+    /// 符合时，读指针移动到被检查数据之后；不符合时返回Ok(false)，读指针位置不确定
+    pub fn matches_schema(&mut self, schema: &Schema) -> Result<bool, ReadBonErr> {
+        match schema {
+            Schema::Tuple(items) => {
+                for item in items {
+                    if !self.matches_schema(item)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
             }
-            7 => {
-                self.head += 8;
-                Ok(EnumValue::F64(self.bytes.get_f64_le()))
+            Schema::Option(item) => match self.is_nil()? {
+                true => Ok(true),
+                false => self.matches_schema(item),
+            },
+            Schema::Vec(item) => {
+                if !self.match_type(&Schema::Int)? {
+                    return Ok(false);
+                }
+                for _ in 0..self.read_usize()? {
+                    if !self.matches_schema(item)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
             }
-            8 => {
-                panic!("128 bit floating-point number temporarily unsupported");
+            Schema::Map(k, v) => {
+                if !self.match_type(&Schema::Int)? {
+                    return Ok(false);
+                }
+                for _ in 0..self.read_usize()? {
+                    if !self.matches_schema(k)? || !self.matches_schema(v)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
             }
-            15 => Ok(EnumValue::I8(-1)),
-            16..36 => Ok(EnumValue::U8(first - 16)),
-            36 => {
-                self.head += 1;
-                Ok(EnumValue::U8(self.bytes.get_u8()))
+            _ => {
+                if !self.match_type(schema)? {
+                    return Ok(false);
+                }
+                let len = value_len(self.bytes)?;
+                self.probe_border(len)?;
+                self.skip_bytes(len);
+                Ok(true)
             }
-            37 => {
-                self.head += 2;
-                Ok(EnumValue::U16(self.bytes.get_u16_le()))
+        }
+    }
+
+    // 检查下一个值的类型是否符合基础类型的schema（不移动读指针）
+    fn match_type(&mut self, schema: &Schema) -> Result<bool, ReadBonErr> {
+        let t = self.get_type_chunk()?;
+        let r = match schema {
+            Schema::Any => true,
+            Schema::Nil => t == 0,
+            Schema::Bool => t == 1 || t == 2,
+            Schema::Float => t >= 3 && t < 9,
+            Schema::Int => t >= 9 && t < 42,
+            Schema::Str => t >= 42 && t < 111,
+            Schema::Bin => t >= 111 && t < 180,
+            Schema::Container(None) => t >= 180 && t < 249,
+            Schema::Container(Some(type_id)) => {
+                t >= 180 && t < 249 && {
+                    let (header_len, _) = container_header(self.bytes)?;
+                    read_uint_le(self.bytes, header_len, 4)? == *type_id as usize
+                }
             }
-            38 => {
-                self.head += 4;
-                Ok(EnumValue::U32(self.bytes.get_u32_le()))
+            _ => false,
+        };
+        Ok(r)
+    }
+
+    /// 跳过下一个值（任意类型，包括字符串、二进制、容器），不解析其内容，用于忽略不认识的字段
+    ///
+    /// 容器头部记录了容器体的总大小，因此跳过容器不需要遍历其中的元素
+    pub fn skip_value(&mut self) -> Result<(), ReadBonErr> {
+        self.skip_debug_padding();
+        let len = value_len(self.bytes)?;
+        self.probe_border(len)?;
+        self.skip_bytes(len);
+        Ok(())
+    }
+
+    // 开启with_debug_padding时，跳过WriteBuffer::set_debug_align写入的填充容器（类型为0，容器体为空或至少两个Void）
+    fn skip_debug_padding(&mut self) {
+        if !self.debug_padding {
+            return;
+        }
+        while let Some(180..=248) = self.bytes.first() {
+            let size = match container_header(self.bytes) {
+                Ok((header_len, body_len)) => {
+                    match self.bytes.get(header_len..header_len + body_len) {
+                        Some(body)
+                            if body.len() >= 4
+                                && body.len() != 5
+                                && body.iter().all(|b| *b == 0) =>
+                        {
+                            header_len + body_len
+                        }
+                        _ => return,
+                    }
+                }
+                Err(_) => return,
+            };
+            self.skip_bytes(size);
+        }
+    }
+
+    // 跳过len个字节，调用者需保证不越界
+    #[inline]
+    fn skip_bytes(&mut self, len: usize) {
+        self.bytes.advance(len);
+        self.head += len;
+    }
+
+    /// 读一个小map，如果二进制当前的值不是小map，返回Err
+    pub fn read_small_map<K: Decode + Eq + Hash, V: Decode>(
+        &mut self,
+    ) -> Result<HashMap<K, V>, ReadBonErr> {
+        let t = self.get_type_chunk()?;
+        if t != 251 {
+            return Err(ReadBonErr::type_no_match(
+                "small map".to_string(),
+                t,
+                self.head,
+            ));
+        }
+        self.probe_border(2)?;
+        let len = self.bytes[1] as usize;
+        self.probe_border(2 + len)?;
+        let mut body = self.child(&self.bytes[2..2 + len], self.head + 2);
+        let mut map = HashMap::new();
+        while body.len() > 0 {
+            body.charge_alloc(std::mem::size_of::<(K, V)>())?;
+            let dup = map
+                .insert(K::decode(&mut body)?, V::decode(&mut body)?)
+                .is_some();
+            if dup && self.strict {
+                return Err(ReadBonErr::other("duplicate map key".to_string()));
             }
-            39 => {
-                self.head += 6;
-                Ok(EnumValue::F64(
-                    (self.bytes.get_u16_le() as u64 + ((self.bytes.get_u32_le() as u64) << 16)) as f64,
+        }
+        self.skip_bytes(2 + len);
+        Ok(map)
+    }
+
+    /// 读一个由WriteBuffer::write_sized写入的值，解包类型为0的容器，返回其内部的值
+    pub fn read_sized<T: Decode>(&mut self) -> Result<T, ReadBonErr> {
+        let mut c = self.read_container_scoped()?;
+        if c.type_id != 0 {
+            return Err(ReadBonErr::other(format!(
+                "sized container type id must be 0, but it's {}",
+                c.type_id
+            )));
+        }
+        let v = T::decode(&mut c.body)?;
+        c.finish();
+        Ok(v)
+    }
+
+    /// 读一个由WriteBuffer::write_tagged_int写入的整数，保留其符号信息
+    pub fn read_tagged_int(&mut self) -> Result<TaggedInt, ReadBonErr> {
+        let mut c = self.read_container_scoped()?;
+        let v = match c.type_id {
+            4 => TaggedInt::Signed(c.body.read_i128()?),
+            5 => TaggedInt::Unsigned(c.body.read_u128()?),
+            t => {
+                return Err(ReadBonErr::other(format!(
+                    "tagged int container type id must be 4 or 5, but it's {}",
+                    t
+                )))
+            }
+        };
+        c.finish();
+        Ok(v)
+    }
+
+    /// 读一个由WriteBuffer::write_decimal写入的定点小数，返回（尾数，小数位数）
+    pub fn read_decimal(&mut self) -> Result<(i128, u8), ReadBonErr> {
+        let mut c = self.read_container_scoped()?;
+        if c.type_id != 1 || c.count != Some(2) {
+            return Err(ReadBonErr::other(format!(
+                "decimal must be an object with 2 fields, type id = {}, count = {:?}",
+                c.type_id, c.count
+            )));
+        }
+        let v = (c.body.read_i128()?, c.body.read_u8()?);
+        c.finish();
+        Ok(v)
+    }
+
+    /// 读一个由WriteBuffer::write_sparse_array写入的稀疏数组，未写入的元素为T::default()
+    ///
+    /// 序号不递增或超出数组长度时返回Err；数组长度来自数据，无法分配时返回Err，不可信的数据可以配合with_total_alloc_budget限制内存
+    pub fn read_sparse_array<T: Decode + Default>(&mut self) -> Result<Vec<T>, ReadBonErr> {
+        let mut c = self.read_container_scoped()?;
+        if c.type_id != 6 {
+            return Err(ReadBonErr::other(format!(
+                "sparse array container type id must be 6, but it's {}",
+                c.type_id
+            )));
+        }
+        let len = c.body.read_usize()?;
+        c.body
+            .charge_alloc(len.saturating_mul(std::mem::size_of::<T>()))?;
+        let mut arr = Vec::new();
+        arr.try_reserve_exact(len)
+            .map_err(|e| ReadBonErr::other(format!("invalid sparse array len {}: {}", len, e)))?;
+        while !c.body.bytes.is_empty() {
+            let i = c.body.read_usize()?;
+            if i < arr.len() || i >= len {
+                return Err(ReadBonErr::other(format!(
+                    "invalid sparse array index {}, len = {}",
+                    i, len
+                )));
+            }
+            arr.resize_with(i, T::default);
+            arr.push(T::decode(&mut c.body)?);
+        }
+        arr.resize_with(len, T::default);
+        c.finish();
+        Ok(arr)
+    }
+
+    /// 读一个扩展类型的值，容器类型必须是registry中为T注册的类型
+    pub fn read_extension<T: 'static>(
+        &mut self,
+        registry: &CodecRegistry,
+    ) -> Result<T, ReadBonErr> {
+        let (type_id, codec) = match registry.codec::<T>() {
+            Some(r) => r,
+            None => {
+                return Err(ReadBonErr::other(format!(
+                    "extension not registered: {}",
+                    std::any::type_name::<T>()
+                )))
+            }
+        };
+        let mut c = self.read_container_scoped()?;
+        if c.type_id != type_id {
+            return Err(ReadBonErr::other(format!(
+                "extension type id mismatch, expect {}, but it's {}",
+                type_id, c.type_id
+            )));
+        }
+        let v = (codec.decode)(&mut c.body)?;
+        c.finish();
+        Ok(v)
+    }
+
+    /// 读一个多态的值，根据容器类型在registry中找到对应的解码方法，解码为Box<dyn Any>，可用downcast得到具体类型
+    ///
+    /// 容器类型未注册时返回Err，且不移动读指针
+    pub fn read_polymorphic(
+        &mut self,
+        registry: &TypeRegistry,
+    ) -> Result<Box<dyn Any>, ReadBonErr> {
+        let (type_id, _) = self.peek_container()?;
+        match registry.decoders.get(&type_id) {
+            Some(decode) => decode(self),
+            None => Err(ReadBonErr::other(format!(
+                "polymorphic type not registered: {}, head: {}",
+                type_id, self.head
+            ))),
+        }
+    }
+
+    /// 读一个由WriteBuffer::write_variant写入的带标签的值，返回（标签，只包含值内容的ReadBuffer）
+    pub fn read_variant(&mut self) -> Result<(u8, ReadBuffer<'a>), ReadBonErr> {
+        let t = self.get_type_chunk()?;
+        let header_len = match t {
+            111..=175 => 1,
+            176..=179 => 1 + [1, 2, 4, 6][(t - 176) as usize],
+            _ => {
+                return Err(ReadBonErr::type_no_match(
+                    "variant".to_string(),
+                    t,
+                    self.head,
                 ))
             }
+        };
+        let start = self.head;
+        let (_, payload) = self.read_raw()?;
+        let data = &payload[header_len - 1..];
+        if data.is_empty() {
+            return Err(ReadBonErr::other("variant without tag".to_string()));
+        }
+        Ok((data[0], self.child(&data[1..], start + header_len + 1)))
+    }
+
+    /// 下一个值是否为None
+    #[inline]
+    pub fn is_nil(&mut self) -> Result<bool, ReadBonErr> {
+        self.probe_border(1)?;
+        let first = self.get_type_chunk()?;
+        if first == 0 {
+            self.head += 1;
+            self.bytes.advance(1);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// 读下一个数据，已经读到最后，返回Err。否则，返回下一个数据
+    ///
+    /// 浮点数0.0和1.0（类型3和4）不记录精度，总是读为EnumValue::F32，需要f64时由调用者转换
+    ///
+    /// 容器会递归读出其中的元素：通用数组读为Arr，通用map读为Map，其它容器读为Struct（字段名来自结构体字段名表）
+    pub fn read(&mut self) -> Result<EnumValue, ReadBonErr> {
+        self.skip_debug_padding();
+        self.probe_value()?;
+        if let 180..=248 = self.bytes[0] {
+            return self.read_container_value();
+        }
+        let first = self.bytes.get_u8();
+        self.head += 1;
+        match first {
+            0 => Ok(EnumValue::Void),
+            1 => Ok(EnumValue::Bool(false)),
+            2 => Ok(EnumValue::Bool(true)),
+            3 => Ok(EnumValue::F32(0.0)),
+            4 => Ok(EnumValue::F32(1.0)),
+            5 => {
+                self.head += 2;
+                Ok(EnumValue::F32(f16_to_f32(self.payload_u16())))
+            }
+            6 => {
+                self.head += 4;
+                Ok(EnumValue::F32(self.payload_f32()))
+            }
+            7 => {
+                self.head += 8;
+                Ok(EnumValue::F64(self.payload_f64()))
+            }
+            8 => Err(ReadBonErr::other(
+                "128 bit floating-point number temporarily unsupported".to_string(),
+            )),
+            15 => Ok(EnumValue::I8(-1)),
+            16..=35 => Ok(EnumValue::U8(first - 16)),
+            36 => {
+                self.head += 1;
+                Ok(EnumValue::U8(self.bytes.get_u8()))
+            }
+            37 => {
+                self.head += 2;
+                Ok(EnumValue::U16(self.payload_u16()))
+            }
+            38 => {
+                self.head += 4;
+                Ok(EnumValue::U32(self.payload_u32()))
+            }
+            39 => {
+                self.head += 6;
+                Ok(EnumValue::F64(self.payload_u48() as f64))
+            }
             40 => {
                 self.head += 8;
-                Ok(EnumValue::U64(self.bytes.get_u64_le() as u64))
+                Ok(EnumValue::U64(self.payload_u64() as u64))
             }
             41 => {
                 self.head += 16;
-                Ok(EnumValue::U128(self.bytes.get_u128_le() as u128))
+                Ok(EnumValue::U128(self.payload_u128() as u128))
             }
             9 => {
                 self.head += 1;
@@ -642,48 +1693,237 @@ impl<'a> ReadBuffer<'a> {
             }
             10 => {
                 self.head += 2;
-                Ok(EnumValue::I32(-(self.bytes.get_u16_le() as i32)))
+                Ok(EnumValue::I32(-(self.payload_u16() as i32)))
             }
             11 => {
                 self.head += 4;
-                Ok(EnumValue::I64(-(self.bytes.get_u32_le() as i64)))
+                Ok(EnumValue::I64(-(self.payload_u32() as i64)))
             }
             12 => {
                 self.head += 6;
-                Ok(EnumValue::F64(
-                    (-(self.bytes.get_u16_le() as i64) - ((self.bytes.get_u32_le() as i64) << 16)) as f64,
-                ))
+                Ok(EnumValue::F64(-(self.payload_u48() as f64)))
             }
             13 => {
                 self.head += 8;
-                Ok(EnumValue::I64(-(self.bytes.get_u64_le() as i64)))
+                Ok(EnumValue::I64(-(self.payload_u64() as i64)))
             }
             14 => {
                 self.head += 16;
-                Ok(EnumValue::I128(-(self.bytes.get_u128_le() as i128)))
+                Ok(EnumValue::I128(-(self.payload_u128() as i128)))
             }
-            42..111 => {
+            42..=110 => {
                 self.read_utf8_inner(first).map(|op|EnumValue::Str(op))
             }
-            111..180 => {
+            111..=179 => {
                 self.read_bin_inner(first).map(|op|EnumValue::Bin(op))
             }
-            245..249 => {
-                return Err(ReadBonErr::IsContainer(first));
+            253..=255 => Err(ReadBonErr::ReservedType(first)),
+            _ => {
+                return Err(ReadBonErr::type_no_match(
+                    "value".to_string(),
+                    first,
+                    self.head - 1,
+                ));
+            }
+        }
+    }
+
+    /// 读下一个数据，与read相同，同时返回其内容哈希（与content_hash相同）
+    ///
+    /// 读取的同时写出每个部分的最短编码（map的键值对按键排序）并计算哈希，只遍历一次二进制；
+    /// 与read不同，也接受content_hash支持的小map（读为Map）和BigInt（读为U128、I128，超出128位时返回Err）
+    pub fn read_and_hash(&mut self) -> Result<(EnumValue, u64), ReadBonErr> {
+        let mut w = WriteBuffer::new();
+        w.set_streaming_hash(true);
+        let v = self.read_canonical(&mut w)?;
+        Ok((v, w.finish_hash()))
+    }
+
+    // 读下一个数据，同时向w写出其最短编码（与canonical_value相同）
+    fn read_canonical(&mut self, w: &mut WriteBuffer) -> Result<EnumValue, ReadBonErr> {
+        self.skip_debug_padding();
+        self.probe_value()?;
+        match self.bytes[0] {
+            9..=41 => {
+                w.write_canonical_int(self.child(self.bytes, self.head).read_any_int()?);
+                self.read()
+            }
+            180..=248 => {
+                let mut c = self.read_container_scoped()?;
+                let mut elems = Vec::new();
+                let mut values = Vec::new();
+                while let Some(elem) = c.next_element() {
+                    let mut e = WriteBuffer::new();
+                    values.push(elem?.read_canonical(&mut e)?);
+                    elems.push(e.unwrap());
+                }
+                let (type_id, count) = (c.type_id, c.count);
+                c.finish();
+                write_canonical_container(w, type_id, count, elems)?;
+                self.container_value(type_id, values)
+            }
+            t @ 249..=251 => {
+                let head = self.head;
+                let (_, payload) = self.read_raw()?;
+                w.mark_value();
+                w.write_raw(&[t]);
+                w.write_raw(payload);
+                if t == 251 {
+                    let mut body = self.child(&payload[1..], head + 2);
+                    let mut elems = Vec::new();
+                    while !body.bytes.is_empty() {
+                        elems.push(body.read()?);
+                    }
+                    return self.container_value(3, elems);
+                }
+                let n = BigInt::from_bytes_le(Sign::Plus, payload);
+                let v = match t {
+                    249 => u128::try_from(n).map(EnumValue::U128).ok(),
+                    _ => i128::try_from(-n).map(EnumValue::I128).ok(),
+                };
+                v.ok_or_else(|| {
+                    ReadBonErr::other(format!("bigint out of 128 bit range, head = {}", head))
+                })
+            }
+            _ => {
+                let v = self.read()?;
+                w.write_value(&v);
+                Ok(v)
+            }
+        }
+    }
+
+    /// 读下一个数据，与read相同，同时返回该数据的类型值，便于统计编码的使用情况（如小整数使用的是15~35的常用数字类型）
+    pub fn read_with_code(&mut self) -> Result<(u8, EnumValue), ReadBonErr> {
+        self.skip_debug_padding();
+        self.probe_border(1)?;
+        let t = self.bytes[0];
+        Ok((t, self.read()?))
+    }
+
+    /// 读下一个数据，与read相同，但读失败时，读指针和已扣除的分配预算（见with_total_alloc_budget）恢复到调用前的状态，便于重试或尝试其他读法
+    pub fn read_atomic(&mut self) -> Result<EnumValue, ReadBonErr> {
+        let (bytes, head) = (self.bytes, self.head);
+        let budget = self
+            .alloc_budget
+            .as_ref()
+            .map(|b| b.load(AtomicOrdering::Relaxed));
+        let r = self.read();
+        if r.is_err() {
+            self.bytes = bytes;
+            self.head = head;
+            // 退还本次读取扣除的预算
+            if let (Some(b), Some(before)) = (&self.alloc_budget, budget) {
+                let charged = before.saturating_sub(b.load(AtomicOrdering::Relaxed));
+                b.fetch_add(charged, AtomicOrdering::Relaxed);
+            }
+        }
+        r
+    }
+
+    /// 读下一个值的类型值及其后的全部字节（包括长度描述和容器体），不解析其内容，便于原样转发未知的值
+    pub fn read_raw(&mut self) -> Result<(u8, &'a [u8]), ReadBonErr> {
+        self.skip_debug_padding();
+        let len = value_len(self.bytes)?;
+        self.probe_border(len)?;
+        let bytes = self.bytes;
+        self.skip_bytes(len);
+        Ok((bytes[0], &bytes[1..len]))
+    }
+
+    /// 读出剩余的所有值，遇到容器时，将其读为数组、map或结构体；任何一个值读取失败，返回Err
+    pub fn read_all(&mut self) -> Result<Vec<EnumValue>, ReadBonErr> {
+        let mut values = Vec::new();
+        while self.bytes.len() > 0 {
+            values.push(self.read()?);
+        }
+        Ok(values)
+    }
+
+    // 读一个容器，通用数组读为Arr，通用map读为Map（保持二进制中的顺序），其他类型的容器读为Struct（字段没有名字）
+    fn read_container_value(&mut self) -> Result<EnumValue, ReadBonErr> {
+        let mut c = self.read_container_scoped()?;
+        let mut elems = Vec::new();
+        while let Some(elem) = c.next_element() {
+            elems.push(elem?.read()?);
+        }
+        let type_id = c.type_id();
+        c.finish();
+        self.container_value(type_id, elems)
+    }
+
+    // 由容器类型和读出的元素构造EnumValue：通用数组为Arr，通用map为Map，其它容器为Struct
+    fn container_value(
+        &self,
+        type_id: u32,
+        elems: Vec<EnumValue>,
+    ) -> Result<EnumValue, ReadBonErr> {
+        match type_id {
+            2 => Ok(EnumValue::Arr(Arc::new(elems))),
+            3 if elems.len() % 2 != 0 => Err(ReadBonErr::other(
+                "map container has odd elements".to_string(),
+            )),
+            3 => {
+                let mut entries = Vec::with_capacity(elems.len() / 2);
+                let mut elems = elems.into_iter();
+                while let (Some(k), Some(v)) = (elems.next(), elems.next()) {
+                    entries.push((k, v));
+                }
+                Ok(EnumValue::Map(entries))
             }
             _ => {
-                panic!("other type TODO ReadBuffer::read");
+                let names = self.schemas.as_ref().and_then(|r| r.get(&type_id));
+                Ok(EnumValue::Struct(Arc::new(StructValue {
+                    hash: type_id,
+                    fields: elems
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, v)| FieldValue {
+                            name: names
+                                .and_then(|n| n.get(i))
+                                .cloned()
+                                .unwrap_or_default(),
+                            fvalue: v,
+                        })
+                        .collect(),
+                })))
             }
         }
     }
 
+    /// 读一个容器为StructValue，容器的元素按顺序与field_names中的名字对应，hash为容器类型；多出的元素名字为空
+    pub fn read_struct_value(&mut self, field_names: &[&str]) -> Result<StructValue, ReadBonErr> {
+        let mut c = self.read_container_scoped()?;
+        let mut fields = Vec::new();
+        while let Some(elem) = c.next_element() {
+            fields.push(FieldValue {
+                name: field_names
+                    .get(fields.len())
+                    .map(|n| n.to_string())
+                    .unwrap_or_default(),
+                fvalue: elem?.read()?,
+            });
+        }
+        let hash = c.type_id();
+        c.finish();
+        Ok(StructValue { hash, fields })
+    }
+
     /// 读一个整数类型，如果二进制当前的值不是整数类型，返回Err
     fn read_integer<
         T: AsFrom<u32> + AsFrom<u64> + AsFrom<i32> + AsFrom<i64> + AsFrom<i128> + AsFrom<u128>,
     >(
         &mut self,
     ) -> Result<T, ReadBonErr> {
+        self.skip_debug_padding();
         self.probe_border(1)?;
+        // 先探测整个值的边界，数据不完整时返回Overflow，而不是在读取时panic
+        let n = match self.bytes[0] {
+            t @ 9..=14 => INT_WIDTH[(t - 9) as usize],
+            t @ 36..=41 => INT_WIDTH[(t - 36) as usize],
+            _ => 0,
+        };
+        self.probe_border(1 + n)?;
         let t = self.bytes.get_u8();
         self.head += 1;
         if t >= 15 && t <= 35 {
@@ -696,26 +1936,23 @@ impl<'a> ReadBuffer<'a> {
                 }
                 10 => {
                     self.head += 2;
-                    Ok(T::from(-(self.bytes.get_u16_le() as i32)))
+                    Ok(T::from(-(self.payload_u16() as i32)))
                 }
                 11 => {
                     self.head += 4;
-                    Ok(T::from(-(self.bytes.get_u32_le() as i64)))
+                    Ok(T::from(-(self.payload_u32() as i64)))
                 }
                 12 => {
                     self.head += 6;
-                    Ok(T::from(
-                        -(self.bytes.get_u16_le() as i64)
-                            - ((self.bytes.get_u32_le() as i64) << 16),
-                    ))
+                    Ok(T::from(-(self.payload_u48() as i64)))
                 }
                 13 => {
                     self.head += 8;
-                    Ok(T::from(-(self.bytes.get_u64_le() as i64)))
+                    Ok(T::from((self.payload_u64() as i64).wrapping_neg()))
                 }
                 14 => {
                     self.head += 16;
-                    Ok(T::from(-(self.bytes.get_u128_le() as i128)))
+                    Ok(T::from((self.payload_u128() as i128).wrapping_neg()))
                 }
                 36 => {
                     self.head += 1;
@@ -723,25 +1960,23 @@ impl<'a> ReadBuffer<'a> {
                 }
                 37 => {
                     self.head += 2;
-                    Ok(T::from(self.bytes.get_u16_le() as u32))
+                    Ok(T::from(self.payload_u16() as u32))
                 }
                 38 => {
                     self.head += 4;
-                    Ok(T::from(self.bytes.get_u32_le()))
+                    Ok(T::from(self.payload_u32()))
                 }
                 39 => {
                     self.head += 6;
-                    Ok(T::from(
-                        self.bytes.get_u16_le() as u64 + ((self.bytes.get_u32_le() as u64) << 16),
-                    ))
+                    Ok(T::from(self.payload_u48()))
                 }
                 40 => {
                     self.head += 8;
-                    Ok(T::from(self.bytes.get_u64_le() as u64))
+                    Ok(T::from(self.payload_u64() as u64))
                 }
                 41 => {
-                    self.head += 8;
-                    Ok(T::from(self.bytes.get_u128_le() as u128))
+                    self.head += 16;
+                    Ok(T::from(self.payload_u128() as u128))
                 }
                 _ => {
                     log::error!("read integer error, act_type: {}, bin: {:?}", t, self.bytes);
@@ -755,6 +1990,12 @@ impl<'a> ReadBuffer<'a> {
         }
     }
 
+    // 探测当前的整个值（包括长度描述和数据）的边界，数据不完整时返回Overflow，避免读取时panic
+    #[inline]
+    fn probe_value(&self) -> Result<(), ReadBonErr> {
+        self.probe_border(value_len(self.bytes)?)
+    }
+
     //探测边界， 如果越界， 返回错误
     #[inline]
     fn probe_border(&self, len: usize) -> Result<(), ReadBonErr> {
@@ -766,13 +2007,251 @@ impl<'a> ReadBuffer<'a> {
     }
 }
 
+/// 借用的bon二进制，可以直接作为BTreeMap等有序容器的键，不需要拥有WriteBuffer
+///
+/// 有效的键（恰好是一个完整的值，且可以与自身比较）之间按ReadBuffer::try_cmp比较；
+/// 无效的键（为空、不完整、包含多个值或不支持比较的类型）总是排在所有有效的键之后，无效的键之间按字节比较，以保证全序
+#[derive(Debug, Clone, Copy)]
+pub struct BonKey<'a>(pub &'a [u8]);
+
+impl<'a> PartialOrd for BonKey<'a> {
+    fn partial_cmp(&self, other: &BonKey<'a>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> PartialEq for BonKey<'a> {
+    fn eq(&self, other: &BonKey<'a>) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a> Eq for BonKey<'a> {}
+
+impl<'a> BonKey<'a> {
+    // 是否为有效的键
+    fn is_valid(&self) -> bool {
+        let r = ReadBuffer::new(self.0, 0);
+        is_single_value(self.0).unwrap_or(false) && r.try_cmp(&r).is_ok()
+    }
+}
+
+impl<'a> Ord for BonKey<'a> {
+    fn cmp(&self, other: &BonKey<'a>) -> Ordering {
+        match (self.is_valid(), other.is_valid()) {
+            (true, true) => ReadBuffer::new(self.0, 0)
+                .try_cmp(&ReadBuffer::new(other.0, 0))
+                .unwrap_or_else(|_| self.0.cmp(other.0)),
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => self.0.cmp(other.0),
+        }
+    }
+}
+
+/// 容器读取器，由ReadBuffer::read_container_scoped创建，只能读取容器体内的元素
+pub struct ContainerReader<'p, 'a> {
+    // 容器所在的ReadBuffer
+    parent: &'p mut ReadBuffer<'a>,
+    // 容器体中尚未读取的部分
+    body: ReadBuffer<'a>,
+    type_id: u32,
+    count: Option<u32>,
+    // 整个容器（包括头部）占用的字节数
+    size: usize,
+}
+
+impl<'p, 'a> ContainerReader<'p, 'a> {
+    /// 容器的类型
+    pub fn type_id(&self) -> u32 {
+        self.type_id
+    }
+
+    /// 元素数量，只有通用对象、数组、map才有
+    pub fn count(&self) -> Option<u32> {
+        self.count
+    }
+
+    /// 容器体中尚未读取的部分，可直接在其上依次读取元素
+    pub fn body(&mut self) -> &mut ReadBuffer<'a> {
+        &mut self.body
+    }
+
+    /// 读下一个元素，返回只包含该元素的ReadBuffer，容器体已读完时，返回None
+    pub fn next_element(&mut self) -> Option<Result<ReadBuffer<'a>, ReadBonErr>> {
+        if self.body.bytes.is_empty() {
+            return None;
+        }
+        let len = match value_len(self.body.bytes) {
+            Ok(len) if len > self.body.bytes.len() => {
+                return Some(Err(ReadBonErr::overflow(len, self.body.bytes.len())))
+            }
+            Ok(len) => len,
+            Err(e) => return Some(Err(e)),
+        };
+        let elem = self.body.child(&self.body.bytes[..len], self.body.head);
+        self.body.bytes.advance(len);
+        self.body.head += len;
+        Some(Ok(elem))
+    }
+
+    /// 结束读取，容器所在的ReadBuffer跳过整个容器
+    pub fn finish(self) {
+        self.parent.bytes.advance(self.size);
+        self.parent.head += self.size;
+    }
+}
+
+/// 通用数组的元素迭代器，由ReadBuffer::array_iter创建
+pub struct BonArrayIter<'p, 'a> {
+    c: Option<ContainerReader<'p, 'a>>,
+    // 剩余的元素数量
+    remaining: u32,
+}
+
+impl<'p, 'a> Iterator for BonArrayIter<'p, 'a> {
+    type Item = Result<EnumValue, ReadBonErr>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let c = self.c.as_mut()?;
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        match c.next_element() {
+            Some(elem) => Some(elem.and_then(|mut e| e.read())),
+            None => {
+                self.remaining = 0;
+                Some(Err(ReadBonErr::other(
+                    "array has fewer elements than its count".to_string(),
+                )))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining as usize))
+    }
+}
+
+impl<'p, 'a> Drop for BonArrayIter<'p, 'a> {
+    fn drop(&mut self) {
+        if let Some(c) = self.c.take() {
+            c.finish();
+        }
+    }
+}
+
+/// 通用map的键值对迭代器，由ReadBuffer::map_iter创建
+pub struct BonMapIter<'p, 'a> {
+    c: Option<ContainerReader<'p, 'a>>,
+    // 剩余的键值对数量
+    remaining: u32,
+}
+
+impl<'p, 'a> BonMapIter<'p, 'a> {
+    // 读键值对中的一个值
+    fn read_one(&mut self) -> Result<EnumValue, ReadBonErr> {
+        match self.c.as_mut().and_then(|c| c.next_element()) {
+            Some(elem) => elem?.read(),
+            None => Err(ReadBonErr::other(
+                "map has fewer entries than its count".to_string(),
+            )),
+        }
+    }
+}
+
+impl<'p, 'a> Iterator for BonMapIter<'p, 'a> {
+    type Item = Result<(EnumValue, EnumValue), ReadBonErr>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.c.is_none() || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let r = self.read_one().and_then(|k| Ok((k, self.read_one()?)));
+        if r.is_err() {
+            self.remaining = 0;
+        }
+        Some(r)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining as usize))
+    }
+}
+
+impl<'p, 'a> Drop for BonMapIter<'p, 'a> {
+    fn drop(&mut self) {
+        if let Some(c) = self.c.take() {
+            c.finish();
+        }
+    }
+}
+
 /// 用于对数据进行序列化
+#[allow(clippy::derived_hash_with_manual_eq)]
 #[derive(Default, Clone, Debug, Hash)]
 pub struct WriteBuffer {
     // u8数组
     pub bytes: Vec<u8>,
     // 尾部指针
     tail: usize,
+    // 每个顶层值的起始位置，为None时不记录
+    offsets: Option<Vec<usize>>,
+    // 当前所在的容器深度，0表示顶层
+    depth: usize,
+    // 流式哈希的状态（哈希值，已参与哈希的字节数），为None时不计算
+    hash: Option<(u64, usize)>,
+    // 数值的字节序
+    endian: Endian,
+    // 调试模式下顶层值的对齐边界，为0时不对齐
+    debug_align: usize,
+}
+
+/// 二进制数据的写入器，由WriteBuffer::bin_writer创建，drop时回填二进制数据的长度
+pub struct BinWriter<'w> {
+    buf: &'w mut WriteBuffer,
+    // 二进制数据（包括类型和长度）的起始位置
+    start: usize,
+}
+
+impl<'w> BinWriter<'w> {
+    // 为类型和长度预留的字节数
+    const RESERVED: usize = 5;
+}
+
+impl<'w> std::io::Write for BinWriter<'w> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buf.bytes.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'w> Drop for BinWriter<'w> {
+    fn drop(&mut self) {
+        let bytes = &mut self.buf.bytes;
+        let body_start = self.start + BinWriter::RESERVED;
+        let len = bytes.len() - body_start;
+        let (code, len_bytes) = data_len_code(len, 111);
+        let header_len = 1 + len_bytes;
+        if header_len != BinWriter::RESERVED {
+            let end = bytes.len();
+            if header_len > BinWriter::RESERVED {
+                bytes.reserve(header_len - BinWriter::RESERVED);
+            }
+            move_part(bytes, body_start..end, self.start + header_len);
+            bytes.truncate(self.start + header_len + len);
+        }
+        bytes[self.start] = code;
+        bytes[self.start + 1..self.start + header_len]
+            .copy_from_slice(&(len as u64).to_le_bytes()[..len_bytes]);
+        self.buf.tail += header_len + len;
+    }
 }
 
 impl Deref for WriteBuffer {
@@ -784,13 +2263,17 @@ impl Deref for WriteBuffer {
 
 impl PartialOrd for WriteBuffer {
     fn partial_cmp(&self, other: &WriteBuffer) -> Option<Ordering> {
-        ReadBuffer::new(self.bytes.as_slice(), 0)
-            .partial_cmp(&ReadBuffer::new(other.bytes.as_slice(), 0))
+        self.reader().partial_cmp(&other.reader())
     }
 }
 
+/// 按协议比较是否相等，与ReadBuffer相同，字节完全相同时即使无法解析也返回true
 impl PartialEq for WriteBuffer {
     fn eq(&self, other: &WriteBuffer) -> bool {
+        // 字节完全相同时一定相等，不需要按协议比较
+        if self.bytes == other.bytes {
+            return true;
+        }
         match self.partial_cmp(other) {
             Some(Ordering::Equal) => return true,
             _ => return false,
@@ -802,9 +2285,9 @@ impl Eq for WriteBuffer {}
 
 impl Ord for WriteBuffer {
     fn cmp(&self, other: &WriteBuffer) -> Ordering {
-        match self.partial_cmp(other) {
-            Some(v) => v,
-            None => panic!("partial_cmp fail"),
+        match self.reader().try_cmp(&other.reader()) {
+            Ok(v) => v,
+            Err(e) => panic!("partial_cmp fail: {}", e),
         }
     }
 }
@@ -815,6 +2298,11 @@ impl WriteBuffer {
         WriteBuffer {
             bytes: Vec::new(),
             tail: 0,
+            offsets: None,
+            depth: 0,
+            hash: None,
+            endian: Endian::Little,
+            debug_align: 0,
         }
     }
 
@@ -823,6 +2311,11 @@ impl WriteBuffer {
         WriteBuffer {
             bytes: buf,
             tail: tail,
+            offsets: None,
+            depth: 0,
+            hash: None,
+            endian: Endian::Little,
+            debug_align: 0,
         }
     }
 
@@ -831,9 +2324,20 @@ impl WriteBuffer {
         WriteBuffer {
             bytes: Vec::with_capacity(size),
             tail: 0,
+            offsets: None,
+            depth: 0,
+            hash: None,
+            endian: Endian::Little,
+            debug_align: 0,
         }
     }
 
+    /// 设置整数、浮点数数值的字节序，仅用于互通，见Endian
+    pub fn with_endianness(mut self, endian: Endian) -> Self {
+        self.endian = endian;
+        self
+    }
+
     /// 下一次写的起始位置
     pub fn tail(&self) -> usize {
         self.tail
@@ -849,63 +2353,168 @@ impl WriteBuffer {
         self.bytes
     }
 
-    /// 清空buffer
+    /// 结束写入，返回以4字节小端的总长度开头的帧，便于在字节流中分隔消息，用read_frame读取
+    pub fn finish_framed(self) -> Vec<u8> {
+        let len = u32::try_from(self.bytes.len()).expect("frame overflow");
+        let mut frame = Vec::with_capacity(4 + self.bytes.len());
+        frame.extend_from_slice(&len.to_le_bytes());
+        frame.extend_from_slice(&self.bytes);
+        frame
+    }
+
+    /// 清空buffer，保留已分配的容量，便于复用WriteBuffer序列化多个消息
     pub fn clear(&mut self) {
+        self.bytes.clear();
         self.tail = 0;
+        if let Some(offsets) = &mut self.offsets {
+            offsets.clear();
+        }
+        if self.hash.is_some() {
+            self.hash = Some((FNV_OFFSET, 0));
+        }
+    }
+
+    /// 设置是否在写入时流式计算哈希，开启时从buffer头部重新开始计算
+    ///
+    /// 每写完一个顶层值，其字节即参与哈希，不需要在结束后再遍历一遍；
+    /// 哈希基于写入的字节，HashMap等无序集合的写入顺序不确定，需要稳定哈希时应避免
+    pub fn set_streaming_hash(&mut self, enable: bool) {
+        self.hash = match enable {
+            true => Some((FNV_OFFSET, 0)),
+            false => None,
+        };
+    }
+
+    /// 已写入的全部字节的哈希值（FNV-1a 64），与hash_bytes(self.get_byte())相同
+    ///
+    /// 开启流式哈希时，只需计算尚未参与哈希的字节
+    pub fn finish_hash(&self) -> u64 {
+        let (h, pos) = self.hash.unwrap_or((FNV_OFFSET, 0));
+        fnv_update(h, &self.bytes[pos..])
+    }
+
+    /// 用指定的哈希算法计算已写入的值的内容哈希，即依次将每个顶层值的最短编码（见canonicalize）送入哈希，
+    /// 与H::default()处理canonicalize(self.get_byte())的结果相同，编码方式不同但内容相同的值哈希相同
+    pub fn hash_with<H: ContentHasher + Default>(&self) -> Result<Vec<u8>, ReadBonErr> {
+        let mut hasher = H::default();
+        let mut r = self.reader();
+        while !r.bytes.is_empty() {
+            let mut w = WriteBuffer::new();
+            canonical_value(&mut r, &mut w)?;
+            hasher.update(w.get_byte());
+        }
+        Ok(hasher.finish())
+    }
+
+    // 读取已写入内容的ReadBuffer，使用相同的字节序
+    fn reader(&self) -> ReadBuffer {
+        ReadBuffer::new(&self.bytes, 0).with_endianness(self.endian)
+    }
+
+    /// 设置是否记录每个顶层值的起始位置，开启时会清空已记录的位置
+    pub fn set_track_offsets(&mut self, track: bool) {
+        self.offsets = match track {
+            true => Some(Vec::new()),
+            false => None,
+        };
+    }
+
+    /// 设置调试模式下顶层值的对齐边界，为0时关闭（默认）
+    ///
+    /// 开启后，每个顶层值写入前会插入类型为0、容器体为空或全为Void的容器作为填充，使值的起始位置对齐到align的整数倍，便于用十六进制工具查看；
+    /// 填充不足5字节时顺延到下一个边界。由多个部分组成的值（如Vec的长度和元素）在部分之间也可能被填充，
+    /// 读取时需开启ReadBuffer::with_debug_padding，各个读取方法（包括read、skip_value、read_u32等类型化的读取和Decode）会先跳过这些填充，仅用于调试
+    pub fn set_debug_align(&mut self, align: usize) {
+        assert!(align <= 0xff00, "debug align too large: {}", align);
+        self.debug_align = align;
+    }
+
+    /// 已写入的每个顶层值的起始位置，未开启记录时为空
+    pub fn value_offsets(&self) -> &[usize] {
+        match &self.offsets {
+            Some(offsets) => offsets.as_slice(),
+            None => &[],
+        }
     }
 
     /// 写一个u8
     pub fn write_u8(&mut self, v: u8) {
+        self.mark_value();
         self.write_uint32(v as u32);
     }
 
     /// 写一个u16
     pub fn write_u16(&mut self, v: u16) {
+        self.mark_value();
         self.write_uint32(v as u32);
     }
 
     /// 写一个u32
     pub fn write_u32(&mut self, v: u32) {
+        self.mark_value();
         self.write_uint32(v);
     }
 
     /// 写一个u64
     pub fn write_u64(&mut self, v: u64) {
+        self.mark_value();
         self.write_uint64(v);
     }
 
+    /// 写一个u64，likely_small为true时，优先检查小于256的值，适用于绝大多数值都较小的场景；序列化结果与write_u64相同
+    #[inline]
+    pub fn write_u64_hint(&mut self, v: u64, likely_small: bool) {
+        self.mark_value();
+        if !likely_small {
+            self.write_uint64(v);
+        } else if v < 20 {
+            self.write_common(v as i8);
+        } else if v <= 0xFF {
+            self.write_8(v as u8, 36);
+        } else {
+            self.write_uint64(v);
+        }
+    }
+
     /// 写一个u128
     pub fn write_u128(&mut self, v: u128) {
+        self.mark_value();
         self.write_uint128(v);
     }
 
     /// 写一个i8
     pub fn write_i8(&mut self, v: i8) {
+        self.mark_value();
         self.write_int32(v as i32);
     }
 
     /// 写一个i16
     pub fn write_i16(&mut self, v: i16) {
+        self.mark_value();
         self.write_int32(v as i32);
     }
 
     /// 写一个i32
     pub fn write_i32(&mut self, v: i32) {
+        self.mark_value();
         self.write_int32(v);
     }
 
     /// 写一个i64
     pub fn write_i64(&mut self, v: i64) {
+        self.mark_value();
         self.write_int64(v);
     }
 
     /// 写一个i128
     pub fn write_i128(&mut self, v: i128) {
+        self.mark_value();
         self.write_int128(v);
     }
 
     /// 写一个None
     pub fn write_nil(&mut self) {
+        self.mark_value();
         self.try_extend_capity(1);
         self.bytes.put_u8(0);
         self.tail += 1;
@@ -913,6 +2522,7 @@ impl WriteBuffer {
 
     /// 写一个bool
     pub fn write_bool(&mut self, v: bool) {
+        self.mark_value();
         self.try_extend_capity(1);
         self.bytes.put_u8(match v {
             true => 2,
@@ -921,8 +2531,31 @@ impl WriteBuffer {
         self.tail += 1;
     }
 
+    /// 写一个16位浮点数，类型为5，之后是2字节小端的值
+    #[cfg(feature = "half")]
+    pub fn write_f16(&mut self, v: half::f16) {
+        self.mark_value();
+        if v == half::f16::ZERO {
+            self.try_extend_capity(1);
+            self.bytes.put_u8(3);
+            self.tail += 1;
+            return;
+        }
+        if v == half::f16::ONE {
+            self.try_extend_capity(1);
+            self.bytes.put_u8(4);
+            self.tail += 1;
+            return;
+        }
+        self.try_extend_capity(3);
+        self.bytes.put_u8(5);
+        self.put_payload_u16(v.to_bits());
+        self.tail += 3;
+    }
+
     /// 写一个f32
     pub fn write_f32(&mut self, v: f32) {
+        self.mark_value();
         if v == 0.0 {
             self.try_extend_capity(1);
             self.bytes.put_u8(3);
@@ -937,12 +2570,13 @@ impl WriteBuffer {
         }
         self.try_extend_capity(5);
         self.bytes.put_u8(6);
-        self.bytes.put_f32_le(v);
+        self.put_payload_u32(v.to_bits());
         self.tail += 5;
     }
 
     /// 写一个f64
     pub fn write_f64(&mut self, v: f64) {
+        self.mark_value();
         if v == 0.0 {
             self.try_extend_capity(1);
             self.bytes.put_u8(3);
@@ -957,13 +2591,29 @@ impl WriteBuffer {
         }
         self.try_extend_capity(9);
         self.bytes.put_u8(7);
-        self.bytes.put_f64_le(v);
+        self.put_payload_u64(v.to_bits());
         self.tail += 9;
     }
+
+    /// 紧凑地写一组坐标，先写坐标数量，再将所有坐标按小端的f64依次排列，写为一个二进制数据，用read_coords读取
+    ///
+    /// 每个坐标固定占用16字节，不受字节序设置的影响
+    pub fn write_coords(&mut self, coords: &[[f64; 2]]) {
+        self.write_u64(coords.len() as u64);
+        let writer = self.bin_writer();
+        writer.buf.bytes.reserve(coords.len() * 16);
+        for [x, y] in coords.iter() {
+            writer.buf.bytes.extend_from_slice(&x.to_le_bytes());
+            writer.buf.bytes.extend_from_slice(&y.to_le_bytes());
+        }
+    }
+
     /// 写入一个动态长度，正整数，不允许大于0x20000000。
     /// * 1字节： 0xxxxxxx
     /// * 2字节： 10xxxxxx xxxxxxxx
     /// * 4字节： 110xxxxx xxxxxxxx xxxxxxxx xxxxxxxx
+    ///
+    /// 多字节时为大端序，以保证标志位在第一个字节
     pub fn write_lengthen(&mut self, t: u32) {
         if t < 0x80 {
             self.try_extend_capity(1);
@@ -971,134 +2621,475 @@ impl WriteBuffer {
             self.tail += 1;
         } else if t < 0x4000 {
             self.try_extend_capity(2);
-            self.bytes.put_u16_ne((0x8000 + t) as u16);
+            self.bytes.put_u16((0x8000 + t) as u16);
             self.tail += 2;
-        } else if t < 0x20000000 {
-            self.try_extend_capity(4);
-            self.bytes.put_u32_ne((0xC0000000 + t) as u32);
-            self.tail += 4;
         } else {
+            self.write_lengthen_fixed(t);
+        }
+    }
+
+    // 总是使用4字节的形式写入动态长度
+    fn write_lengthen_fixed(&mut self, t: u32) {
+        if t >= 0x20000000 {
             panic!("invalid lengthen, it's {}", t);
         }
+        self.try_extend_capity(4);
+        self.bytes.put_u32(0xC0000000 + t);
+        self.tail += 4;
     }
 
     ///写字符串
     pub fn write_utf8(&mut self, s: &str) {
-        self.write_data(s.as_bytes(), 42);
+        self.mark_value();
+        let arr = s.as_bytes();
+        if arr.len() <= 64 {
+            self.write_tiny_data(arr, 42);
+        } else {
+            self.write_data(arr, 42);
+        }
+    }
+
+    /// 写一个UTF-16编码的字符串，转为UTF-8写入，无效的代理项会被替换为U+FFFD
+    pub fn write_utf16(&mut self, s: &[u16]) {
+        self.write_utf8(&String::from_utf16_lossy(s));
     }
 
     /// 写二进制数据
     pub fn write_bin(&mut self, arr: &[u8], range: Range<usize>) {
-        self.write_data(&arr[range], 111)
+        self.mark_value();
+        let arr = &arr[range];
+        if arr.len() <= 64 {
+            self.write_tiny_data(arr, 111);
+        } else {
+            self.write_data(arr, 111);
+        }
+    }
+
+    /// 写一个小map，没有类型，只用1个字节描述总大小，适用于键值类型已约定的少量键值对
+    ///
+    /// 键值对序列化后的总大小不能超过255，否则panic
+    pub fn write_small_map<'m, K, V, I>(&mut self, entries: I)
+    where
+        K: Encode + 'm,
+        V: Encode + 'm,
+        I: IntoIterator<Item = (&'m K, &'m V)>,
+    {
+        self.mark_value();
+        self.try_extend_capity(2);
+        self.bytes.put_u8(251);
+        self.bytes.put_u8(0);
+        self.tail += 2;
+        let start = self.bytes.len();
+        self.depth += 1;
+        for (k, v) in entries {
+            k.encode(self);
+            v.encode(self);
+        }
+        self.depth -= 1;
+        let len = self.bytes.len() - start;
+        if len > 0xff {
+            panic!("small map overflow, len = {}", len);
+        }
+        self.bytes[start - 1] = len as u8;
+    }
+
+    /// 将一个值包装在类型为0的容器中写入，使其带有总大小的描述，读时可以整体跳过，或通过ReadBuffer::read_sized解包
+    pub fn write_sized<T: Encode>(&mut self, value: &T) {
+        self.write_container(
+            value,
+            |w, v| {
+                w.try_extend_capity(4);
+                w.bytes.put_u32_le(0);
+                w.tail += 4;
+                v.encode(w);
+            },
+            None,
+        );
+    }
+
+    /// 写一个保留符号信息的整数，用类型为4（有符号）或5（无符号）的容器包装整数，读时可区分同值的有符号和无符号整数
+    pub fn write_tagged_int(&mut self, v: TaggedInt) {
+        self.write_container(
+            &v,
+            |w, v| {
+                w.try_extend_capity(4);
+                match *v {
+                    TaggedInt::Signed(i) => {
+                        w.bytes.put_u32_le(4);
+                        w.tail += 4;
+                        w.write_int128(i);
+                    }
+                    TaggedInt::Unsigned(u) => {
+                        w.bytes.put_u32_le(5);
+                        w.tail += 4;
+                        w.write_uint128(u);
+                    }
+                }
+            },
+            Some(21),
+        );
+    }
+
+    /// 写一个定点小数，值为mantissa / 10^scale，写为包含尾数和小数位数两个元素的通用对象
+    pub fn write_decimal(&mut self, mantissa: i128, scale: u8) {
+        self.write_container_counted(
+            1,
+            2,
+            false,
+            &(mantissa, scale),
+            |w, &(mantissa, scale)| {
+                w.write_i128(mantissa);
+                w.write_u8(scale);
+            },
+            Some(25),
+        );
+    }
+
+    /// 写一个稀疏数组，只写入不等于T::default()的元素及其序号，适合绝大部分元素为默认值的大数组
+    ///
+    /// 容器类型为6，容器体先写数组长度，再按序号递增依次写入（序号，值）
+    pub fn write_sparse_array<T: Encode + Default + PartialEq>(&mut self, arr: &[T]) {
+        let default = T::default();
+        self.write_container_typed(
+            6,
+            &arr,
+            |w, arr| {
+                w.write_u64(arr.len() as u64);
+                for (i, v) in arr.iter().enumerate() {
+                    if *v != default {
+                        w.write_u64(i as u64);
+                        v.encode(w);
+                    }
+                }
+            },
+            None,
+        );
+    }
+
+    /// 写一个扩展类型的值，使用registry中为T注册的类型和编码方法，T未注册时panic
+    pub fn write_extension<T: 'static>(&mut self, registry: &CodecRegistry, value: &T) {
+        let (type_id, codec) = registry
+            .codec::<T>()
+            .unwrap_or_else(|| panic!("extension not registered: {}", std::any::type_name::<T>()));
+        self.write_container(
+            value,
+            |w, v| {
+                w.try_extend_capity(4);
+                w.bytes.put_u32_le(type_id);
+                w.tail += 4;
+                (codec.encode)(v, w);
+            },
+            None,
+        );
+    }
+
+    /// 写一个EnumValue，总是选择最短的编码：整数使用最小宽度，容器使用最短的长度描述，map按键的二进制排序
+    ///
+    /// Arr写为通用数组，Map写为通用map，Struct写为类型为hash的容器（hash为1~3时带元素数量）
+    pub fn write_value(&mut self, v: &EnumValue) {
+        match v {
+            EnumValue::Void => self.write_nil(),
+            EnumValue::Bool(v) => self.write_bool(*v),
+            EnumValue::U8(v) => self.write_canonical_int(*v as i128),
+            EnumValue::U16(v) => self.write_canonical_int(*v as i128),
+            EnumValue::U32(v) => self.write_canonical_int(*v as i128),
+            EnumValue::U64(v) => self.write_canonical_int(*v as i128),
+            EnumValue::U128(v) if *v > i128::MAX as u128 => self.write_u128(*v),
+            EnumValue::U128(v) => self.write_canonical_int(*v as i128),
+            EnumValue::I8(v) => self.write_canonical_int(*v as i128),
+            EnumValue::I16(v) => self.write_canonical_int(*v as i128),
+            EnumValue::I32(v) => self.write_canonical_int(*v as i128),
+            EnumValue::I64(v) => self.write_canonical_int(*v as i128),
+            EnumValue::I128(v) => self.write_canonical_int(*v),
+            EnumValue::F32(v) => self.write_f32(*v),
+            EnumValue::F64(v) => self.write_f64(*v),
+            EnumValue::Str(v) => self.write_utf8(v),
+            EnumValue::Bin(v) => self.write_bin(v, 0..v.len()),
+            EnumValue::Arr(arr) => self.write_container_counted(
+                2,
+                arr.len() as u32,
+                false,
+                arr,
+                |w, arr| {
+                    for v in arr.iter() {
+                        w.write_value(v);
+                    }
+                },
+                None,
+            ),
+            EnumValue::Map(map) => {
+                let mut entries: Vec<(Vec<u8>, &EnumValue)> =
+                    map.iter().map(|(k, v)| (encode_value(k), v)).collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                self.write_container_counted(
+                    3,
+                    entries.len() as u32,
+                    false,
+                    &entries,
+                    |w, entries| {
+                        for (k, v) in entries.iter() {
+                            w.write_raw(k);
+                            w.write_value(v);
+                        }
+                    },
+                    None,
+                );
+            }
+            EnumValue::Struct(st) => {
+                let count = match st.hash {
+                    1..=3 => Some(st.fields.len() as u32),
+                    _ => None,
+                };
+                self.write_typed_container(st.hash, count, st, |w, st| {
+                    for f in st.fields.iter() {
+                        w.write_value(&f.fvalue);
+                    }
+                });
+            }
+        }
+    }
+
+    // 以最短的编码写一个整数，非负数与无符号整数的编码相同
+    fn write_canonical_int(&mut self, v: i128) {
+        if v >= 0 && v <= u64::MAX as i128 {
+            self.write_u64(v as u64);
+        } else if v >= i64::MIN as i128 && v < 0 {
+            self.write_i64(v as i64);
+        } else {
+            self.write_i128(v);
+        }
+    }
+
+    // 写已编码好的二进制
+    fn write_raw(&mut self, bytes: &[u8]) {
+        self.try_extend_capity(bytes.len());
+        self.bytes.extend_from_slice(bytes);
+        self.tail += bytes.len();
+    }
+
+    // 写一个指定类型的容器，count不为None时，在类型后写元素数量
+    fn write_typed_container<T, F>(
+        &mut self,
+        type_id: u32,
+        count: Option<u32>,
+        o: &T,
+        write_next: F,
+    ) where
+        F: Fn(&mut WriteBuffer, &T),
+    {
+        match count {
+            Some(count) => self.write_container_counted(type_id, count, false, o, write_next, None),
+            None => self.write_container(
+                o,
+                |w, o| {
+                    w.try_extend_capity(4);
+                    w.bytes.put_u32_le(type_id);
+                    w.tail += 4;
+                    write_next(w, o);
+                },
+                None,
+            ),
+        }
+    }
+
+    /// 写一个带标签的值，标签为1个字节，与协议的类型值无关；标签和write_payload写入的内容一起写为一个二进制数据
+    pub fn write_variant<F: FnOnce(&mut WriteBuffer)>(&mut self, tag: u8, write_payload: F) {
+        let writer = self.bin_writer();
+        let tail = writer.buf.tail;
+        writer.buf.bytes.push(tag);
+        writer.buf.depth += 1;
+        write_payload(writer.buf);
+        writer.buf.depth -= 1;
+        // 由BinWriter统一更新tail
+        writer.buf.tail = tail;
+    }
+
+    /// 返回一个二进制数据的写入器，可以通过std::io::Write分多次写入二进制数据，写入器drop时，回填二进制数据的长度
+    pub fn bin_writer(&mut self) -> BinWriter<'_> {
+        self.mark_value();
+        let start = self.bytes.len();
+        self.try_extend_capity(BinWriter::RESERVED);
+        self.bytes.resize(start + BinWriter::RESERVED, 0);
+        BinWriter { buf: self, start }
+    }
+
+    /// 写一个指定类型的容器，容器体以4字节的type_id开头，之后由write_next写入容器内容
+    pub fn write_container_typed<T, F>(
+        &mut self,
+        type_id: u32,
+        o: &T,
+        write_next: F,
+        estimated_size: Option<usize>,
+    ) where
+        F: Fn(&mut WriteBuffer, &T),
+    {
+        self.write_container(
+            o,
+            |w, o| {
+                w.try_extend_capity(4);
+                w.bytes.put_u32_le(type_id);
+                w.tail += 4;
+                write_next(w, o);
+            },
+            estimated_size,
+        );
+    }
+
+    /// 写一个带元素数量的通用容器，type_id为容器类型（1通用对象，2通用数组，3通用map），count为元素数量
+    ///
+    /// fixed_count为true时，数量总是使用4字节形式的动态长度写入，第一个元素在容器体中的偏移固定为8（4字节类型 + 4字节数量），
+    /// 便于随机访问；代价是元素数量小于0x4000时，会多占用2~3个字节。读取方式与非固定宽度的数量相同。
+    pub fn write_container_counted<T, F>(
+        &mut self,
+        type_id: u32,
+        count: u32,
+        fixed_count: bool,
+        o: &T,
+        write_next: F,
+        estimated_size: Option<usize>,
+    ) where
+        F: Fn(&mut WriteBuffer, &T),
+    {
+        self.write_container(
+            o,
+            |w, o| {
+                w.try_extend_capity(4);
+                w.bytes.put_u32_le(type_id);
+                w.tail += 4;
+                match fixed_count {
+                    true => w.write_lengthen_fixed(count),
+                    false => w.write_lengthen(count),
+                }
+                let elems_start = w.bytes.len();
+                write_next(w, o);
+                // 调试模式下，检查实际写入的元素数量与声明的数量是否一致，map的每个元素为一个键值对
+                if cfg!(debug_assertions) {
+                    let expected = match type_id {
+                        3 => count as usize * 2,
+                        _ => count as usize,
+                    };
+                    let written = count_values(&w.bytes[elems_start..]);
+                    assert_eq!(
+                        written,
+                        Some(expected),
+                        "container element count mismatch, declared {}",
+                        count
+                    );
+                }
+            },
+            estimated_size,
+        );
     }
 
     /// 写容器。容器有数组，map，枚举，struct
+    ///
+    /// estimated_size只用于预留容器头和预分配内存，写完后容器头总是按实际大小使用最短的长度描述，
+    /// 因此输出与estimated_size无关（0.2.6及之前会保留按estimated_size预留的较长的容器头）
     pub fn write_container<T, F>(&mut self, o: &T, write_next: F, estimated_size: Option<usize>)
     where
         F: Fn(&mut WriteBuffer, &T),
     {
-        let t = self.bytes.len();
-        let len_bytes: usize; //描述容器长度的值的字节数
-        let capacity = self.bytes.capacity();
+        let header = self.begin_container(estimated_size);
+        write_next(self, o);
+        self.end_container(header);
+    }
+
+    // 开始写一个容器，根据预估大小预留容器头，返回（容器起始位置，预留的长度字节数），容器体写完后需调用end_container
+    fn begin_container(&mut self, estimated_size: Option<usize>) -> (usize, usize) {
+        self.mark_value();
         // 根据预估大小，预留出足够的空间来写入容器的总大小
         let estimated_size = match estimated_size {
             Some(v) => v,
             None => 0xffff,
         };
-        let mut limit_size: u64;
-
-        if estimated_size <= 64 {
-            self.try_extend_capity(5 + estimated_size);
-            len_bytes = 0;
-            limit_size = 64;
-        } else if estimated_size <= 0xff {
-            self.try_extend_capity(6 + estimated_size);
-            len_bytes = 1;
-            limit_size = 0xff;
-        } else if estimated_size <= 0xffff {
-            self.try_extend_capity(8 + estimated_size);
-            len_bytes = 3;
-            limit_size = 0xffff;
-        } else if estimated_size <= 0xffffffff {
-            self.try_extend_capity(10 + estimated_size);
-            len_bytes = 5;
-            limit_size = 0xffffffff;
-        } else if estimated_size as u64 <= 0xffffffffffff {
-            self.try_extend_capity(12 + estimated_size);
-            len_bytes = 7;
-            limit_size = 0xffffffffffff;
-        } else {
-            self.try_extend_capity(14 + estimated_size);
-            len_bytes = 9;
-            limit_size = 0xffffffffffffffff;
-        }
-        let tt = self.tail;
-        write_next(self, o);
-        let len = (self.tail - tt) as u64;
-        // 判断实际写入的大小超出预期的大小，需要移动数据
-        if limit_size < len && len > 64 {
-            let mut len_bytes1: usize = 0;
-            if limit_size <= 64 && len <= 0xff {
-                len_bytes1 = 1;
-                limit_size = 0xff;
-            } else if len <= 0xffff {
-                len_bytes1 = 3;
-                limit_size = 0xffff;
-            } else if len <= 0xffffffff {
-                len_bytes1 = 5;
-                limit_size = 0xffffffff;
-            } else if len <= 0xffffffffffff as u64 {
-                len_bytes1 = 7;
-                limit_size = 0xffffffffffff;
-            } else if len <= 0xfffffffffffffffe + 1 {
-                len_bytes1 = 9;
-                limit_size = 0xffffffffffffffff;
-            }
-
-            let offset = len_bytes1 - len_bytes;
-            let l = self.bytes.len();
-            self.try_extend_capity(l + offset - capacity);
-            // self.bytes.move_part(t..l, t + offset);
-            move_part(&mut self.bytes, t..l, t + offset);
-            self.tail += offset;
-        }
-        // 根据实际的限制大小，写入实际长度
-        match limit_size {
-            64 => {
-                self.bytes.put_u8((180 + len) as u8);
-            }
-            0xff => {
-                self.bytes.put_u8(245);
-                self.bytes.put_u8(len as u8);
-            }
-            0xffff => {
-                let mut v: Vec<u8> = Vec::with_capacity(1 + 2 + self.bytes.len());
-                v.put_u8(246);
-                v.put_u16_le(len as u16);
-                v.extend_from_slice(&self.bytes);
-                std::mem::replace(&mut self.bytes, v);
-            }
-            0xffffffff => {
-                let mut v: Vec<u8> = Vec::with_capacity(1 + 4 + self.bytes.len());
-                v.put_u8(247);
-                v.put_u32_le(len as u32);
-                v.extend_from_slice(&self.bytes);
-                std::mem::replace(&mut self.bytes, v);
-            }
-            0xffffffffffff => {
-                let mut v: Vec<u8> = Vec::with_capacity(1 + 6 + self.bytes.len());
-                v.put_u8(248);
-                v.put_u16_le((len & 0xffff) as u16);
-                v.put_u32_le((len >> 16) as u32);
-                std::mem::replace(&mut self.bytes, v);
+        let (_, reserved) = container_len_code(estimated_size as u64);
+        self.try_extend_capity(1 + reserved + estimated_size);
+        let start = self.bytes.len();
+        self.bytes.resize(start + 1 + reserved, 0);
+        self.tail += 1 + reserved;
+        self.depth += 1;
+        (start, reserved)
+    }
+
+    // 结束写容器，根据容器体的实际大小写入容器头
+    fn end_container(&mut self, (start, reserved): (usize, usize)) {
+        self.depth -= 1;
+        let body_start = start + 1 + reserved;
+        let len = (self.bytes.len() - body_start) as u64;
+        let (code, len_bytes) = container_len_code(len);
+        // 实际写入的大小所需的长度字节数与预留的不同，需要移动数据
+        if len_bytes != reserved {
+            if len_bytes > reserved {
+                self.try_extend_capity(len_bytes - reserved);
             }
+            let end = self.bytes.len();
+            let new_body_start = start + 1 + len_bytes;
+            move_part(&mut self.bytes, body_start..end, new_body_start);
+            self.bytes.truncate(new_body_start + len as usize);
+            self.tail = self.tail + len_bytes - reserved;
+        }
+        // 根据实际的大小，写入实际长度
+        debug_assert!(match len_bytes {
+            0 => len <= 64,
+            n => len >> (n * 8) == 0,
+        });
+        self.bytes[start] = code;
+        self.bytes[start + 1..start + 1 + len_bytes]
+            .copy_from_slice(&len.to_le_bytes()[..len_bytes]);
+    }
 
-            _ => {
-                panic!("container overflow");
+    // 记录顶层值的起始位置
+    #[inline]
+    fn mark_value(&mut self) {
+        if self.depth == 0 {
+            self.pad_value();
+            if let Some(offsets) = &mut self.offsets {
+                offsets.push(self.bytes.len());
+            }
+            // 顶层之前的值都已写完，不会再改变，可以参与哈希
+            if let Some((h, pos)) = self.hash {
+                self.hash = Some((fnv_update(h, &self.bytes[pos..]), self.bytes.len()));
             }
         }
     }
 
+    // 开启调试对齐时，在顶层值之前写入填充；已对齐时不写入
+    #[inline]
+    fn pad_value(&mut self) {
+        if self.depth == 0 && self.debug_align > 0 {
+            self.write_debug_padding();
+        }
+    }
+
+    // 写入填充容器，使下一个值对齐到debug_align
+    fn write_debug_padding(&mut self) {
+        let align = self.debug_align;
+        let mut gap = (align - self.bytes.len() % align) % align;
+        if gap == 0 {
+            return;
+        }
+        // 填充容器至少需要容器头1字节和类型4字节，且容器体不能恰好是一个Void，以免与write_sized的容器混淆
+        let (code, len_bytes) = loop {
+            let (code, len_bytes) = match gap {
+                0..=65 => (180 + gap.saturating_sub(1) as u8, 0),
+                66..=257 => (245, 1),
+                _ => (246, 2),
+            };
+            if gap >= 5 && gap - len_bytes - 5 != 1 {
+                break (code, len_bytes);
+            }
+            gap += align;
+        };
+        self.try_extend_capity(gap);
+        let start = self.bytes.len();
+        self.bytes.resize(start + gap, 0);
+        self.bytes[start] = code;
+        let body_len = (gap - 1 - len_bytes) as u64;
+        self.bytes[start + 1..start + 1 + len_bytes]
+            .copy_from_slice(&body_len.to_le_bytes()[..len_bytes]);
+        self.tail += gap;
+    }
+
     // 扩容
     fn extend_capity(&mut self, len: usize) {
         let old_capacity = self.bytes.capacity();
@@ -1117,6 +3108,16 @@ impl WriteBuffer {
         }
     }
 
+    // 写长度不超过64的字符串或二进制，类型与长度在同一个字节，只需一次扩容和拷贝
+    #[inline(always)]
+    fn write_tiny_data(&mut self, arr: &[u8], t: u8) {
+        let length = arr.len();
+        self.bytes.reserve(1 + length);
+        self.bytes.push(t + length as u8);
+        self.bytes.extend_from_slice(arr);
+        self.tail += 1 + length;
+    }
+
     //写字符串或二进制
     fn write_data(&mut self, arr: &[u8], t: u8) {
         let length = arr.len();
@@ -1307,51 +3308,97 @@ impl WriteBuffer {
         self.tail += 2;
     }
 
-    // 写2字节（小端）
+    // 写2字节
     #[inline]
     fn write_16(&mut self, v: u16, t: u8) {
         self.try_extend_capity(3);
         self.bytes.put_u8(t);
-        self.bytes.put_u16_le(v as u16);
+        self.put_payload_u16(v as u16);
         self.tail += 3;
     }
 
-    // 写4字节（小端）
+    // 写4字节
     #[inline]
     fn write_32(&mut self, v: u32, t: u8) {
         self.try_extend_capity(5);
         self.bytes.put_u8(t);
-        self.bytes.put_u32_le(v as u32);
+        self.put_payload_u32(v as u32);
         self.tail += 5;
     }
 
-    // 写6字节（小端）
+    // 写6字节
     #[inline]
     fn write_48(&mut self, v: u64, t: u8) {
         self.try_extend_capity(7);
         self.bytes.put_u8(t);
-        self.bytes.put_u16_le((v & 0xffff) as u16);
-        self.bytes.put_u32_le((v >> 16) as u32);
+        self.put_payload_u48(v);
         self.tail += 7;
     }
 
-    // 写8字节（小端）
+    // 写8字节
     #[inline]
     fn write_64(&mut self, v: u64, t: u8) {
         self.try_extend_capity(9);
         self.bytes.put_u8(t);
-        self.bytes.put_u64_le(v as u64);
+        self.put_payload_u64(v as u64);
         self.tail += 9;
     }
 
-    // 写16字节（小端）
+    // 写16字节
     #[inline]
     fn write_128(&mut self, v: u128, t: u8) {
         self.try_extend_capity(17);
         self.bytes.put_u8(t);
-        self.bytes.put_u128_le(v);
+        self.put_payload_u128(v);
         self.tail += 17;
     }
+
+    // 按字节序写数值，调用者需预留空间
+    #[inline]
+    fn put_payload_u16(&mut self, v: u16) {
+        match self.endian {
+            Endian::Little => self.bytes.put_u16_le(v),
+            Endian::Big => self.bytes.put_u16(v),
+        }
+    }
+
+    #[inline]
+    fn put_payload_u32(&mut self, v: u32) {
+        match self.endian {
+            Endian::Little => self.bytes.put_u32_le(v),
+            Endian::Big => self.bytes.put_u32(v),
+        }
+    }
+
+    #[inline]
+    fn put_payload_u48(&mut self, v: u64) {
+        match self.endian {
+            Endian::Little => {
+                self.bytes.put_u16_le((v & 0xffff) as u16);
+                self.bytes.put_u32_le((v >> 16) as u32);
+            }
+            Endian::Big => {
+                self.bytes.put_u32((v >> 16) as u32);
+                self.bytes.put_u16((v & 0xffff) as u16);
+            }
+        }
+    }
+
+    #[inline]
+    fn put_payload_u64(&mut self, v: u64) {
+        match self.endian {
+            Endian::Little => self.bytes.put_u64_le(v),
+            Endian::Big => self.bytes.put_u64(v),
+        }
+    }
+
+    #[inline]
+    fn put_payload_u128(&mut self, v: u128) {
+        match self.endian {
+            Endian::Little => self.bytes.put_u128_le(v),
+            Endian::Big => self.bytes.put_u128(v),
+        }
+    }
 }
 
 trait AsFrom<T> {
@@ -1580,6 +3627,114 @@ pub trait Decode: Sized {
     fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr>;
 }
 
+/// 将一个值序列化为WriteBuffer
+pub fn encode_to_buffer<T: Encode>(value: &T) -> WriteBuffer {
+    let mut bb = WriteBuffer::new();
+    value.encode(&mut bb);
+    bb
+}
+
+/// 将一个值序列化为二进制，同时返回二进制的哈希值（见WriteBuffer::finish_hash）
+pub fn encode_with_hash<T: Encode>(value: &T) -> (Vec<u8>, u64) {
+    let mut bb = WriteBuffer::new();
+    bb.set_streaming_hash(true);
+    value.encode(&mut bb);
+    let hash = bb.finish_hash();
+    (bb.unwrap(), hash)
+}
+
+/// 计算二进制的哈希值（FNV-1a 64）
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    fnv_update(FNV_OFFSET, bytes)
+}
+
+/// 计算二进制中的值的内容哈希，即其最短编码（见canonicalize）的哈希值，编码方式不同但内容相同的值哈希相同
+pub fn content_hash(bytes: &[u8]) -> Result<u64, ReadBonErr> {
+    Ok(hash_bytes(&canonicalize(bytes)?))
+}
+
+/// 内容寻址使用的哈希算法，见WriteBuffer::hash_with
+///
+/// 已为std的DefaultHasher实现；开启blake3、sha2特性后，为blake3::Hasher、sha2::Sha256、sha2::Sha512实现
+pub trait ContentHasher {
+    /// 追加数据
+    fn update(&mut self, bytes: &[u8]);
+    /// 计算哈希值
+    fn finish(self) -> Vec<u8>;
+}
+
+// 8字节哈希值，小端序；DefaultHasher的算法不保证跨版本稳定，不适合持久化
+impl ContentHasher for std::collections::hash_map::DefaultHasher {
+    fn update(&mut self, bytes: &[u8]) {
+        std::hash::Hasher::write(self, bytes);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        std::hash::Hasher::finish(&self).to_le_bytes().to_vec()
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl ContentHasher for blake3::Hasher {
+    fn update(&mut self, bytes: &[u8]) {
+        blake3::Hasher::update(self, bytes);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.finalize().as_bytes().to_vec()
+    }
+}
+
+#[cfg(feature = "sha2")]
+macro_rules! impl_sha2_hasher {
+    ($($t: ty),+) => {
+        $(
+            impl ContentHasher for $t {
+                fn update(&mut self, bytes: &[u8]) {
+                    sha2::Digest::update(self, bytes);
+                }
+
+                fn finish(self) -> Vec<u8> {
+                    sha2::Digest::finalize(self).to_vec()
+                }
+            }
+        )+
+    };
+}
+
+#[cfg(feature = "sha2")]
+impl_sha2_hasher!(sha2::Sha256, sha2::Sha512);
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+fn fnv_update(mut h: u64, bytes: &[u8]) -> u64 {
+    for b in bytes {
+        h ^= *b as u64;
+        h = h.wrapping_mul(FNV_PRIME);
+    }
+    h
+}
+
+/// 将一个值序列化为二进制
+pub fn encode_to_vec<T: Encode>(value: &T) -> Vec<u8> {
+    encode_to_buffer(value).unwrap()
+}
+
+/// 读取bytes开头的一帧（由WriteBuffer::finish_framed写入），返回只包含该帧消息的ReadBuffer，及下一帧的起始位置
+pub fn read_frame(bytes: &[u8]) -> Result<(ReadBuffer, usize), ReadBonErr> {
+    let end = 4 + read_uint_le(bytes, 0, 4)?;
+    if end > bytes.len() {
+        return Err(ReadBonErr::overflow(end, bytes.len()));
+    }
+    Ok((ReadBuffer::new(&bytes[4..end], 0), end))
+}
+
+/// 从二进制的开头反序列化一个值
+pub fn decode_one<T: Decode>(bytes: &[u8]) -> Result<T, ReadBonErr> {
+    T::decode(&mut ReadBuffer::new(bytes, 0))
+}
+
 impl Encode for u8 {
     fn encode(&self, bb: &mut WriteBuffer) {
         bb.write_u8(self.clone());
@@ -1760,12 +3915,59 @@ impl Decode for isize {
     }
 }
 
+// EnumValue按write_value的规则（最短编码）写入，读时与ReadBuffer::read相同
+impl Encode for EnumValue {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        bb.write_value(self);
+    }
+}
+
+impl Decode for EnumValue {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        bb.read()
+    }
+}
+
+// char写为其码点（u32），读时检查是否为合法的unicode标量值
+impl Encode for char {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        bb.write_u32(*self as u32);
+    }
+}
+
+impl Decode for char {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        let v = bb.read_u32()?;
+        char::from_u32(v).ok_or_else(|| ReadBonErr::other(format!("invalid char {:#x}", v)))
+    }
+}
+
+// Duration依次写入秒数（u64）和不足1秒的纳秒数（u32）
+impl Encode for Duration {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        bb.write_u64(self.as_secs());
+        bb.write_u32(self.subsec_nanos());
+    }
+}
+
+impl Decode for Duration {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        let secs = bb.read_u64()?;
+        let nanos = bb.read_u32()?;
+        if nanos >= 1_000_000_000 {
+            return Err(ReadBonErr::other(format!("invalid subsec nanos {}", nanos)));
+        }
+        Ok(Duration::new(secs, nanos))
+    }
+}
+
 impl Encode for String {
     fn encode(&self, bb: &mut WriteBuffer) {
         bb.write_utf8(self);
     }
 }
 
+// 为了兼容已有的数据，使用宽松的read_utf8；需要严格校验时，直接调用ReadBuffer::read_utf8_strict
 impl Decode for String {
     fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
         bb.read_utf8()
@@ -1788,13 +3990,17 @@ impl<K: Decode + Eq + Hash, V: Decode> Decode for HashMap<K, V> {
         let mut map = HashMap::new();
         let count = usize::decode(bb)?;
         for _ in 0..count {
-            map.insert(K::decode(bb)?, V::decode(bb)?);
+            bb.charge_alloc(std::mem::size_of::<(K, V)>())?;
+            let dup = map.insert(K::decode(bb)?, V::decode(bb)?).is_some();
+            if dup && bb.is_strict() {
+                return Err(ReadBonErr::other("duplicate map key".to_string()));
+            }
         }
         Ok(map)
     }
 }
 
-impl<T: Encode> Encode for Vec<T> {
+impl<T: Encode + Eq + Hash> Encode for HashSet<T> {
     fn encode(&self, bb: &mut WriteBuffer) {
         self.len().encode(bb);
         for v in self.iter() {
@@ -1803,14 +4009,61 @@ impl<T: Encode> Encode for Vec<T> {
     }
 }
 
-impl<T: Decode> Decode for Vec<T> {
+// 与HashMap相同，重复的元素默认被合并，严格模式下返回Err
+impl<T: Decode + Eq + Hash> Decode for HashSet<T> {
     fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        let mut set = HashSet::new();
         let count = usize::decode(bb)?;
-        let mut vec = Vec::new();
         for _ in 0..count {
-            vec.push(T::decode(bb)?);
+            bb.charge_alloc(std::mem::size_of::<T>())?;
+            let dup = !set.insert(T::decode(bb)?);
+            if dup && bb.is_strict() {
+                return Err(ReadBonErr::other("duplicate set element".to_string()));
+            }
         }
-        Ok(vec)
+        Ok(set)
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        self.as_slice().encode(bb);
+    }
+}
+
+// 按引用依次写入元素，与Vec<T>的格式相同，可解码为Vec<T>
+impl<T: Encode> Encode for &[T] {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        self.len().encode(bb);
+        for v in self.iter() {
+            v.encode(bb);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        bb.read_collection()
+    }
+}
+
+// 定长数组与Vec<T>的格式相同（先写元素数量，再依次写入元素），可与Vec<T>互相解码；解码时元素数量必须等于N
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        self.as_slice().encode(bb);
+    }
+}
+
+impl<T: Decode, const N: usize> Decode for [T; N] {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        let v: Vec<T> = bb.read_collection()?;
+        let len = v.len();
+        v.try_into().map_err(|_| {
+            ReadBonErr::other(format!(
+                "array length mismatch, expect {}, but it's {}",
+                N, len
+            ))
+        })
     }
 }
 
@@ -1836,209 +4089,477 @@ impl<T: Decode> Decode for Option<T> {
     }
 }
 
-
-pub fn partial_cmp<'a>(b1: &mut ReadBuffer<'a>, b2: &mut ReadBuffer<'a>) -> Option<Ordering> {
-    let err = "partial_cmp err";
-    let t1 = b1.get_type_chunk().expect(err);
-    let t2 = b2.get_type_chunk().expect(err);
-    // println!("partial_cmp  b1:{:?}, b2:{:?}", b1.bytes, b2.bytes);
-    // println!(
-    //     "###########################t1:{:?}, t2:{:?}, b1.head:{}, b2.head:{}, b1:{:?}, b2:{:?}",
-    //     t1, t2, b1.head, b2.head, &b1, &b2
-    // );
-    match (t1, t2) {
-        (3..8, 3..42) => {
-            // b1是浮点数， b2是数字,需要读取比较对象的值进行比较
-            let v1 = match t1 < 7 {
-                true => b1.read_f32().expect(err) as f64,
-                false => b1.read_f64().expect(err),
-            };
-            compare_number(b2, v1, t2)
-        }
-        (3..8, 0..3) => {
-            // b1是浮点数， b2是非数字， 并且b1的类型值大于b2的类型值，则认为b1更大
-            let len1 = base_type_len(b1, t1);
-            b1.head += len1;
-            b2.head += 1;
-            b1.bytes.advance(len1);
-            b2.bytes.advance(1);
-            Some(Ordering::Greater)
-        }
-        (3..8, _) => {
-            // b1是浮点数， b2是非数字， 并且b1的类型值小于b2的类型值，则认为b1更小
-            let len1 = base_type_len(b1, t1);
-            b1.head += len1;
-            b1.bytes.advance(len1);
-            let len2 = base_type_len(b2, t2);
-            b2.head += len2;
-            b2.bytes.advance(len2);
-            Some(Ordering::Less)
-        }
-        (9..42, 3..8) => {
-            // b1是整数， b2是浮点数，需要读取比较对象的值进行比较
-            let v2 = match t2 < 7 {
-                true => b2.read_f32().expect(err) as f64,
-                false => b2.read_f64().expect(err),
-            };
-            match compare_number(b1, v2, t1) {
-                Some(Ordering::Less) => Some(Ordering::Greater),
-                Some(Ordering::Greater) => Some(Ordering::Less),
-                Some(Ordering::Equal) => Some(Ordering::Equal),
-                None => None,
+// Result先写一个u8表示分支（0为Ok，1为Err），再写分支内的值
+impl<T: Encode, E: Encode> Encode for Result<T, E> {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        match self {
+            Ok(v) => {
+                bb.write_u8(0);
+                v.encode(bb);
+            }
+            Err(e) => {
+                bb.write_u8(1);
+                e.encode(bb);
             }
         }
-        (9..42, 9..42) => {
-            // b1是整数, b2是整数
-            if t1 > t2 {
-                //同是整数， 类型较大的，值也较大
-                let len1 = base_type_len(b1, t1);
-                let len2 = base_type_len(b2, t2);
-                b1.bytes.advance(len1);
-                b2.bytes.advance(len2);
+    }
+}
+
+impl<T: Decode, E: Decode> Decode for Result<T, E> {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        match bb.read_u8()? {
+            0 => Ok(Ok(T::decode(bb)?)),
+            1 => Ok(Err(E::decode(bb)?)),
+            v => Err(ReadBonErr::other(format!("unknown result discriminant {}", v))),
+        }
+    }
+}
+
+// Infallible没有值，实现Encode、Decode只为满足Result<T, Infallible>等的约束
+impl Encode for Infallible {
+    fn encode(&self, _bb: &mut WriteBuffer) {
+        match *self {}
+    }
+}
+
+impl Decode for Infallible {
+    fn decode(_bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        Err(ReadBonErr::other("can not decode Infallible".to_string()))
+    }
+}
+
+impl<T: Encode + Copy> Encode for Cell<T> {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        self.get().encode(bb);
+    }
+}
+
+impl<T: Decode + Copy> Decode for Cell<T> {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        Ok(Cell::new(T::decode(bb)?))
+    }
+}
+
+impl<T: Encode> Encode for RefCell<T> {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        self.borrow().encode(bb);
+    }
+}
+
+impl<T: Decode> Decode for RefCell<T> {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        Ok(RefCell::new(T::decode(bb)?))
+    }
+}
+
+// 智能指针直接写入内部的值，不增加任何标记
+impl<T: Encode + ?Sized> Encode for Box<T> {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        (**self).encode(bb);
+    }
+}
+
+impl<T: Decode> Decode for Box<T> {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        Ok(Box::new(T::decode(bb)?))
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for Arc<T> {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        (**self).encode(bb);
+    }
+}
+
+impl<T: Decode> Decode for Arc<T> {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        Ok(Arc::new(T::decode(bb)?))
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for Rc<T> {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        (**self).encode(bb);
+    }
+}
+
+impl<T: Decode> Decode for Rc<T> {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        Ok(Rc::new(T::decode(bb)?))
+    }
+}
+
+// 加锁后写入内部的值，与直接写入内部的值相同；
+// 锁已中毒（持锁的线程panic）时仍写入其中的数据，与PoisonError::into_inner相同，写入只读取数据，不会扩散不一致
+impl<T: Encode + ?Sized> Encode for Mutex<T> {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        self.lock().unwrap_or_else(|e| e.into_inner()).encode(bb);
+    }
+}
+
+// 读出内部的值，构造新的锁（不会中毒）
+impl<T: Decode> Decode for Mutex<T> {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        Ok(Mutex::new(T::decode(bb)?))
+    }
+}
+
+// 加读锁后写入内部的值，中毒的处理与Mutex相同
+impl<T: Encode + ?Sized> Encode for RwLock<T> {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        self.read().unwrap_or_else(|e| e.into_inner()).encode(bb);
+    }
+}
+
+impl<T: Decode> Decode for RwLock<T> {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        Ok(RwLock::new(T::decode(bb)?))
+    }
+}
+
+// 元组不使用容器包装，按顺序依次写入各元素，与依次调用各元素的encode相同
+macro_rules! impl_tuple {
+    ($($t: ident),+) => {
+        impl<$($t: Encode),+> Encode for ($($t,)+) {
+            #[allow(non_snake_case)]
+            fn encode(&self, bb: &mut WriteBuffer) {
+                let ($($t,)+) = self;
+                $($t.encode(bb);)+
+            }
+        }
+
+        impl<$($t: Decode),+> Decode for ($($t,)+) {
+            fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+                Ok(($($t::decode(bb)?,)+))
+            }
+        }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
+impl_tuple!(A, B, C, D, E, F, G);
+impl_tuple!(A, B, C, D, E, F, G, H);
+impl_tuple!(A, B, C, D, E, F, G, H, I);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J, K);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+/// 可以用一个u64表示的类型（如包装了基础类型的新类型），配合impl_bon_primitive使用
+pub trait IntoBon {
+    fn to_bon_primitive(&self) -> u64;
+}
+
+/// 可以从一个u64构造的类型，配合impl_bon_primitive使用
+pub trait FromBon: Sized {
+    fn from_bon_primitive(v: u64) -> Self;
+}
+
+/// 为实现了IntoBon、FromBon的类型实现Encode、Decode，序列化结果与一个u64相同
+///
+/// ```
+/// struct Millis(u64);
+/// impl pi_bon::IntoBon for Millis {
+///     fn to_bon_primitive(&self) -> u64 { self.0 }
+/// }
+/// impl pi_bon::FromBon for Millis {
+///     fn from_bon_primitive(v: u64) -> Self { Millis(v) }
+/// }
+/// pi_bon::impl_bon_primitive!(Millis);
+/// ```
+#[macro_export]
+macro_rules! impl_bon_primitive {
+    ($name: ty) => {
+        impl $crate::Encode for $name {
+            fn encode(&self, bb: &mut $crate::WriteBuffer) {
+                bb.write_u64($crate::IntoBon::to_bon_primitive(self));
+            }
+        }
+
+        impl $crate::Decode for $name {
+            fn decode(bb: &mut $crate::ReadBuffer) -> Result<Self, $crate::ReadBonErr> {
+                Ok(<$name as $crate::FromBon>::from_bon_primitive(bb.read_u64()?))
+            }
+        }
+    };
+}
+
+/// 为无负载的枚举（C风格枚举）实现Encode、Decode，只序列化判别值，不使用容器
+///
+/// 开启derive特性时，也可以用#[derive(Encode, Decode)]加#[bon(repr = "int")]实现，编码相同
+///
+/// 判别值通过write_u32写入，小于20的判别值只占一个字节；读时遇到未知的判别值，返回Err
+///
+/// 写入的是变体声明的判别值（如`A = 10`），而不是变体的序号，因此调整变体的顺序不会改变序列化结果
+///
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// enum Color { Red, Green = 5, Blue }
+/// pi_bon::impl_int_enum!(Color { Red, Green, Blue });
+/// ```
+#[macro_export]
+macro_rules! impl_int_enum {
+    ($name: ident { $($variant: ident),+ $(,)? }) => {
+        impl $crate::Encode for $name {
+            fn encode(&self, bb: &mut $crate::WriteBuffer) {
+                match self {
+                    $($name::$variant => bb.write_u32($name::$variant as u32),)+
+                }
+            }
+        }
+
+        impl $crate::Decode for $name {
+            fn decode(bb: &mut $crate::ReadBuffer) -> Result<Self, $crate::ReadBonErr> {
+                let v = bb.read_u32()?;
+                $(if v == $name::$variant as u32 {
+                    return Ok($name::$variant);
+                })+
+                Err($crate::ReadBonErr::Other(format!(
+                    "unknown discriminant {} for {}",
+                    v,
+                    stringify!($name)
+                )))
+            }
+        }
+    };
+}
+
+
+/// 为带数据的枚举实现Encode、Decode
+///
+/// 先通过write_u32写入变体的序号（从0开始），再依次写入变体的字段；单元变体只写序号，不写其他任何数据。
+/// 支持单元变体、只有一个字段的元组变体和具名字段的结构体变体。
+///
+/// ```
+/// #[derive(Debug, PartialEq)]
+/// enum Shape { Empty, Circle(f32), Rect { w: f32, h: f32 } }
+/// pi_bon::impl_enum!(Shape { Empty, Circle(f32), Rect { w: f32, h: f32 } });
+/// ```
+#[macro_export]
+macro_rules! impl_enum {
+    ($name: ident { $($body: tt)* }) => {
+        $crate::impl_enum!(@munch bb, $name, 0u32, [], [], $($body)*);
+    };
+    (@munch $bb: ident, $name: ident, $idx: expr, [$($enc: tt)*], [$($dec: tt)*],
+        $variant: ident ( $t: ty ) $(, $($rest: tt)*)?) => {
+        $crate::impl_enum!(@munch $bb, $name, $idx + 1,
+            [$($enc)* $name::$variant(f) => {
+                $bb.write_u32($idx);
+                $crate::Encode::encode(f, $bb);
+            }],
+            [$($dec)* i if i == $idx => Ok($name::$variant(<$t as $crate::Decode>::decode($bb)?)),],
+            $($($rest)*)?);
+    };
+    (@munch $bb: ident, $name: ident, $idx: expr, [$($enc: tt)*], [$($dec: tt)*],
+        $variant: ident { $($field: ident : $ft: ty),* $(,)? } $(, $($rest: tt)*)?) => {
+        $crate::impl_enum!(@munch $bb, $name, $idx + 1,
+            [$($enc)* $name::$variant { $($field),* } => {
+                $bb.write_u32($idx);
+                $($crate::Encode::encode($field, $bb);)*
+            }],
+            [$($dec)* i if i == $idx => Ok($name::$variant {
+                $($field: <$ft as $crate::Decode>::decode($bb)?),*
+            }),],
+            $($($rest)*)?);
+    };
+    (@munch $bb: ident, $name: ident, $idx: expr, [$($enc: tt)*], [$($dec: tt)*],
+        $variant: ident $(, $($rest: tt)*)?) => {
+        $crate::impl_enum!(@munch $bb, $name, $idx + 1,
+            [$($enc)* $name::$variant => $bb.write_u32($idx),],
+            [$($dec)* i if i == $idx => Ok($name::$variant),],
+            $($($rest)*)?);
+    };
+    (@munch $bb: ident, $name: ident, $idx: expr, [$($enc: tt)*], [$($dec: tt)*],) => {
+        impl $crate::Encode for $name {
+            fn encode(&self, $bb: &mut $crate::WriteBuffer) {
+                match self {
+                    $($enc)*
+                }
+            }
+        }
+
+        impl $crate::Decode for $name {
+            fn decode($bb: &mut $crate::ReadBuffer) -> Result<Self, $crate::ReadBonErr> {
+                match $bb.read_u32()? {
+                    $($dec)*
+                    i => Err($crate::ReadBonErr::Other(format!(
+                        "unknown variant {} for {}",
+                        i,
+                        stringify!($name)
+                    ))),
+                }
+            }
+        }
+    };
+}
 
-                b1.head += len1;
-                b2.head += len2;
+/// 按协议比较b1、b2当前的值，并将读指针移到下一个值，无法比较时返回None，需要知道原因时使用try_partial_cmp
+pub fn partial_cmp<'a>(b1: &mut ReadBuffer<'a>, b2: &mut ReadBuffer<'a>) -> Option<Ordering> {
+    try_partial_cmp(b1, b2).ok()
+}
 
-                return Some(Ordering::Greater);
+/// 与partial_cmp相同，但无法比较时返回具体的原因：值不完整、类型值无效、128位浮点数、NaN等
+pub fn try_partial_cmp<'a>(
+    b1: &mut ReadBuffer<'a>,
+    b2: &mut ReadBuffer<'a>,
+) -> Result<Ordering, ReadBonErr> {
+    let t1 = b1.get_type_chunk()?;
+    let t2 = b2.get_type_chunk()?;
+    // 值不完整或类型值无效时无法比较，之后在值的范围内读取都不会越界
+    b1.probe_value()?;
+    b2.probe_value()?;
+    // println!("partial_cmp  b1:{:?}, b2:{:?}", b1.bytes, b2.bytes);
+    // println!(
+    //     "###########################t1:{:?}, t2:{:?}, b1.head:{}, b2.head:{}, b1:{:?}, b2:{:?}",
+    //     t1, t2, b1.head, b2.head, &b1, &b2
+    // );
+    // 128位浮点数暂不支持，无法比较
+    if t1 == 8 || t2 == 8 {
+        return Err(ReadBonErr::other(
+            "128 bit float is unsupported to compare".to_string(),
+        ));
+    }
+    match (t1, t2) {
+        (3..=7, 3..=41) => {
+            // b1是浮点数， b2是数字,需要读取比较对象的值进行比较
+            let v1 = match t1 < 7 {
+                true => b1.read_f32()? as f64,
+                false => b1.read_f64()?,
+            };
+            compare_number(b2, v1, t2)
+        }
+        (3..=7, 0..=2) => {
+            // b1是浮点数， b2是非数字， 并且b1的类型值大于b2的类型值，则认为b1更大
+            b1.read_raw()?;
+            b2.read_raw()?;
+            Ok(Ordering::Greater)
+        }
+        (3..=7, _) => {
+            // b1是浮点数， b2是非数字， 并且b1的类型值小于b2的类型值，则认为b1更小
+            b1.read_raw()?;
+            b2.read_raw()?;
+            Ok(Ordering::Less)
+        }
+        (9..=41, 3..=7) => {
+            // b1是整数， b2是浮点数，需要读取比较对象的值进行比较
+            let v2 = match t2 < 7 {
+                true => b2.read_f32()? as f64,
+                false => b2.read_f64()?,
+            };
+            compare_number(b1, v2, t1).map(Ordering::reverse)
+        }
+        (9..=41, 9..=41) => {
+            // b1是整数, b2是整数
+            if t1 > t2 {
+                //同是整数， 类型较大的，值也较大
+                b1.read_raw()?;
+                b2.read_raw()?;
+                return Ok(Ordering::Greater);
             } else if t1 < t2 {
                 //同是整数， 类型较小的，值也较小
-                let len1 = base_type_len(b1, t1);
-                let len2 = base_type_len(b2, t2);
-                b1.bytes.advance(len1);
-                b2.bytes.advance(len2);
-
-                b1.head += len1;
-                b2.head += len2;
-                return Some(Ordering::Less);
+                b1.read_raw()?;
+                b2.read_raw()?;
+                return Ok(Ordering::Less);
             } else if t1 > 14 && t1 < 36 {
                 //同是整数且类型相等， 当类型值在15~35之间时，其表示的数值大小是确定的（-1~19）， 因此， b1与b2相等
-                let len1 = base_type_len(b1, t1);
-                let len2 = base_type_len(b2, t2);
-                b1.bytes.advance(len1);
-                b2.bytes.advance(len2);
-
-                b1.head += len1;
-                b2.head += len2;
-                return Some(Ordering::Equal);
+                b1.read_raw()?;
+                b2.read_raw()?;
+                return Ok(Ordering::Equal);
             } else {
                 //同是整数且类型相等，但并不是常用数字（-1~19），需要读值进行比较
                 return compare_int(b1, b2, t1);
             }
         }
-        (9..42, 0..3) => {
+        (9..=41, 0..=2) => {
             //b1是整数， b2是非数字，并且b1的类型值更大，则b1更大
-            let len1 = base_type_len(b1, t1);
-            b1.bytes.advance(len1);
-            b2.bytes.advance(1);
-
-            b1.head += len1;
-            b2.head += 1;
-            Some(Ordering::Greater)
+            b1.read_raw()?;
+            b2.read_raw()?;
+            Ok(Ordering::Greater)
         }
-        (9..42, _) => {
+        (9..=41, _) => {
             //b1是整数， b2是非数字，并且b1的类型值更小，则b1更小
-            let len1 = base_type_len(b1, t1);
-            let len2 = base_type_len(b2, t2);
-            b1.bytes.advance(len1);
-            b2.bytes.advance(len2);
-            b1.head += len1;
-            b2.head += len2;
-            Some(Ordering::Less)
+            b1.read_raw()?;
+            b2.read_raw()?;
+            Ok(Ordering::Less)
         }
-        (0..3, _) => {
+        (0..=2, _) => {
             //b1是null, true或false， 理论上除了与自身相等， 无法与其他类型的值进行比较， 规定其大小与其类型值保持一致
-            b1.head += 1;
-            b1.bytes.advance(1);
-            let len2 = base_type_len(b2, t2);
-            b2.head += len2;
-            b2.bytes.advance(len2);
+            b1.read_raw()?;
+            b2.read_raw()?;
             if t2 > t1 {
                 //t1小于3， t2大于t1,
-                return Some(Ordering::Less);
+                return Ok(Ordering::Less);
             } else if t2 < t1 {
-                return Some(Ordering::Greater);
+                return Ok(Ordering::Greater);
             } else {
-                return Some(Ordering::Equal);
+                return Ok(Ordering::Equal);
             }
         }
-        (42..111, _) => {
+        (42..=110, _) => {
             //b1是字符串
             if t2 > 110 {
                 //b1是字符串， b2是非字符串，且b1的类型值更小， 则b1更小
-                let len1 = base_type_len(b1, t1);
-                let len2 = base_type_len(b2, t2);
-                b1.bytes.advance(len1);
-                b2.bytes.advance(len2);
-
-                b1.head += len1;
-                b2.head += len2;
-                return Some(Ordering::Less);
+                b1.read_raw()?;
+                b2.read_raw()?;
+                return Ok(Ordering::Less);
             } else if t2 < 42 {
                 //b1是字符串， b2是非字符串，且b1的类型值更大， 则b1更大
-                let len1 = base_type_len(b1, t1);
-                let len2 = base_type_len(b2, t2);
-                b1.bytes.advance(len1);
-                b2.bytes.advance(len2);
-
-                b1.head += len1;
-                b2.head += len2;
-                return Some(Ordering::Greater);
+                b1.read_raw()?;
+                b2.read_raw()?;
+                return Ok(Ordering::Greater);
             } else {
                 //b1是字符串， b2也是字符串，需要读值比较字符串的二进制数据的大小
                 return compare_str(b1, b2);
             }
         }
-        (111..180, _) => {
+        (111..=179, _) => {
             // b1是二进制
             if t2 > 179 {
                 // b1是二进制， b2是非二进制，且b1的类型值更小， 则b1更小
-                let len1 = base_type_len(b1, t1);
-                let len2 = base_type_len(b2, t2);
-                b1.bytes.advance(len1);
-                b2.bytes.advance(len2);
-
-                b1.head += len1;
-                b2.head += len2;
-                return Some(Ordering::Less);
+                b1.read_raw()?;
+                b2.read_raw()?;
+                return Ok(Ordering::Less);
             } else if t2 < 111 {
                 // b1是二进制， b2是非二进制，且b1的类型值更大， 则b1更大
-                let len1 = base_type_len(b1, t1);
-                let len2 = base_type_len(b2, t2);
-                b1.bytes.advance(len1);
-                b2.bytes.advance(len2);
-
-                b1.head += len1;
-                b2.head += len2;
-                return Some(Ordering::Greater);
+                b1.read_raw()?;
+                b2.read_raw()?;
+                return Ok(Ordering::Greater);
             } else {
                 // b1是二进制， b2也是二进制，需要读值比二进制数据的大小
                 return compare_bin(b1, b2);
             }
         }
         (_, 0) => {
-            return Some(Ordering::Greater);
+            return Ok(Ordering::Greater);
+        }
+        (249 | 250, 180..=248) => {
+            // 大整数与容器，按类型值比较
+            b1.read_raw()?;
+            b2.read_raw()?;
+            Ok(Ordering::Greater)
+        }
+        (180..=248, 249 | 250) => {
+            b1.read_raw()?;
+            b2.read_raw()?;
+            Ok(Ordering::Less)
         }
-        (249, 249) => {
-            // b1.head += 32;
-            // b2.head += 32;
-            // b1.bytes[b1.head - 32..b1.head].partial_cmp(&b2.bytes[b2.head - 32..b2.head])
-            // BigInt::from_bytes_le(Sign::Plus, &b1.bytes[b1.head - 32..b1.head]);
+        (249 | 250, 249 | 250) => {
+            // 249为正数，250为负数，按带符号的值比较
             let b1n = to_bigint(b1);
             let b2n = to_bigint(b2);
-            return b1n.partial_cmp(&b2n);
+            return Ok(b1n.cmp(&b2n));
         }
         _ => {
             // b1是容器， b2也是二进制，需要读值比二进制数据的大小
             if t2 < 180 {
                 // b1是容器， b2是非容器，b1的类型值更大， 则b1更大
-                let len1 = base_type_len(b1, t1);
-                let len2 = base_type_len(b2, t2);
-                b1.bytes.advance(len1);
-                b2.bytes.advance(len2);
-
-                b1.head += len1;
-                b2.head += len2;
-                return Some(Ordering::Greater);
+                b1.read_raw()?;
+                b2.read_raw()?;
+                return Ok(Ordering::Greater);
             } else {
                 // b1是容器， b2也是容器，需要读值比容器二进制数据的大小
                 return compare_contain(b1, b2);
@@ -2047,298 +4568,764 @@ pub fn partial_cmp<'a>(b1: &mut ReadBuffer<'a>, b2: &mut ReadBuffer<'a>) -> Opti
     }
 }
 
-fn to_bigint<'a>(bb: &mut ReadBuffer<'a>) -> BigInt {
-    let mut base = BigInt::from(1);
-    let mut n = BigInt::from(0);
-    let mut c = BigInt::from(4294967296 as i64);
-    bb.head += 1;
-    bb.bytes.advance(1);
-    for _ in 0..=7 {
-        bb.head += 4;
-        // n += BigInt::from_bytes_le(Sign::Plus, &bb.bytes[bb.head - 4..bb.head]);
-        n += BigInt::from_bytes_le(Sign::Plus, &bb.bytes.copy_to_bytes(4));
-        base *= &c;
+/// 带误差的比较，浮点数与浮点数、浮点数与整数比较时，差值的绝对值不大于epsilon则认为相等，其他情况与partial_cmp一致
+///
+/// **警告**：该比较不满足传递性（a≈b、b≈c，并不能推出a≈c），因此不能用于排序、BTreeMap的键等依赖全序的场合，
+/// ReadBuffer、WriteBuffer的Ord实现也不会使用它。
+pub fn partial_cmp_with_epsilon<'a>(
+    b1: &mut ReadBuffer<'a>,
+    b2: &mut ReadBuffer<'a>,
+    epsilon: f64,
+) -> Option<Ordering> {
+    let t1 = b1.get_type_chunk().ok()?;
+    let t2 = b2.get_type_chunk().ok()?;
+    if t1 == 8 || t2 == 8 {
+        return None;
+    }
+    match (t1, t2) {
+        (3..=7, 3..=7) | (3..=7, 9..=41) | (9..=41, 3..=7) => {
+            let v1 = read_as_f64(b1, t1)?;
+            let v2 = read_as_f64(b2, t2)?;
+            if (v1 - v2).abs() <= epsilon {
+                Some(Ordering::Equal)
+            } else {
+                v1.partial_cmp(&v2)
+            }
+        }
+        _ => partial_cmp(b1, b2),
     }
-    n
 }
 
-pub fn base_type_len(bb: &mut ReadBuffer, t: u8) -> usize {
-    match t {
-        0..5 | 15..36 => 1,
-        5 => {
-            panic!("16 bit floating-point number temporarily unsupported");
-        }
-        6 => 5,
-        7 => 9,
-        8 => {
-            panic!("128 bit floating-point number temporarily unsupported");
-        }
-        9 | 36 => 2,
-        10 | 37 => 3,
-        11 | 38 => 5,
-        12 | 39 => 7,
-        13 | 40 => 9,
-        14 | 41 => 17,
-        42..107 => (t - 42) as usize + 1,
-        111..176 => (t - 111) as usize + 1,
-        107 | 176 => {
-            bb.bytes.advance(1);
-            bb.bytes.get_u8() as usize
-        }
-        108 | 177 => {
-            bb.bytes.advance(1);
-            bb.bytes.get_u16_le() as usize
-        }
-        109 | 178 => {
-            bb.bytes.advance(1);
-            bb.bytes.get_u32_le() as usize
-        }
-        110 | 179 => {
-            bb.bytes.advance(1);
-            bb.bytes.get_u32_le() as usize + 2
-        }
-        249 | 250 => 32,
-        _ => {
-            panic!("other type TODO base_type_len type:{:?}", t);
+/// 检查二进制是否由完整的值依次排列而成，并递归检查每个容器的元素是否恰好填满容器体，
+/// 容器的声明长度大于元素实际占用的长度时（尾部有填充，可能藏有数据），返回Err
+pub fn validate(bytes: &[u8]) -> Result<(), ReadBonErr> {
+    let mut r = ReadBuffer::new(bytes, 0);
+    r.set_strict(true);
+    while !r.bytes.is_empty() {
+        match r.get_type_chunk()? {
+            180..=248 => {
+                let mut c = r.read_container_scoped()?;
+                while let Some(elem) = c.next_element() {
+                    validate(elem?.bytes)?;
+                }
+                c.finish();
+            }
+            _ => {
+                r.read_raw()?;
+            }
         }
     }
+    Ok(())
 }
 
-fn compare_number<'a>(rb: &mut ReadBuffer<'a>, v1: f64, t2: u8) -> Option<Ordering> {
-    let err = "compare_number err";
-    let v2 = match t2 {
-        3..8 => rb.read_f64().expect(err),
-        9..14 => rb.read_i64().expect(err) as f64,
-        14 => {
-            rb.head += 17;
-            rb.bytes.advance(17);
-            return Some(Ordering::Greater);
-        }
-        15 => {
-            rb.head += 1;
-            rb.bytes.advance(1);
-            -1.0
-        }
-        16..41 => rb.read_u64().expect(err) as f64,
-        41 => {
-            rb.head += 17;
-            rb.bytes.advance(17);
-            return Some(Ordering::Less);
-        }
-        _ => panic!("t2 is not number:{}", t2),
-    };
-    if v1.is_nan() {
-        if v2.is_nan() {
-            return Some(Ordering::Equal);
-        } else {
-            return Some(Ordering::Less);
-        }
+/// 检查二进制是否恰好是一个完整的值（标量或容器），值不完整时返回Err，之后还有其它数据时返回false
+///
+/// 只检查最外层的值的长度，不检查容器内部，需要时配合validate使用
+pub fn is_single_value(bytes: &[u8]) -> Result<bool, ReadBonErr> {
+    let len = value_len(bytes)?;
+    if len > bytes.len() {
+        return Err(ReadBonErr::overflow(len, bytes.len()));
     }
-    v1.partial_cmp(&v2)
+    Ok(len == bytes.len())
 }
 
-fn compare_int<'a>(rb1: &mut ReadBuffer<'a>, rb2: &mut ReadBuffer<'a>, t: u8) -> Option<Ordering> {
-    let err = "compare_int";
-    match t {
-        9..14 => rb1
-            .read_i64()
-            .expect(err)
-            .partial_cmp(&rb2.read_i64().expect(err)),
-        14 => rb1
-            .read_i128()
-            .expect(err)
-            .partial_cmp(&rb2.read_i128().expect(err)),
-        36..41 => rb1
-            .read_u64()
-            .expect(err)
-            .partial_cmp(&rb2.read_u64().expect(err)),
-        41 => rb1
-            .read_u128()
-            .expect(err)
-            .partial_cmp(&rb2.read_u128().expect(err)),
-        _ => panic!("t is not int:{}", t),
-    }
-}
-
-fn compare_str<'a>(rb1: &mut ReadBuffer<'a>, rb2: &mut ReadBuffer<'a>) -> Option<Ordering> {
-    rb1.head += 1;
-    rb2.head += 1;
-    let t1 = rb1.get_type().unwrap();
-    let t2 = rb2.get_type().unwrap();
-    let len1 = match t1 {
-        42..107 => (t1 - 42) as usize,
-        107 => {
-            rb1.head += 1;
-            rb1.bytes.get_u8() as usize
-        }
-        108 => {
-            rb1.head += 2;
-            rb1.bytes.get_u16_le() as usize
-        }
-        109 => {
-            rb1.head += 4;
-            rb1.bytes.get_u32_le() as usize
-        }
-        110 => {
-            rb1.head += 6;
-            rb1.bytes.get_u16_le() as usize + (rb1.bytes.get_u32_le() * 0x10000) as usize
-        }
-        _ => {
-            panic!("t1 is not str:{}", t1);
-        }
+// 检查容器体中的元素是否恰好填满容器体，通用对象、数组、map按元素数量检查，其它容器要求容器体由完整的值组成
+fn check_container_padding(
+    type_id: u32,
+    count: Option<u32>,
+    mut body: &[u8],
+) -> Result<(), ReadBonErr> {
+    let padding = || ReadBonErr::other("container has trailing padding".to_string());
+    let count = match count {
+        Some(count) => count,
+        None => return count_values(body).map(|_| ()).ok_or_else(padding),
     };
+    let expected = match type_id {
+        3 => count as usize * 2,
+        _ => count as usize,
+    };
+    for _ in 0..expected {
+        let len = value_len(body)?;
+        body = body
+            .get(len..)
+            .ok_or(ReadBonErr::overflow(len, body.len()))?;
+    }
+    match body.is_empty() {
+        true => Ok(()),
+        false => Err(padding()),
+    }
+}
 
-    let len2 = match t2 {
-        42..107 => (t2 - 42) as usize,
-        107 => {
-            rb2.head += 1;
-            rb2.bytes.get_u8() as usize
+/// 将二进制中的所有值改写为最短的编码（规则见WriteBuffer::write_value），通用map的键值对按键的二进制排序
+///
+/// 与读出为EnumValue再调用to_canonical_bytes的结果相同；BigInt、小map不作改写
+pub fn canonicalize(bytes: &[u8]) -> Result<Vec<u8>, ReadBonErr> {
+    canonical_values(ReadBuffer::new(bytes, 0))
+}
+
+// 将r中剩余的所有值改写为最短的编码，数值按r的字节序读取，输出总是小端
+fn canonical_values(mut r: ReadBuffer) -> Result<Vec<u8>, ReadBonErr> {
+    let mut w = WriteBuffer::new();
+    while !r.bytes.is_empty() {
+        canonical_value(&mut r, &mut w)?;
+    }
+    Ok(w.unwrap())
+}
+
+// 由已是最短编码的元素写出容器，map的键值对按键的二进制排序
+fn write_canonical_container(
+    w: &mut WriteBuffer,
+    type_id: u32,
+    count: Option<u32>,
+    mut elems: Vec<Vec<u8>>,
+) -> Result<(), ReadBonErr> {
+    // 元素数量来自数据，与实际读出的元素不符时返回Err（write_container_counted只对调用者写入的容器体做断言）
+    if let Some(count) = count {
+        let expected = match type_id {
+            3 => count as usize * 2,
+            _ => count as usize,
+        };
+        if elems.len() != expected {
+            return Err(ReadBonErr::other(format!(
+                "container element count mismatch, declared {}, found {}",
+                count,
+                elems.len()
+            )));
         }
-        108 => {
-            rb2.head += 2;
-            rb2.bytes.get_u16_le() as usize
+    }
+    if type_id == 3 {
+        if elems.len() % 2 != 0 {
+            return Err(ReadBonErr::other(
+                "map container has odd elements".to_string(),
+            ));
         }
-        109 => {
-            rb2.head += 4;
-            rb2.bytes.get_u32_le() as usize
+        let mut pairs: Vec<&[Vec<u8>]> = elems.chunks(2).collect();
+        pairs.sort_by(|a, b| a[0].cmp(&b[0]));
+        elems = pairs.concat();
+    }
+    w.write_typed_container(type_id, count, &elems, |w, elems| {
+        for e in elems.iter() {
+            w.write_raw(e);
         }
-        110 => {
-            rb2.head += 6;
-            rb2.bytes.get_u16_le() as usize + (rb2.bytes.get_u32_le() * 0x10000) as usize
+    });
+    Ok(())
+}
+
+fn canonical_value(r: &mut ReadBuffer, w: &mut WriteBuffer) -> Result<(), ReadBonErr> {
+    match r.get_type_chunk()? {
+        9..=41 => w.write_canonical_int(r.read_any_int()?),
+        180..=248 => {
+            let mut c = r.read_container_scoped()?;
+            let mut elems = Vec::new();
+            while let Some(elem) = c.next_element() {
+                elems.push(canonical_values(elem?)?);
+            }
+            write_canonical_container(w, c.type_id, c.count, elems)?;
+            c.finish();
         }
-        _ => {
-            panic!("t2 is not str:{}", t2);
+        249..=251 => {
+            let (t, payload) = r.read_raw()?;
+            w.mark_value();
+            w.write_raw(&[t]);
+            w.write_raw(payload);
         }
+        _ => w.write_value(&r.read()?),
+    }
+    Ok(())
+}
+
+/// 比较两个容器，元素较少的一方，缺少的元素使用默认值补齐后再比较，适用于结构体末尾增加了可选字段的情况
+///
+/// defaults为依次排列的多个值，第i个值为第i个元素的默认值；没有默认值的位置，元素较少的一方较小；
+/// 容器类型不同或不是容器时，与partial_cmp一致
+pub fn cmp_with_defaults(a: &[u8], b: &[u8], defaults: &[u8]) -> Option<Ordering> {
+    let (mut ra, mut rb) = (ReadBuffer::new(a, 0), ReadBuffer::new(b, 0));
+    let (ca, cb) = match (ra.read_container_scoped(), rb.read_container_scoped()) {
+        (Ok(ca), Ok(cb)) if ca.type_id == cb.type_id => (ca, cb),
+        _ => return ReadBuffer::new(a, 0).partial_cmp(&ReadBuffer::new(b, 0)),
     };
+    let (mut ba, mut bb, mut bd) = (ca.body, cb.body, defaults);
+    loop {
+        let default = match value_len(bd) {
+            Ok(len) if len <= bd.len() => {
+                let (d, rest) = bd.split_at(len);
+                bd = rest;
+                Some(d)
+            }
+            _ => None,
+        };
+        let ea = next_value(&mut ba)?;
+        let eb = next_value(&mut bb)?;
+        let (ea, eb) = match (ea, eb, default) {
+            (None, None, _) => return Some(Ordering::Equal),
+            (Some(ea), Some(eb), _) => (ea, eb),
+            (None, Some(eb), Some(d)) => (d, eb),
+            (Some(ea), None, Some(d)) => (ea, d),
+            (None, Some(_), None) => return Some(Ordering::Less),
+            (Some(_), None, None) => return Some(Ordering::Greater),
+        };
+        match ReadBuffer::new(ea, 0).partial_cmp(&ReadBuffer::new(eb, 0)) {
+            Some(Ordering::Equal) => (),
+            r => return r,
+        }
+    }
+}
 
-    rb1.head += len1;
-    rb2.head += len2;
-    // println!("head1:{}, len1:{},head2:{}, len2:{}, t1:{},  byte1_len:{}, byte1:{:?}", head1, len1, head2, len2, t1, rb1.bytes.len(), rb1.bytes);
-    // println!("{:?}, {:?}", &rb1.bytes[rb1.head - len1..rb1.head], &rb2.bytes[rb2.head - len2..rb2.head]);
-    // println!("rb1 start = {:?}, rb1 end = {:?}, rb1 = {:?}", rb1.head - len1, rb1.head, rb1.bytes);
-    // println!("rb2 start = {:?}, rb2 end = {:?}, rb2 = {:?}", rb2.head - len2, rb2.head, rb2.bytes);
-    let dst1 = rb1.bytes.copy_to_bytes(len1);
-    let dst2 = rb2.bytes.copy_to_bytes(len2);
+// 取出下一个值的二进制，已读完返回Some(None)，数据错误返回None
+fn next_value<'a>(rb: &mut ReadBuffer<'a>) -> Option<Option<&'a [u8]>> {
+    if rb.bytes.is_empty() {
+        return Some(None);
+    }
+    let len = value_len(rb.bytes).ok()?;
+    rb.probe_border(len).ok()?;
+    let bytes = rb.bytes;
+    rb.skip_bytes(len);
+    Some(Some(&bytes[..len]))
+}
 
-    // rb1.bytes[rb1.head - len1..rb1.head].partial_cmp(&rb2.bytes[rb2.head - len2..rb2.head])
-    dst1.partial_cmp(&dst2)
+/// 两段二进制第一个不同之处
+#[derive(Debug)]
+pub struct DiffPoint {
+    /// 不同之处的路径，依次为顶层值的序号、各层容器中元素的序号（通用容器的元素数量不计入序号）
+    pub path: Vec<usize>,
+    /// a在该处的值，a已结束时为None
+    pub left: Option<EnumValue>,
+    /// b在该处的值，b已结束时为None
+    pub right: Option<EnumValue>,
 }
 
-fn compare_bin<'a>(rb1: &mut ReadBuffer<'a>, rb2: &mut ReadBuffer<'a>) -> Option<Ordering> {
-    rb1.head += 1;
+/// 同步遍历两段二进制，返回第一个不同之处，相同返回None
+///
+/// 类型和元素数量都相同的两个容器，会进入容器内部继续比较；非容器的值，partial_cmp相等即认为相同
+pub fn diff(a: &[u8], b: &[u8]) -> Result<Option<DiffPoint>, ReadBonErr> {
+    let mut path = Vec::new();
+    diff_values(ReadBuffer::new(a, 0), ReadBuffer::new(b, 0), &mut path)
+}
+
+fn diff_values<'a>(
+    mut ra: ReadBuffer<'a>,
+    mut rb: ReadBuffer<'a>,
+    path: &mut Vec<usize>,
+) -> Result<Option<DiffPoint>, ReadBonErr> {
+    let mut index = 0;
+    loop {
+        let (ea, eb) = (ra.bytes.is_empty(), rb.bytes.is_empty());
+        if ea && eb {
+            return Ok(None);
+        }
+        if !ea && !eb {
+            let (la, lb) = (value_len(ra.bytes)?, value_len(rb.bytes)?);
+            ra.probe_border(la)?;
+            rb.probe_border(lb)?;
+            let (ta, tb) = (ra.bytes[0], rb.bytes[0]);
+            if ra.bytes[..la] == rb.bytes[..lb] {
+                ra.skip_bytes(la);
+                rb.skip_bytes(lb);
+                index += 1;
+                continue;
+            }
+            if (180..249).contains(&ta) && (180..249).contains(&tb) {
+                let mut ca = ra.read_container_scoped()?;
+                let mut cb = rb.read_container_scoped()?;
+                if ca.type_id == cb.type_id && ca.count == cb.count {
+                    path.push(index);
+                    let r = diff_values(ca.body.clone(), cb.body.clone(), path)?;
+                    if r.is_some() {
+                        return Ok(r);
+                    }
+                    path.pop();
+                    ca.finish();
+                    cb.finish();
+                    index += 1;
+                    continue;
+                }
+            } else if !(180..249).contains(&ta) && !(180..249).contains(&tb) {
+                let mut b1 = ra.child(&ra.bytes[..la], ra.head);
+                let mut b2 = rb.child(&rb.bytes[..lb], rb.head);
+                if partial_cmp(&mut b1, &mut b2) == Some(Ordering::Equal) {
+                    ra.skip_bytes(la);
+                    rb.skip_bytes(lb);
+                    index += 1;
+                    continue;
+                }
+            }
+        }
+        path.push(index);
+        let left = if ea { None } else { Some(ra.read()?) };
+        let right = if eb { None } else { Some(rb.read()?) };
+        return Ok(Some(DiffPoint {
+            path: path.clone(),
+            left,
+            right,
+        }));
+    }
+}
+
+/// 读一个整数，与v比较，不分配内存；支持任意宽度的整数，不是整数时返回Err
+pub fn cmp_to_u64(rb: &mut ReadBuffer, v: u64) -> Result<Ordering, ReadBonErr> {
+    Ok(match rb.read_int_parts()? {
+        (true, _) => Ordering::Less,
+        (false, m) => m.cmp(&(v as u128)),
+    })
+}
+
+/// 读一个整数，与v比较，不分配内存；支持任意宽度的整数，不是整数时返回Err
+pub fn cmp_to_i64(rb: &mut ReadBuffer, v: i64) -> Result<Ordering, ReadBonErr> {
+    let (neg, m) = rb.read_int_parts()?;
+    let other = v.unsigned_abs() as u128;
+    Ok(match (neg, v < 0) {
+        (false, false) => m.cmp(&other),
+        (true, true) => other.cmp(&m),
+        (false, true) => Ordering::Greater,
+        (true, false) => Ordering::Less,
+    })
+}
+
+/// 读一个浮点数或整数，与v比较；整数转为f64后比较，超过2^53的整数可能有精度损失；任意一方为NaN或不是数字时返回Err
+pub fn cmp_to_f64(rb: &mut ReadBuffer, v: f64) -> Result<Ordering, ReadBonErr> {
+    let head = rb.head;
+    let f = match rb.get_type_chunk()? {
+        9..=41 => match rb.read_int_parts()? {
+            (true, m) => -(m as f64),
+            (false, m) => m as f64,
+        },
+        _ => rb.read_f64()?,
+    };
+    f.partial_cmp(&v)
+        .ok_or_else(|| ReadBonErr::other(format!("can not compare NaN, head = {}", head)))
+}
+
+/// 读一个字符串，按字节与v比较（即按unicode码点比较），直接借用底层的二进制，不分配内存，也不做UTF-8校验
+pub fn cmp_to_str(rb: &mut ReadBuffer, v: &str) -> Result<Ordering, ReadBonErr> {
+    Ok(rb.read_str_bytes()?.cmp(v.as_bytes()))
+}
+
+/// 比较两段二进制，同时返回决定大小的元素路径（格式与DiffPoint::path相同），相等时路径为空
+///
+/// 两个容器逐个元素比较，元素都相等时，元素较少的更小（与partial_cmp一致，不比较容器类型）；其它值的比较与partial_cmp一致，无法比较时返回Err
+pub fn cmp_explained(a: &[u8], b: &[u8]) -> Result<(Ordering, Vec<usize>), ReadBonErr> {
+    let mut path = Vec::new();
+    let r = cmp_values(ReadBuffer::new(a, 0), ReadBuffer::new(b, 0), &mut path)?;
+    Ok((r, path))
+}
+
+fn cmp_values<'a>(
+    mut ra: ReadBuffer<'a>,
+    mut rb: ReadBuffer<'a>,
+    path: &mut Vec<usize>,
+) -> Result<Ordering, ReadBonErr> {
+    let mut index = 0;
+    loop {
+        match (ra.bytes.is_empty(), rb.bytes.is_empty()) {
+            (true, true) => return Ok(Ordering::Equal),
+            (true, false) => {
+                path.push(index);
+                return Ok(Ordering::Less);
+            }
+            (false, true) => {
+                path.push(index);
+                return Ok(Ordering::Greater);
+            }
+            _ => (),
+        }
+        let (la, lb) = (value_len(ra.bytes)?, value_len(rb.bytes)?);
+        ra.probe_border(la)?;
+        rb.probe_border(lb)?;
+        let (ta, tb) = (ra.bytes[0], rb.bytes[0]);
+        if (180..249).contains(&ta) && (180..249).contains(&tb) {
+            let ca = ra.read_container_scoped()?;
+            let cb = rb.read_container_scoped()?;
+            path.push(index);
+            let r = cmp_values(ca.body.clone(), cb.body.clone(), path)?;
+            if r != Ordering::Equal {
+                return Ok(r);
+            }
+            path.pop();
+            ca.finish();
+            cb.finish();
+        } else {
+            let mut b1 = ra.child(&ra.bytes[..la], ra.head);
+            let mut b2 = rb.child(&rb.bytes[..lb], rb.head);
+            match try_partial_cmp(&mut b1, &mut b2)? {
+                Ordering::Equal => (),
+                r => {
+                    path.push(index);
+                    return Ok(r);
+                }
+            }
+            ra.skip_bytes(la);
+            rb.skip_bytes(lb);
+        }
+        index += 1;
+    }
+}
+
+// 将16位浮点数（IEEE 754 binary16）的位表示扩展为f32
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = ((bits >> 15) as u32) << 31;
+    let exp = ((bits >> 10) & 0x1f) as u32;
+    let man = (bits & 0x3ff) as u32;
+    let v = match exp {
+        // 0或非规格化数，值为man * 2^-24
+        0 => {
+            let v = man as f32 / (1 << 24) as f32;
+            return if sign == 0 { v } else { -v };
+        }
+        // 无穷大或NaN
+        0x1f => sign | 0x7f800000 | (man << 13),
+        _ => sign | ((exp + 112) << 23) | (man << 13),
+    };
+    f32::from_bits(v)
+}
+
+// 将浮点数或整数读为f64
+fn read_as_f64<'a>(rb: &mut ReadBuffer<'a>, t: u8) -> Option<f64> {
+    match t {
+        3..=7 => rb.read_f64().ok(),
+        14 => rb.read_i128().ok().map(|v| v as f64),
+        40 => rb.read_u64().ok().map(|v| v as f64),
+        41 => rb.read_u128().ok().map(|v| v as f64),
+        _ => rb.read_i64().ok().map(|v| v as f64),
+    }
+}
+
+// 读一个256位整数（类型249为正数，250为负数），值为32字节的小端数据（绝对值）
+fn to_bigint<'a>(bb: &mut ReadBuffer<'a>) -> BigInt {
+    let sign = match bb.bytes[0] {
+        250 => Sign::Minus,
+        _ => Sign::Plus,
+    };
+    bb.head += 1 + 32;
+    bb.bytes.advance(1);
+    let n = BigInt::from_bytes_le(sign, &bb.bytes[..32]);
+    bb.bytes.advance(32);
+    n
+}
+
+/// 读缓冲区当前值（类型为t）序列化后占用的字节数，包括类型和长度描述，不移动读指针
+///
+/// t与当前值的类型不符、长度描述不完整或类型为保留类型时返回Err；128位浮点数（类型8）返回其长度17
+pub fn base_type_len(bb: &ReadBuffer, t: u8) -> Result<usize, ReadBonErr> {
+    match bb.bytes.first() {
+        Some(v) if *v != t => Err(ReadBonErr::type_no_match(
+            format!("type {}", t),
+            *v,
+            bb.head,
+        )),
+        _ => value_len(bb.bytes),
+    }
+}
+
+// 从bytes的start位置读一个n字节的小端正整数
+fn read_uint_le(bytes: &[u8], start: usize, n: usize) -> Result<usize, ReadBonErr> {
+    if start + n > bytes.len() {
+        return Err(ReadBonErr::overflow(start + n, bytes.len()));
+    }
+    let mut v = 0usize;
+    for i in (0..n).rev() {
+        v = (v << 8) | bytes[start + i] as usize;
+    }
+    Ok(v)
+}
+
+// 解析bytes开头的容器头部，返回（头部长度， 容器体长度）, 容器体包括4字节的类型
+fn container_header(bytes: &[u8]) -> Result<(usize, usize), ReadBonErr> {
+    let t = match bytes.first() {
+        Some(t) => *t,
+        None => return Err(ReadBonErr::overflow(1, 0)),
+    };
+    match t {
+        180..=244 => Ok((1, (t - 180) as usize)),
+        245 => Ok((2, read_uint_le(bytes, 1, 1)?)),
+        246 => Ok((3, read_uint_le(bytes, 1, 2)?)),
+        247 => Ok((5, read_uint_le(bytes, 1, 4)?)),
+        248 => Ok((7, read_uint_le(bytes, 1, 6)?)),
+        _ => Err(ReadBonErr::type_no_match("container".to_string(), t, 0)),
+    }
+}
+
+// 计算bytes中连续写入的值的数量，bytes不是完整的值序列时返回None
+fn count_values(mut bytes: &[u8]) -> Option<usize> {
+    let mut n = 0;
+    while !bytes.is_empty() {
+        let len = value_len(bytes).ok()?;
+        bytes = bytes.get(len..)?;
+        n += 1;
+    }
+    Some(n)
+}
+
+// 计算bytes开头的一个值序列化后占用的字节数（包括类型）
+fn value_len(bytes: &[u8]) -> Result<usize, ReadBonErr> {
+    let t = match bytes.first() {
+        Some(t) => *t,
+        None => return Err(ReadBonErr::overflow(1, 0)),
+    };
+    let len = match t {
+        0..=4 | 15..=35 => 1,
+        9 | 36 => 2,
+        5 | 10 | 37 => 3,
+        6 | 11 | 38 => 5,
+        12 | 39 => 7,
+        7 | 13 | 40 => 9,
+        8 | 14 | 41 => 17,
+        42..=106 => (t - 42) as usize + 1,
+        111..=175 => (t - 111) as usize + 1,
+        107 | 176 => read_uint_le(bytes, 1, 1)? + 2,
+        108 | 177 => read_uint_le(bytes, 1, 2)? + 3,
+        109 | 178 => read_uint_le(bytes, 1, 4)? + 5,
+        110 | 179 => read_uint_le(bytes, 1, 6)? + 7,
+        180..=248 => {
+            let (header_len, body_len) = container_header(bytes)?;
+            header_len + body_len
+        }
+        249 | 250 => 33,
+        251 => read_uint_le(bytes, 1, 1)? + 2,
+        252 => 5,
+        253..=255 => return Err(ReadBonErr::ReservedType(t)),
+    };
+    Ok(len)
+}
+
+fn compare_number<'a>(rb: &mut ReadBuffer<'a>, v1: f64, t2: u8) -> Result<Ordering, ReadBonErr> {
+    let v2 = match t2 {
+        3..=7 => rb.read_f64()?,
+        9..=13 => rb.read_i64()? as f64,
+        14 => {
+            rb.head += 17;
+            rb.bytes.advance(17);
+            return Ok(Ordering::Greater);
+        }
+        15 => {
+            rb.head += 1;
+            rb.bytes.advance(1);
+            -1.0
+        }
+        16..=40 => rb.read_u64()? as f64,
+        41 => {
+            rb.head += 17;
+            rb.bytes.advance(17);
+            return Ok(Ordering::Less);
+        }
+        _ => return Err(ReadBonErr::type_no_match("number".to_string(), t2, rb.head)),
+    };
+    if v1.is_nan() {
+        if v2.is_nan() {
+            return Ok(Ordering::Equal);
+        } else {
+            return Ok(Ordering::Less);
+        }
+    }
+    v1.partial_cmp(&v2)
+        .ok_or_else(|| ReadBonErr::other(format!("can not compare {} with {}", v1, v2)))
+}
+
+fn compare_int<'a>(
+    rb1: &mut ReadBuffer<'a>,
+    rb2: &mut ReadBuffer<'a>,
+    t: u8,
+) -> Result<Ordering, ReadBonErr> {
+    Ok(match t {
+        9..=13 => rb1.read_i64()?.cmp(&rb2.read_i64()?),
+        14 => rb1.read_i128()?.cmp(&rb2.read_i128()?),
+        36..=40 => rb1.read_u64()?.cmp(&rb2.read_u64()?),
+        41 => rb1.read_u128()?.cmp(&rb2.read_u128()?),
+        _ => {
+            return Err(ReadBonErr::type_no_match(
+                "integer".to_string(),
+                t,
+                rb1.head,
+            ))
+        }
+    })
+}
+
+fn compare_str<'a>(
+    rb1: &mut ReadBuffer<'a>,
+    rb2: &mut ReadBuffer<'a>,
+) -> Result<Ordering, ReadBonErr> {
+    rb1.head += 1;
     rb2.head += 1;
-    let t1 = rb1.get_type().unwrap();
-    let t2 = rb2.get_type().unwrap();
+    let t1 = rb1.get_type()?;
+    let t2 = rb2.get_type()?;
     let len1 = match t1 {
-        111..176 => (t1 - 111) as usize,
-        176 => {
+        42..=106 => (t1 - 42) as usize,
+        107 => {
             rb1.head += 1;
             rb1.bytes.get_u8() as usize
         }
-        177 => {
+        108 => {
             rb1.head += 2;
             rb1.bytes.get_u16_le() as usize
         }
-        178 => {
+        109 => {
             rb1.head += 4;
             rb1.bytes.get_u32_le() as usize
         }
-        179 => {
+        110 => {
             rb1.head += 6;
             rb1.bytes.get_u16_le() as usize + (rb1.bytes.get_u32_le() * 0x10000) as usize
         }
         _ => {
-            panic!("t1 is not bin:{}", t1);
+            return Err(ReadBonErr::type_no_match(
+                "string".to_string(),
+                t1,
+                rb1.head,
+            ))
         }
     };
 
     let len2 = match t2 {
-        111..176 => (t2 - 111) as usize,
-        176 => {
+        42..=106 => (t2 - 42) as usize,
+        107 => {
             rb2.head += 1;
             rb2.bytes.get_u8() as usize
         }
-        177 => {
+        108 => {
             rb2.head += 2;
             rb2.bytes.get_u16_le() as usize
         }
-        178 => {
+        109 => {
             rb2.head += 4;
             rb2.bytes.get_u32_le() as usize
         }
-        179 => {
+        110 => {
             rb2.head += 6;
             rb2.bytes.get_u16_le() as usize + (rb2.bytes.get_u32_le() * 0x10000) as usize
         }
         _ => {
-            panic!("t2 is not bin:{}", t2);
+            return Err(ReadBonErr::type_no_match(
+                "string".to_string(),
+                t2,
+                rb2.head,
+            ))
         }
     };
 
     rb1.head += len1;
     rb2.head += len2;
-
+    // println!("head1:{}, len1:{},head2:{}, len2:{}, t1:{},  byte1_len:{}, byte1:{:?}", head1, len1, head2, len2, t1, rb1.bytes.len(), rb1.bytes);
+    // println!("{:?}, {:?}", &rb1.bytes[rb1.head - len1..rb1.head], &rb2.bytes[rb2.head - len2..rb2.head]);
+    // println!("rb1 start = {:?}, rb1 end = {:?}, rb1 = {:?}", rb1.head - len1, rb1.head, rb1.bytes);
+    // println!("rb2 start = {:?}, rb2 end = {:?}, rb2 = {:?}", rb2.head - len2, rb2.head, rb2.bytes);
     let dst1 = rb1.bytes.copy_to_bytes(len1);
     let dst2 = rb2.bytes.copy_to_bytes(len2);
 
-    dst1.partial_cmp(&dst2)
     // rb1.bytes[rb1.head - len1..rb1.head].partial_cmp(&rb2.bytes[rb2.head - len2..rb2.head])
+    Ok(dst1.cmp(&dst2))
 }
 
-fn compare_contain<'a>(rb1: &mut ReadBuffer<'a>, rb2: &mut ReadBuffer<'a>) -> Option<Ordering> {
-    let t1 = rb1.get_type().unwrap();
-    let t2 = rb2.get_type().unwrap();
+fn compare_bin<'a>(
+    rb1: &mut ReadBuffer<'a>,
+    rb2: &mut ReadBuffer<'a>,
+) -> Result<Ordering, ReadBonErr> {
     rb1.head += 1;
     rb2.head += 1;
-    match t1 {
-        180..245 => (t1 - 180) as usize,
-        245 => {
+    let t1 = rb1.get_type()?;
+    let t2 = rb2.get_type()?;
+    let len1 = match t1 {
+        111..=175 => (t1 - 111) as usize,
+        176 => {
             rb1.head += 1;
             rb1.bytes.get_u8() as usize
         }
-        246 => {
+        177 => {
             rb1.head += 2;
             rb1.bytes.get_u16_le() as usize
         }
-        247 => {
+        178 => {
             rb1.head += 4;
             rb1.bytes.get_u32_le() as usize
         }
-        248 => {
+        179 => {
             rb1.head += 6;
             rb1.bytes.get_u16_le() as usize + (rb1.bytes.get_u32_le() * 0x10000) as usize
         }
-        _ => {
-            panic!("it is not contain");
-        }
+        _ => return Err(ReadBonErr::type_no_match("bin".to_string(), t1, rb1.head)),
     };
 
-    match t2 {
-        180..245 => (t2 - 180) as usize,
-        245 => {
+    let len2 = match t2 {
+        111..=175 => (t2 - 111) as usize,
+        176 => {
             rb2.head += 1;
             rb2.bytes.get_u8() as usize
         }
-        246 => {
+        177 => {
             rb2.head += 2;
             rb2.bytes.get_u16_le() as usize
         }
-        247 => {
+        178 => {
             rb2.head += 4;
             rb2.bytes.get_u32_le() as usize
         }
-        248 => {
+        179 => {
             rb2.head += 6;
             rb2.bytes.get_u16_le() as usize + (rb2.bytes.get_u32_le() * 0x10000) as usize
         }
-        _ => {
-            panic!("it is not contain");
+        _ => return Err(ReadBonErr::type_no_match("bin".to_string(), t2, rb2.head)),
+    };
+
+    rb1.head += len1;
+    rb2.head += len2;
+
+    let dst1 = rb1.bytes.copy_to_bytes(len1);
+    let dst2 = rb2.bytes.copy_to_bytes(len2);
+
+    Ok(dst1.cmp(&dst2))
+    // rb1.bytes[rb1.head - len1..rb1.head].partial_cmp(&rb2.bytes[rb2.head - len2..rb2.head])
+}
+
+fn compare_contain<'a>(
+    rb1: &mut ReadBuffer<'a>,
+    rb2: &mut ReadBuffer<'a>,
+) -> Result<Ordering, ReadBonErr> {
+    let c1 = rb1.read_container_scoped()?;
+    let c2 = rb2.read_container_scoped()?;
+    // 跳过容器头、类型（及元素数量），逐个比较元素，前面的元素都相等时，元素较少的更小
+    let (mut b1, mut b2) = (c1.body.clone(), c2.body.clone());
+    let r = loop {
+        match (b1.bytes.is_empty(), b2.bytes.is_empty()) {
+            (true, true) => break Ok(Ordering::Equal),
+            (true, false) => break Ok(Ordering::Less),
+            (false, true) => break Ok(Ordering::Greater),
+            _ => match try_partial_cmp(&mut b1, &mut b2) {
+                Ok(Ordering::Equal) => (),
+                r => break r,
+            },
         }
     };
+    c1.finish();
+    c2.finish();
+    r
+}
 
-    return Some(Ordering::Equal);
+/// 按协议的类别读出的值，见ReadBuffer::read_typed，字符串、二进制直接借用底层的二进制
+#[derive(Debug, Clone)]
+pub enum BonType<'a> {
+    Null,
+    Bool(bool),
+    /// 16、32、64位浮点数
+    Float(f64),
+    /// 任意宽度的整数
+    Int(i128),
+    /// 超出i128范围的正整数
+    UInt(u128),
+    Str(&'a str),
+    Bin(&'a [u8]),
+    /// 容器，count为元素数量（只有通用对象、数组、map才有），len为容器体的字节数（包括类型），
+    /// body为元素部分（通用容器的数量之后），可在其上依次读取元素
+    Container {
+        type_id: u32,
+        count: Option<u32>,
+        len: usize,
+        body: ReadBuffer<'a>,
+    },
+    /// 其它类型（BigInt、小map、引用），类型值及其后的全部字节
+    Other(u8, &'a [u8]),
 }
 
+/// 值的类型，与read读出的EnumValue对应，见ReadBuffer::peek_type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EnumType {
     Void,
     Bool,
@@ -2346,18 +5333,315 @@ pub enum EnumType {
     U16,
     U32,
     U64,
+    U128,
     I8,
     I16,
     I32,
     I64,
+    I128,
     F32,
     F64,
+    /// 字符串，数据的字节数
     Str(u64),
+    /// 二进制，数据的字节数
     Bin(u64),
+    /// 通用数组，（元素数量， 整个容器占用的字节数）
     Arr(u32, u64),
+    /// 通用map，（元素数量， 整个容器占用的字节数）
     Map(u32, u64),
+    /// 其它容器，容器类型
     Struct(u64),
 }
+/// 扩展类型可使用的容器类型范围
+pub const EXTENSION_TYPE_IDS: RangeInclusive<u32> = 0xFFFF0000..=0xFFFFFFFF;
+
+/// 计算结构体、枚举的容器类型（FNV-1a 32），避开0~6的内置容器类型和扩展类型的范围
+///
+/// serde序列化和派生宏（生成的代码调用本函数）都使用这个规则
+pub const fn type_hash(desc: &str) -> u32 {
+    let bytes = desc.as_bytes();
+    let mut h: u32 = 0x811c9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        h ^= bytes[i] as u32;
+        h = h.wrapping_mul(0x01000193);
+        i += 1;
+    }
+    if h <= 6 || h >= *EXTENSION_TYPE_IDS.start() {
+        h ^= 0x80000000;
+    }
+    h
+}
+
+/// 扩展类型的编解码表，为Rust类型指定容器类型及编解码方法，配合write_extension、read_extension使用
+///
+/// 编解码方法要求Send + Sync，注册完成后可放在static或Arc中供多个线程共享
+#[derive(Default)]
+pub struct CodecRegistry {
+    // Rust类型 -> (容器类型, Codec<T>)
+    codecs: HashMap<TypeId, (u32, Box<dyn Any + Send + Sync>)>,
+    // 已使用的容器类型
+    type_ids: HashMap<u32, TypeId>,
+}
+
+struct Codec<T> {
+    encode: Box<dyn Fn(&T, &mut WriteBuffer) + Send + Sync>,
+    decode: Box<dyn Fn(&mut ReadBuffer) -> Result<T, ReadBonErr> + Send + Sync>,
+}
+
+impl CodecRegistry {
+    pub fn new() -> CodecRegistry {
+        CodecRegistry::default()
+    }
+
+    /// 注册扩展类型，type_id必须在EXTENSION_TYPE_IDS范围内，且不能重复注册，否则panic
+    pub fn register<T, E, D>(&mut self, type_id: u32, encode: E, decode: D)
+    where
+        T: 'static,
+        E: Fn(&T, &mut WriteBuffer) + Send + Sync + 'static,
+        D: Fn(&mut ReadBuffer) -> Result<T, ReadBonErr> + Send + Sync + 'static,
+    {
+        assert!(
+            EXTENSION_TYPE_IDS.contains(&type_id),
+            "extension type id out of range: {:#x}",
+            type_id
+        );
+        if self.type_ids.insert(type_id, TypeId::of::<T>()).is_some() {
+            panic!("extension type id already registered: {:#x}", type_id);
+        }
+        let codec: Codec<T> = Codec {
+            encode: Box::new(encode),
+            decode: Box::new(decode),
+        };
+        self.codecs
+            .insert(TypeId::of::<T>(), (type_id, Box::new(codec)));
+    }
+
+    /// T注册的容器类型
+    pub fn type_id_of<T: 'static>(&self) -> Option<u32> {
+        self.codecs.get(&TypeId::of::<T>()).map(|(id, _)| *id)
+    }
+
+    fn codec<T: 'static>(&self) -> Option<(u32, &Codec<T>)> {
+        let (id, codec) = self.codecs.get(&TypeId::of::<T>())?;
+        Some((*id, codec.downcast_ref::<Codec<T>>()?))
+    }
+}
+
+/// 多态值的解码表，容器类型 -> 解码方法，配合read_polymorphic使用，用于读取由不同类型的消息组成的流
+#[derive(Default)]
+pub struct TypeRegistry {
+    decoders: HashMap<u32, Box<dyn Fn(&mut ReadBuffer) -> Result<Box<dyn Any>, ReadBonErr>>>,
+}
+
+impl TypeRegistry {
+    pub fn new() -> TypeRegistry {
+        TypeRegistry::default()
+    }
+
+    /// 注册容器类型为type_id的值使用T的Decode解码，T的Decode需要读取整个容器（包括容器头）；重复注册时panic
+    pub fn register<T: Decode + 'static>(&mut self, type_id: u32) {
+        self.register_with(type_id, T::decode);
+    }
+
+    /// 注册容器类型为type_id的值使用decode解码，decode需要读取整个容器（包括容器头）；重复注册时panic
+    pub fn register_with<T, D>(&mut self, type_id: u32, decode: D)
+    where
+        T: 'static,
+        D: Fn(&mut ReadBuffer) -> Result<T, ReadBonErr> + 'static,
+    {
+        let decoder: Box<dyn Fn(&mut ReadBuffer) -> Result<Box<dyn Any>, ReadBonErr>> =
+            Box::new(move |r| Ok(Box::new(decode(r)?) as Box<dyn Any>));
+        if self.decoders.insert(type_id, decoder).is_some() {
+            panic!("polymorphic type id already registered: {}", type_id);
+        }
+    }
+}
+
+/// 引用写入器，用于序列化共享子值的有向无环图
+///
+/// 同一个Arc第一次写入时完整写出，并记录其相对于创建RefWriter时缓冲区末尾的偏移；之后再写入时，只写一个引用（类型252 + 4字节偏移）。
+/// 用RefReader读取，共享的值只会被解码一次，读出的Arc也是共享的
+pub struct RefWriter<'w> {
+    wb: &'w mut WriteBuffer,
+    // 引用区域的起始位置
+    start: usize,
+    // Arc指针 -> （Arc的克隆， 偏移），持有Arc，避免值释放后地址被新的Arc复用
+    offsets: HashMap<usize, (Arc<dyn Any>, u32)>,
+}
+
+impl<'w> RefWriter<'w> {
+    pub fn new(wb: &'w mut WriteBuffer) -> RefWriter<'w> {
+        let start = wb.bytes.len();
+        RefWriter {
+            wb,
+            start,
+            offsets: HashMap::new(),
+        }
+    }
+
+    /// 写一个共享的值，以Arc的指针判断是否已写入过；写入过的Arc会被RefWriter持有，直到RefWriter drop
+    pub fn write_arc<T: Encode + 'static>(&mut self, v: &Arc<T>) {
+        let key = Arc::as_ptr(v) as usize;
+        match self.offsets.get(&key) {
+            Some((_, offset)) => {
+                self.wb.mark_value();
+                self.wb.try_extend_capity(5);
+                self.wb.bytes.put_u8(252);
+                self.wb.bytes.put_u32_le(*offset);
+                self.wb.tail += 5;
+            }
+            None => {
+                // 偏移指向填充之后的值，与RefReader跳过填充后的位置一致
+                self.wb.pad_value();
+                let offset = u32::try_from(self.wb.bytes.len() - self.start)
+                    .expect("reference offset overflow");
+                self.offsets
+                    .insert(key, (v.clone() as Arc<dyn Any>, offset));
+                v.as_ref().encode(self.wb);
+            }
+        }
+    }
+
+    /// 写入非共享值的缓冲区
+    pub fn buffer(&mut self) -> &mut WriteBuffer {
+        self.wb
+    }
+}
+
+/// 引用读取器，读取RefWriter写入的数据，bin为引用区域（从创建RefWriter时的位置开始）
+pub struct RefReader<'a> {
+    bin: &'a [u8],
+    // bin开头在ReadBuffer中的位置
+    base: usize,
+    rb: ReadBuffer<'a>,
+    // 偏移 -> 已解码的Arc<T>
+    values: HashMap<usize, Box<dyn Any>>,
+}
+
+impl<'a> RefReader<'a> {
+    pub fn new(bin: &'a [u8]) -> RefReader<'a> {
+        RefReader::with_reader(ReadBuffer::new(bin, 0))
+    }
+
+    /// 用已设置好的ReadBuffer创建，rb的剩余部分为引用区域；rb的设置（严格模式、分配预算、字节序、调试填充等）同样用于解码引用的值
+    pub fn with_reader(rb: ReadBuffer<'a>) -> RefReader<'a> {
+        RefReader {
+            bin: rb.bytes,
+            base: rb.head,
+            rb,
+            values: HashMap::new(),
+        }
+    }
+
+    /// 读一个共享的值，如果是引用，跳转到引用的偏移处解码，同一偏移的值只解码一次
+    pub fn read_arc<T: Decode + 'static>(&mut self) -> Result<Arc<T>, ReadBonErr> {
+        self.rb.skip_debug_padding();
+        self.rb.probe_border(1)?;
+        let offset = match self.rb.bytes[0] {
+            252 => {
+                self.rb.probe_border(5)?;
+                let offset = read_uint_le(self.rb.bytes, 1, 4)?;
+                self.rb.bytes.advance(5);
+                self.rb.head += 5;
+                offset
+            }
+            _ => {
+                let offset = self.rb.head - self.base;
+                if !self.values.contains_key(&offset) {
+                    let v = Arc::new(T::decode(&mut self.rb)?);
+                    self.values.insert(offset, Box::new(v));
+                }
+                offset
+            }
+        };
+        if let Some(v) = self.values.get(&offset) {
+            return match v.downcast_ref::<Arc<T>>() {
+                Some(v) => Ok(v.clone()),
+                None => Err(ReadBonErr::other(format!(
+                    "reference type mismatch, offset: {}",
+                    offset
+                ))),
+            };
+        }
+        if offset >= self.bin.len() {
+            return Err(ReadBonErr::overflow(offset, self.bin.len()));
+        }
+        let v = Arc::new(T::decode(
+            &mut self.rb.child(&self.bin[offset..], self.base + offset),
+        )?);
+        self.values.insert(offset, Box::new(v.clone()));
+        Ok(v)
+    }
+
+    /// 读取非共享值的缓冲区
+    pub fn buffer(&mut self) -> &mut ReadBuffer<'a> {
+        &mut self.rb
+    }
+}
+
+/// 定点小数，值为mantissa / 10^scale，见WriteBuffer::write_decimal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decimal {
+    pub mantissa: i128,
+    pub scale: u8,
+}
+
+impl Encode for Decimal {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        bb.write_decimal(self.mantissa, self.scale);
+    }
+}
+
+impl Decode for Decimal {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        let (mantissa, scale) = bb.read_decimal()?;
+        Ok(Decimal { mantissa, scale })
+    }
+}
+
+/// 相对于调用者提供的起点（如会话开始时的Instant）的单调时间，用于序列化Instant，序列化为u64的纳秒数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RelInstant {
+    pub since_epoch_nanos: u64,
+}
+
+impl RelInstant {
+    /// 计算instant相对于epoch的时间，instant早于epoch时为0，超过u64纳秒（约584年）时取u64::MAX
+    pub fn from_instant(instant: Instant, epoch: Instant) -> RelInstant {
+        let nanos = instant.saturating_duration_since(epoch).as_nanos();
+        RelInstant {
+            since_epoch_nanos: u64::try_from(nanos).unwrap_or(u64::MAX),
+        }
+    }
+
+    /// 以epoch为起点，还原为Instant
+    pub fn to_instant(&self, epoch: Instant) -> Instant {
+        epoch + Duration::from_nanos(self.since_epoch_nanos)
+    }
+}
+
+impl Encode for RelInstant {
+    fn encode(&self, bb: &mut WriteBuffer) {
+        bb.write_u64(self.since_epoch_nanos);
+    }
+}
+
+impl Decode for RelInstant {
+    fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+        Ok(RelInstant {
+            since_epoch_nanos: bb.read_u64()?,
+        })
+    }
+}
+
+/// 带符号信息的整数，见WriteBuffer::write_tagged_int
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaggedInt {
+    Signed(i128),
+    Unsigned(u128),
+}
+
 #[derive(Debug)]
 pub enum EnumValue {
     Void,
@@ -2377,43 +5661,185 @@ pub enum EnumValue {
     Str(String),
     Bin(Vec<u8>),
     Arr(Arc<Vec<EnumValue>>),
-    Map(HashMap<Arc<EnumValue>, Arc<EnumValue>>),
+    /// 通用map，按二进制中的顺序排列的键值对，不要求键可哈希
+    Map(Vec<(EnumValue, EnumValue)>),
     Struct(Arc<StructValue>),
 }
 
-#[derive(Debug)]
-pub struct StructValue {
-    pub hash: u32,
-    pub fields: Vec<FieldValue>,
-}
+impl EnumValue {
+    /// 空值
+    pub fn null() -> EnumValue {
+        EnumValue::Void
+    }
 
-#[derive(Debug)]
-pub struct FieldValue {
-    pub name: String,
-    pub fvalue: EnumValue,
-}
+    /// 有符号整数，写入时总是使用最短的编码
+    pub fn int(v: i64) -> EnumValue {
+        EnumValue::I64(v)
+    }
 
-fn move_part(bytes: &mut Vec<u8>, range: Range<usize>, offset: usize) {
-    unsafe {
-        let len = bytes.len();
-        let dl = range.end - range.start;
-        if len < offset + dl {
-            bytes.set_len(offset + dl);
+    /// 无符号整数，写入时总是使用最短的编码
+    pub fn uint(v: u64) -> EnumValue {
+        EnumValue::U64(v)
+    }
+
+    pub fn float(v: f64) -> EnumValue {
+        EnumValue::F64(v)
+    }
+
+    pub fn string(v: impl Into<String>) -> EnumValue {
+        EnumValue::Str(v.into())
+    }
+
+    pub fn bin(v: impl Into<Vec<u8>>) -> EnumValue {
+        EnumValue::Bin(v.into())
+    }
+
+    /// 通用数组
+    pub fn array(elems: Vec<EnumValue>) -> EnumValue {
+        EnumValue::Arr(Arc::new(elems))
+    }
+
+    /// 通用map，键值对按给定的顺序排列
+    pub fn map(entries: Vec<(EnumValue, EnumValue)>) -> EnumValue {
+        EnumValue::Map(entries)
+    }
+
+    /// 结构体，hash为容器类型，字段按给定的顺序排列
+    pub fn structure(hash: u32, fields: Vec<(&str, EnumValue)>) -> EnumValue {
+        EnumValue::Struct(Arc::new(StructValue {
+            hash,
+            fields: fields
+                .into_iter()
+                .map(|(name, fvalue)| FieldValue {
+                    name: name.to_string(),
+                    fvalue,
+                })
+                .collect(),
+        }))
+    }
+
+    /// 以最短的编码序列化，用于生成内容寻址的表示，规则见WriteBuffer::write_value
+    pub fn to_canonical_bytes(&self) -> Result<Vec<u8>, ReadBonErr> {
+        Ok(encode_value(self))
+    }
+
+    /// 将Map转为以键的最短编码（to_canonical_bytes）为键的HashMap，便于查找，重复的键后者覆盖前者；不是Map时返回None
+    pub fn to_hashmap(&self) -> Option<HashMap<Vec<u8>, &EnumValue>> {
+        match self {
+            EnumValue::Map(entries) => {
+                Some(entries.iter().map(|(k, v)| (encode_value(k), v)).collect())
+            }
+            _ => None,
         }
-        let src = bytes.as_mut_ptr();
-        src.wrapping_offset(range.start as isize)
-            .copy_to(src.wrapping_offset(offset as isize), dl)
     }
 }
 
+// 以最短的编码序列化一个值
+fn encode_value(v: &EnumValue) -> Vec<u8> {
+    let mut w = WriteBuffer::new();
+    w.write_value(v);
+    w.unwrap()
+}
+
+/// 数据结构的描述，用于ReadBuffer::matches_schema
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schema {
+    /// 任意类型的一个值
+    Any,
+    Nil,
+    Bool,
+    /// 浮点数（包括0.0和1.0）
+    Float,
+    /// 整数，不区分宽度和符号
+    Int,
+    Str,
+    Bin,
+    /// 容器，可指定容器的类型
+    Container(Option<u32>),
+    /// 为None或为指定的类型，与Option<T>的序列化方式一致
+    Option(Box<Schema>),
+    /// 数量 + 元素，与Vec<T>的序列化方式一致
+    Vec(Box<Schema>),
+    /// 数量 + 键值对，与HashMap<K, V>的序列化方式一致
+    Map(Box<Schema>, Box<Schema>),
+    /// 依次排列的多个值
+    Tuple(Vec<Schema>),
+}
+
+#[derive(Debug)]
+pub struct StructValue {
+    pub hash: u32,
+    pub fields: Vec<FieldValue>,
+}
+
+impl StructValue {
+    /// 按名字查找字段的值，有同名字段时返回第一个
+    pub fn get(&self, name: &str) -> Option<&EnumValue> {
+        self.fields
+            .iter()
+            .find(|f| f.name == name)
+            .map(|f| &f.fvalue)
+    }
+}
+
+#[derive(Debug)]
+pub struct FieldValue {
+    pub name: String,
+    pub fvalue: EnumValue,
+}
+
+// 根据字符串或二进制数据的长度，选择类型值（t为长度为0时的类型值），返回（类型值， 描述长度的字节数）
+fn data_len_code(len: usize, t: u8) -> (u8, usize) {
+    if len <= 64 {
+        (t + len as u8, 0)
+    } else if len <= 0xff {
+        (t + 65, 1)
+    } else if len <= 0xffff {
+        (t + 66, 2)
+    } else if len <= 0xffffffff {
+        (t + 67, 4)
+    } else if len as u64 <= 0xffffffffffff {
+        (t + 68, 6)
+    } else {
+        panic!("data overflow, len = {}", len);
+    }
+}
+
+// 根据容器体的长度，选择容器的类型值，返回（类型值， 描述长度的字节数）
+fn container_len_code(len: u64) -> (u8, usize) {
+    if len <= 64 {
+        (180 + len as u8, 0)
+    } else if len <= 0xff {
+        (245, 1)
+    } else if len <= 0xffff {
+        (246, 2)
+    } else if len <= 0xffffffff {
+        (247, 4)
+    } else if len <= 0xffffffffffff {
+        (248, 6)
+    } else {
+        panic!("container overflow, len = {}", len);
+    }
+}
+
+// 将range中的字节移动到offset处（区域可以重叠），目标超出长度时先扩展长度
+fn move_part(bytes: &mut Vec<u8>, range: Range<usize>, offset: usize) {
+    let end = offset + range.len();
+    if bytes.len() < end {
+        bytes.resize(end, 0);
+    }
+    bytes.copy_within(range, offset);
+}
+
 #[cfg(test)]
 extern crate rand;
-#[cfg(test)]
+#[cfg(all(test, feature = "bench"))]
 extern crate test;
 #[cfg(test)]
 mod tests {
     use super::*;
     use rand::prelude::*;
+    #[cfg(feature = "bench")]
     use test::Bencher;
 
     macro_rules! test_number {
@@ -2468,163 +5894,4089 @@ mod tests {
     test_utf8!(test_utf8_10000, 10000);
 
     #[test]
-    fn test_bin() -> Result<(), Box<dyn Error>> {
-        let mut buf = WriteBuffer::with_bytes(vec![], 0);
-        let arr: Vec<u8> = (0..1000).map(|_| thread_rng().gen::<u8>()).collect();
-        buf.write_bin(&arr, 0..1000);
+    fn test_bin() -> Result<(), Box<dyn Error>> {
+        let mut buf = WriteBuffer::with_bytes(vec![], 0);
+        let arr: Vec<u8> = (0..1000).map(|_| thread_rng().gen::<u8>()).collect();
+        buf.write_bin(&arr, 0..1000);
+
+        let mut read_buf = ReadBuffer::new(buf.get_byte(), 0);
+        assert_eq!(read_buf.read_bin()?, arr);
+
+        Ok(())
+    }
+
+    //测试大小比较
+    #[test]
+    fn test_ord() {
+        let mut buf1 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf2 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf3 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf4 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf5 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf6 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf7 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf8 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf9 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf10 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf11 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf12 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf13 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf14 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf15 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf16 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf17 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf18 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf19 = WriteBuffer::with_bytes(Vec::new(), 0);
+        let mut buf20 = WriteBuffer::with_bytes(Vec::new(), 0);
+        buf1.write_nil();
+        buf2.write_bool(false);
+        buf3.write_bool(true);
+        buf4.write_f32(0.0);
+        buf5.write_f32(1.0);
+        buf6.write_f32(5.1);
+        buf7.write_f32(5.6);
+        buf8.write_f64(7.5);
+        buf9.write_f64(3.4);
+        buf10.write_i8(-1);
+        buf11.write_i8(-1);
+        buf12.write_i8(120);
+        buf13.write_u32(10);
+        buf14.write_i32(5);
+        buf15.write_utf8("abcdefg");
+        buf16.write_utf8("abcdefgh");
+        buf17.write_utf8("abcddfgh");
+        buf18.write_bin(&[5; 10], 0..10);
+        buf19.write_bin(&[6; 5], 0..5);
+        buf20.write_bin(&[6; 5], 0..5);
+        let read_buf1 = ReadBuffer::new(buf1.get_byte(), 0);
+        let read_buf2 = ReadBuffer::new(buf2.get_byte(), 0);
+        let read_buf3 = ReadBuffer::new(buf3.get_byte(), 0);
+        let read_buf4 = ReadBuffer::new(buf4.get_byte(), 0);
+        let read_buf5 = ReadBuffer::new(buf5.get_byte(), 0);
+        let read_buf6 = ReadBuffer::new(buf6.get_byte(), 0);
+        let read_buf7 = ReadBuffer::new(buf7.get_byte(), 0);
+        let read_buf8 = ReadBuffer::new(buf8.get_byte(), 0);
+        let read_buf9 = ReadBuffer::new(buf9.get_byte(), 0);
+        let read_buf10 = ReadBuffer::new(buf10.get_byte(), 0);
+        let read_buf11 = ReadBuffer::new(buf11.get_byte(), 0);
+        let read_buf12 = ReadBuffer::new(buf12.get_byte(), 0);
+        let read_buf13 = ReadBuffer::new(buf13.get_byte(), 0);
+        let read_buf14 = ReadBuffer::new(buf14.get_byte(), 0);
+        let read_buf15 = ReadBuffer::new(buf15.get_byte(), 0);
+        let read_buf16 = ReadBuffer::new(buf16.get_byte(), 0);
+        let read_buf17 = ReadBuffer::new(buf17.get_byte(), 0);
+        let read_buf18 = ReadBuffer::new(buf18.get_byte(), 0);
+        let read_buf19 = ReadBuffer::new(buf19.get_byte(), 0);
+        let read_buf20 = ReadBuffer::new(buf20.get_byte(), 0);
+        assert_eq!(read_buf1 < read_buf2, true); //测试null, false
+        assert_eq!(read_buf2 < read_buf3, true); //测试false, true
+        assert_eq!(read_buf2 < read_buf4, true); //测试false, 0.0
+        assert_eq!(read_buf4 < read_buf5, true); //测试0.0, 1.0
+        assert_eq!(read_buf9 < read_buf7, true); //测试3.4, 5.6
+        assert_eq!(read_buf6 < read_buf7, true); //测试5.1, 5.6
+        assert_eq!(read_buf7 < read_buf8, true); //测试5.6, 7.5
+        assert_eq!(read_buf10 < read_buf6, true); //测试-1, 5.6
+        assert_eq!(read_buf10 == read_buf11, true); //测试-1, -1
+        assert_eq!(read_buf11 < read_buf12, true); //测试-1, 200
+        assert_eq!(read_buf12 > read_buf13, true); //测试 120, 10
+        assert_eq!(read_buf13 > read_buf14, true); //测试 10, 5
+        assert_eq!(read_buf1 < read_buf15, true); //测试 null, "abcdefg"
+        assert_eq!(read_buf4 < read_buf15, true); //测试 0.0, "abcdefg"
+        assert_eq!(read_buf6 < read_buf15, true); //测试 5.1, "abcdefg"
+        assert_eq!(read_buf12 < read_buf15, true); //测试 200, "abcdefg"
+        assert_eq!(read_buf13 < read_buf15, true); //测试 10, "abcdefg"
+        assert_eq!(read_buf15 < read_buf16, true); //测试 "abcdefg", "abcdefgh"
+        assert_eq!(read_buf15 > read_buf17, true); //测试 "abcdefg", "abcddfgh"
+        assert_eq!(read_buf13 < read_buf18, true); //测试 10, &[5;10]
+        assert_eq!(read_buf6 < read_buf18, true); //测试 5.1, &[5;10]
+        assert_eq!(read_buf3 < read_buf18, true); //测试 true, &[5;10]
+        assert_eq!(read_buf15 < read_buf18, true); //测试 "abcdefg", &[5;10]
+        assert_eq!(read_buf18 < read_buf19, true); //测试 &[5;10], &[6;5]
+        assert_eq!(read_buf19 == read_buf20, true); //测试 &[6;5], &[6;5]
+        let mut buf1 = WriteBuffer::with_bytes(Vec::new(), 0);
+        buf1.write_nil(); //null
+        buf1.write_bool(false); //false
+        buf1.write_bool(true); //true
+        buf1.write_f32(0.0); //0.0
+        buf1.write_f32(1.0); //1.0
+        buf1.write_f32(5.0); //1.0
+        buf1.write_u8(5);
+        buf1.write_utf8("abc");
+        let mut buf2 = WriteBuffer::with_bytes(Vec::new(), 0);
+        buf2.write_utf8("acc");
+        buf2.write_u8(5);
+        assert_eq!(
+            ReadBuffer::new(buf1.get_byte(), 0) < ReadBuffer::new(buf2.get_byte(), 0),
+            true
+        ); //测试 abc,5 < acc,5
+    }
+
+    #[test]
+    fn test_container_cmp() {
+        // struct xxx { x: bool, y: [&i32]}
+        let mut w1 = WriteBuffer::new();
+        let v = vec![1, 2, 3, 4, 100];
+        w1.write_container(
+            &v,
+            |w1, e| {
+                let hash = 0x12345678u32.to_le_bytes();
+                w1.bytes.extend_from_slice(&hash);
+                w1.tail += 4;
+                w1.write_bool(true);
+                for elem in e {
+                    w1.write_i32(elem.clone());
+                }
+            },
+            None,
+        );
+
+        let mut w2 = WriteBuffer::new();
+        let v2 = vec![1, 2, 3, 4, 99];
+        w2.write_container(
+            &v2,
+            |w2, e| {
+                let hash = 0x12345678u32.to_le_bytes();
+                w2.bytes.extend_from_slice(&hash);
+                w2.tail += 4;
+                w2.write_bool(false);
+                for elem in e {
+                    w2.write_i32(elem.clone());
+                }
+            },
+            None,
+        );
+
+        assert!(w1 > w2);
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Direction {
+        East,
+        South,
+        West = 10,
+        North,
+    }
+
+    impl_int_enum!(Direction { East, South, West, North });
+
+    #[test]
+    fn test_int_enum() -> Result<(), Box<dyn Error>> {
+        for d in [Direction::East, Direction::South, Direction::West, Direction::North] {
+            let mut buf = WriteBuffer::new();
+            d.encode(&mut buf);
+            assert_eq!(buf.get_byte().len(), 1);
+            let mut read_buf = ReadBuffer::new(buf.get_byte(), 0);
+            assert_eq!(Direction::decode(&mut read_buf)?, d);
+        }
+
+        let mut buf = WriteBuffer::new();
+        buf.write_u32(2);
+        let mut read_buf = ReadBuffer::new(buf.get_byte(), 0);
+        assert!(Direction::decode(&mut read_buf).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_atomic() {
+        // 一个u8，之后是一个8位长度的容器，声明的长度超出了实际的数据
+        let bytes = [21, 245, 9, 1, 0, 0, 0, 2];
+        let mut r = ReadBuffer::new(&bytes, 0);
+        assert!(r.read_atomic().is_ok());
+        let (head, len) = (r.head(), r.len());
+        assert!(r.read_atomic().is_err());
+        assert_eq!(r.head(), head);
+        assert_eq!(r.len(), len);
+
+        // 读失败时退还已扣除的分配预算，之后仍可读取预算内的值
+        let s = "a".repeat(100);
+        let mut w = WriteBuffer::new();
+        w.write_container_typed(
+            0x1234,
+            &s,
+            |w, s| {
+                w.write_utf8(s);
+                w.write_raw(&[253]);
+            },
+            None,
+        );
+        w.write_utf8(&s);
+        let mut r = ReadBuffer::new(w.get_byte(), 0).with_total_alloc_budget(150);
+        for _ in 0..3 {
+            assert!(r.read_atomic().is_err());
+        }
+        r.skip_value().unwrap();
+        assert_eq!(r.read_utf8().unwrap(), s);
+    }
+
+    #[test]
+    fn test_cmp_with_epsilon() {
+        let mut w1 = WriteBuffer::new();
+        let mut w2 = WriteBuffer::new();
+        w1.write_f64(5.1);
+        w2.write_f64(5.1005);
+        w1.write_f32(2.0001);
+        w2.write_u8(2);
+        w1.write_f64(-7.5);
+        w2.write_i32(-7);
+        let mut r1 = ReadBuffer::new(w1.get_byte(), 0);
+        let mut r2 = ReadBuffer::new(w2.get_byte(), 0);
+        assert_eq!(
+            partial_cmp_with_epsilon(&mut r1, &mut r2, 0.001),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            partial_cmp_with_epsilon(&mut r1, &mut r2, 0.001),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            partial_cmp_with_epsilon(&mut r1, &mut r2, 0.001),
+            Some(Ordering::Less)
+        );
+
+        let mut r1 = ReadBuffer::new(w1.get_byte(), 0);
+        let mut r2 = ReadBuffer::new(w2.get_byte(), 0);
+        assert_eq!(partial_cmp(&mut r1, &mut r2), Some(Ordering::Less));
+    }
+
+    // 写一个通用数组容器
+    fn write_array(w: &mut WriteBuffer, arr: &Vec<u32>) {
+        w.write_container(
+            arr,
+            |w, arr| {
+                w.bytes.extend_from_slice(&2u32.to_le_bytes());
+                w.tail += 4;
+                w.write_lengthen(arr.len() as u32);
+                for v in arr {
+                    w.write_u32(*v);
+                }
+            },
+            None,
+        );
+    }
+
+    #[test]
+    fn test_container_scoped() -> Result<(), Box<dyn Error>> {
+        let mut w = WriteBuffer::new();
+        write_array(&mut w, &vec![1, 300, 70000, 4, 5]);
+        w.write_bool(true);
+
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        let mut c = r.read_container_scoped()?;
+        assert_eq!(c.type_id(), 2);
+        assert_eq!(c.count(), Some(5));
+        assert_eq!(c.next_element().unwrap()?.read_u32()?, 1);
+        assert_eq!(c.next_element().unwrap()?.read_u32()?, 300);
+        c.finish();
+        assert_eq!(r.read_bool()?, true);
+        assert_eq!(r.len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cell() -> Result<(), Box<dyn Error>> {
+        let mut w = WriteBuffer::new();
+        Cell::new(70000u32).encode(&mut w);
+        RefCell::new("abc".to_string()).encode(&mut w);
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert_eq!(Cell::<u32>::decode(&mut r)?.get(), 70000);
+        assert_eq!(*RefCell::<String>::decode(&mut r)?.borrow(), "abc");
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_schema() -> Result<(), Box<dyn Error>> {
+        let schema = Schema::Tuple(vec![
+            Schema::Int,
+            Schema::Vec(Box::new(Schema::Str)),
+            Schema::Map(Box::new(Schema::Str), Box::new(Schema::Int)),
+        ]);
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), 1u32);
+        map.insert("b".to_string(), 300u32);
+
+        let mut w = WriteBuffer::new();
+        7u32.encode(&mut w);
+        vec!["x".to_string(), "y".to_string()].encode(&mut w);
+        map.encode(&mut w);
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert!(r.matches_schema(&schema)?);
+        assert_eq!(r.len(), 0);
+
+        let mut w = WriteBuffer::new();
+        7u32.encode(&mut w);
+        vec![1u32, 2].encode(&mut w);
+        map.encode(&mut w);
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert!(!r.matches_schema(&schema)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_offsets() -> Result<(), Box<dyn Error>> {
+        let mut w = WriteBuffer::new();
+        w.write_u8(1);
+        assert!(w.value_offsets().is_empty());
+
+        w.set_track_offsets(true);
+        w.write_u32(70000);
+        w.write_utf8("abc");
+        w.write_bool(true);
+        w.write_f64(3.5);
+        w.write_bin(&[1, 2, 3], 0..3);
+
+        let mut offsets = vec![];
+        let mut pos = 1;
+        while pos < w.len() {
+            offsets.push(pos);
+            pos += value_len(&w[pos..])?;
+        }
+        assert_eq!(w.value_offsets(), offsets.as_slice());
+        Ok(())
+    }
+
+    #[test]
+    fn test_container_len_code() {
+        assert_eq!(container_len_code(64), (244, 0));
+        assert_eq!(container_len_code(65), (245, 1));
+        assert_eq!(container_len_code(0xff), (245, 1));
+        assert_eq!(container_len_code(0x100), (246, 2));
+        assert_eq!(container_len_code(0xffff), (246, 2));
+        assert_eq!(container_len_code(0x10000), (247, 4));
+        assert_eq!(container_len_code(0xffffffff), (247, 4));
+        assert_eq!(container_len_code(0x100000000), (248, 6));
+        assert!(std::panic::catch_unwind(|| container_len_code(0x1000000000000)).is_err());
+    }
+
+    #[test]
+    fn test_container_promote() -> Result<(), Box<dyn Error>> {
+        for (size, estimated) in [(60, Some(300)), (70, Some(10)), (300, Some(64)), (70000, None)] {
+            let body: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let mut w = WriteBuffer::new();
+            w.write_u8(7);
+            w.write_container(
+                &body,
+                |w, body| {
+                    w.bytes.extend_from_slice(&9u32.to_le_bytes());
+                    w.tail += 4;
+                    w.write_bin(body, 0..body.len());
+                },
+                estimated,
+            );
+            w.write_bool(true);
+            assert_eq!(w.tail(), w.len());
+
+            let mut r = ReadBuffer::new(w.get_byte(), 0);
+            assert_eq!(r.read_u8()?, 7);
+            let mut c = r.read_container_scoped()?;
+            assert_eq!(c.type_id(), 9);
+            assert_eq!(c.next_element().unwrap()?.read_bin()?, body);
+            assert!(c.next_element().is_none());
+            c.finish();
+            assert_eq!(r.read_bool()?, true);
+
+            // 容器头总是最短的形式，输出与estimated_size无关
+            let (header_len, body_len) = container_header(&w.get_byte()[1..])?;
+            let (code, len_bytes) = container_len_code(body_len as u64);
+            assert_eq!(w.get_byte()[1], code);
+            assert_eq!(header_len, 1 + len_bytes);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_bon_key() {
+        let mut w = WriteBuffer::new();
+        w.set_track_offsets(true);
+        w.write_utf8("b");
+        w.write_u32(300);
+        w.write_utf8("a");
+        w.write_f32(2.5);
+        w.write_nil();
+        let mut offsets = w.value_offsets().to_vec();
+        offsets.push(w.len());
+
+        let mut map = std::collections::BTreeMap::new();
+        for (i, range) in offsets.windows(2).enumerate() {
+            map.insert(BonKey(&w[range[0]..range[1]]), i);
+        }
+        assert_eq!(map.values().cloned().collect::<Vec<_>>(), vec![4, 3, 1, 2, 0]);
+
+        let mut k = WriteBuffer::new();
+        k.write_u64(300);
+        assert_eq!(map.get(&BonKey(k.get_byte())), Some(&1));
+
+        // 无效的键排在有效的键之后，任意键之间的比较满足全序
+        let mut big = vec![249];
+        big.extend_from_slice(&[1; 32]);
+        let mut f128 = vec![8];
+        f128.extend_from_slice(&[0; 16]);
+        let keys: Vec<Vec<u8>> = vec![
+            w[offsets[0]..offsets[1]].to_vec(),
+            w[offsets[1]..offsets[2]].to_vec(),
+            w[offsets[4]..offsets[5]].to_vec(),
+            encode_to_vec(&-5i32),
+            vec![186, 2, 0, 0, 0, 1, 18],
+            big,
+            vec![],
+            vec![38, 1],
+            vec![254],
+            vec![18, 19],
+            f128,
+            vec![16],
+        ];
+        let valid = |k: &Vec<u8>| is_single_value(k).unwrap_or(false) && k[0] != 8;
+        for a in keys.iter() {
+            for b in keys.iter() {
+                let ab = BonKey(a).cmp(&BonKey(b));
+                assert_eq!(ab, BonKey(b).cmp(&BonKey(a)).reverse());
+                if valid(a) && !valid(b) {
+                    assert_eq!(ab, Ordering::Less, "{:?} {:?}", a, b);
+                }
+                for c in keys.iter() {
+                    if ab != Ordering::Greater && BonKey(b).cmp(&BonKey(c)) != Ordering::Greater {
+                        assert_ne!(BonKey(a).cmp(&BonKey(c)), Ordering::Greater);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_any_int() -> Result<(), Box<dyn Error>> {
+        let mut w = WriteBuffer::new();
+        let cases: Vec<(u8, i128)> = vec![
+            (9, -100),
+            (10, -1000),
+            (11, -100000),
+            (12, -(1 << 40)),
+            (13, -(1 << 50)),
+            (14, -(1 << 100)),
+            (15, -1),
+            (16, 0),
+            (35, 19),
+            (36, 200),
+            (37, 1000),
+            (38, 100000),
+            (39, 1 << 40),
+            (40, 1 << 50),
+            (41, 1 << 100),
+        ];
+        for (_, v) in &cases {
+            match *v {
+                v if v < 0 => w.write_i128(v),
+                v if v <= u64::MAX as i128 => w.write_u64(v as u64),
+                v => w.write_u128(v as u128),
+            }
+        }
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        for (t, v) in cases {
+            assert_eq!(r.get_type_chunk()?, t);
+            assert_eq!(r.read_any_int()?, v);
+        }
+
+        let mut w = WriteBuffer::new();
+        w.write_u128(u128::MAX);
+        w.write_utf8("1");
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert!(r.read_any_int().is_err());
+        let mut r = ReadBuffer::new(&w.get_byte()[17..], 0);
+        assert!(r.read_any_int().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_compact_float() -> Result<(), Box<dyn Error>> {
+        let mut w = WriteBuffer::new();
+        w.write_f64(0.0);
+        w.write_f64(1.0);
+        w.write_f64(2.5);
+        assert_eq!(w.len(), 1 + 1 + 9);
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert!(matches!(r.read()?, EnumValue::F32(v) if v == 0.0));
+        assert!(matches!(r.read()?, EnumValue::F32(v) if v == 1.0));
+        assert!(matches!(r.read()?, EnumValue::F64(v) if v == 2.5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_helper() -> Result<(), Box<dyn Error>> {
+        assert_eq!(decode_one::<u32>(&encode_to_vec(&70000u32))?, 70000);
+        assert_eq!(decode_one::<String>(&encode_to_vec(&"abc".to_string()))?, "abc");
+        let v = vec![Some(1i64), None, Some(-300)];
+        assert_eq!(decode_one::<Vec<Option<i64>>>(&encode_to_vec(&v))?, v);
+        let buf = encode_to_buffer(&true);
+        assert_eq!(buf.get_byte(), &vec![2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_small_map() -> Result<(), Box<dyn Error>> {
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), 1u32);
+        map.insert("bb".to_string(), 300u32);
+        map.insert("ccc".to_string(), 70000u32);
+
+        let mut small = WriteBuffer::new();
+        small.write_small_map(&map);
+        small.write_bool(true);
+        let mut standard = WriteBuffer::new();
+        standard.write_container(
+            &map,
+            |w, map| {
+                w.bytes.extend_from_slice(&3u32.to_le_bytes());
+                w.tail += 4;
+                w.write_lengthen(map.len() as u32);
+                for (k, v) in map {
+                    k.encode(w);
+                    v.encode(w);
+                }
+            },
+            Some(30),
+        );
+        standard.write_bool(true);
+        assert_eq!(small.len() + 4, standard.len());
+
+        let mut r = ReadBuffer::new(small.get_byte(), 0);
+        assert_eq!(value_len(&r.bytes)?, small.len() - 1);
+        assert_eq!(r.read_small_map::<String, u32>()?, map);
+        assert_eq!(r.read_bool()?, true);
+        Ok(())
+    }
+
+    mod reordered {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum Level {
+            High = 300,
+            Low = 10,
+            Mid = 20,
+        }
+        impl_int_enum!(Level { High, Low, Mid });
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Level {
+        Low = 10,
+        Mid = 20,
+        High = 300,
+    }
+
+    impl_int_enum!(Level { Low, Mid, High });
+
+    #[test]
+    fn test_int_enum_discriminant() -> Result<(), Box<dyn Error>> {
+        let cases = [
+            (Level::Low, reordered::Level::Low, 10),
+            (Level::Mid, reordered::Level::Mid, 20),
+            (Level::High, reordered::Level::High, 300),
+        ];
+        for (v, reordered, d) in cases {
+            let bytes = encode_to_vec(&v);
+            assert_eq!(decode_one::<u32>(&bytes)?, d);
+            assert_eq!(encode_to_vec(&reordered), bytes);
+            assert_eq!(decode_one::<Level>(&bytes)?, v);
+        }
+        assert!(decode_one::<Level>(&encode_to_vec(&11u32)).is_err());
+        Ok(())
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Mixed {
+        Unit,
+        Tuple(u32),
+        Struct { x: bool },
+    }
+
+    impl_enum!(Mixed {
+        Unit,
+        Tuple(u32),
+        Struct { x: bool },
+    });
+
+    #[test]
+    fn test_enum() -> Result<(), Box<dyn Error>> {
+        assert_eq!(encode_to_vec(&Mixed::Unit), vec![16]);
+        for v in [Mixed::Unit, Mixed::Tuple(300), Mixed::Struct { x: true }] {
+            assert_eq!(decode_one::<Mixed>(&encode_to_vec(&v))?, v);
+        }
+        assert!(decode_one::<Mixed>(&encode_to_vec(&3u32)).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "allocator_api")]
+    #[test]
+    fn test_read_vec_in() -> Result<(), Box<dyn Error>> {
+        use std::alloc::{AllocError, Global, Layout};
+        use std::ptr::NonNull;
+
+        // 记录分配字节数的分配器
+        struct Counting(Cell<usize>);
+        unsafe impl Allocator for &Counting {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.set(self.0.get() + layout.size());
+                Global.allocate(layout)
+            }
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                Global.deallocate(ptr, layout)
+            }
+        }
+
+        let v = vec!["a".to_string(), "bc".to_string()];
+        let bytes = encode_to_vec(&v);
+        let alloc = Counting(Cell::new(0));
+        let mut r = ReadBuffer::new(&bytes, 0);
+        let vec = r.read_vec_in::<String, _>(&alloc)?;
+        assert_eq!(vec.as_slice(), v.as_slice());
+        assert!(alloc.0.get() > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_container() -> Result<(), Box<dyn Error>> {
+        let mut w = WriteBuffer::new();
+        write_array(&mut w, &vec![1, 2]);
+        w.write_u8(3);
+
+        // read直接读出容器
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert!(matches!(r.read_atomic()?, EnumValue::Arr(arr) if arr.len() == 2));
+        assert_eq!(r.read_u8()?, 3);
+        assert_eq!(ReadBonErr::IsContainer(180).is_container(), Some(180));
+        assert_eq!(ReadBonErr::other("x".to_string()).is_container(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_container_value() -> Result<(), ReadBonErr> {
+        let mut w = WriteBuffer::new();
+        w.write_container(
+            &(),
+            |w, _| {
+                w.bytes.extend_from_slice(&77u32.to_le_bytes());
+                w.tail += 4;
+                w.write_i32(-5);
+                w.write_utf8("pi");
+                w.write_container_counted(
+                    2,
+                    2,
+                    false,
+                    &(),
+                    |w, _| {
+                        w.write_bool(true);
+                        w.write_f64(2.5);
+                    },
+                    None,
+                );
+                w.write_container_counted(
+                    3,
+                    1,
+                    false,
+                    &(),
+                    |w, _| {
+                        w.write_u8(1);
+                        w.write_nil();
+                    },
+                    None,
+                );
+            },
+            None,
+        );
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        let st = match r.read()? {
+            EnumValue::Struct(st) => st,
+            v => panic!("not a struct: {:?}", v),
+        };
+        assert!(r.bytes.is_empty());
+        assert_eq!(st.hash, 77);
+        assert_eq!(st.fields.len(), 4);
+        assert!(matches!(st.fields[0].fvalue, EnumValue::I16(-5)));
+        assert!(matches!(&st.fields[1].fvalue, EnumValue::Str(s) if s == "pi"));
+        match &st.fields[2].fvalue {
+            EnumValue::Arr(arr) => {
+                assert!(matches!(arr[..], [EnumValue::Bool(true), EnumValue::F64(v)] if v == 2.5))
+            }
+            v => panic!("not an array: {:?}", v),
+        }
+        match &st.fields[3].fvalue {
+            EnumValue::Map(map) => {
+                assert!(matches!(map[..], [(EnumValue::U8(1), EnumValue::Void)]))
+            }
+            v => panic!("not a map: {:?}", v),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_all() -> Result<(), Box<dyn Error>> {
+        let mut w = WriteBuffer::new();
+        w.write_nil();
+        w.write_bool(true);
+        w.write_i32(-300);
+        w.write_f64(2.5);
+        w.write_utf8("abc");
+        w.write_bin(&[1, 2], 0..2);
+        write_array(&mut w, &vec![7, 8]);
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        let values = r.read_all()?;
+        assert_eq!(
+            format!("{:?}", values),
+            "[Void, Bool(true), I32(-300), F64(2.5), Str(\"abc\"), Bin([1, 2]), Arr([U8(7), U8(8)])]"
+        );
+
+        let mut w = WriteBuffer::new();
+        w.write_u8(1);
+        w.bytes.push(251);
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert!(r.read_all().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_eq() {
+        let mut w1 = WriteBuffer::new();
+        let mut w2 = WriteBuffer::new();
+        w1.write_f32(5.0);
+        w2.write_u8(5);
+        assert_ne!(w1.get_byte(), w2.get_byte());
+        assert!(w1 == w2);
+        assert!(ReadBuffer::new(w1.get_byte(), 0) == ReadBuffer::new(w2.get_byte(), 0));
+        w2.write_u8(6);
+        w1.write_u8(7);
+        assert!(w1 != w2);
+
+        // 无法解析的内容：字节相同时相等，但partial_cmp返回None
+        for bin in [&[255u8][..], &[9], &[6, 0, 0]] {
+            let (r1, r2) = (ReadBuffer::new(bin, 0), ReadBuffer::new(bin, 0));
+            assert!(r1 == r2);
+            assert_eq!(r1.partial_cmp(&r2), None);
+            let w = WriteBuffer::with_bytes(bin.to_vec(), bin.len());
+            assert!(w == w.clone());
+            assert_eq!(w.partial_cmp(&w.clone()), None);
+            let other = ReadBuffer::new(&[1], 0);
+            assert!(r1 != other);
+        }
+    }
+
+    // 生成一个较大的结构，用于比较的性能测试
+    fn bench_eq_buffer() -> WriteBuffer {
+        let mut w = WriteBuffer::new();
+        for i in 0..100 {
+            w.write_u32(i * 1000);
+            w.write_utf8("abcdefgh");
+        }
+        w
+    }
+
+    #[cfg(feature = "bench")]
+    #[bench]
+    fn bench_eq_fast(b: &mut Bencher) {
+        let (w1, w2) = (bench_eq_buffer(), bench_eq_buffer());
+        b.iter(|| assert!(w1 == w2));
+    }
+
+    #[cfg(feature = "bench")]
+    #[bench]
+    fn bench_eq_partial_cmp(b: &mut Bencher) {
+        let (w1, w2) = (bench_eq_buffer(), bench_eq_buffer());
+        b.iter(|| assert_eq!(w1.partial_cmp(&w2), Some(Ordering::Equal)));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Millis(u64);
+
+    impl IntoBon for Millis {
+        fn to_bon_primitive(&self) -> u64 {
+            self.0
+        }
+    }
+
+    impl FromBon for Millis {
+        fn from_bon_primitive(v: u64) -> Self {
+            Millis(v)
+        }
+    }
+
+    impl_bon_primitive!(Millis);
+
+    #[test]
+    fn test_bon_primitive() -> Result<(), Box<dyn Error>> {
+        for v in [0, 19, 300, 1 << 40, u64::MAX] {
+            let bytes = encode_to_vec(&Millis(v));
+            assert_eq!(bytes, encode_to_vec(&v));
+            assert_eq!(decode_one::<Millis>(&bytes)?, Millis(v));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_duplicate_key() -> Result<(), Box<dyn Error>> {
+        let mut w = WriteBuffer::new();
+        2usize.encode(&mut w);
+        "a".to_string().encode(&mut w);
+        1u32.encode(&mut w);
+        "a".to_string().encode(&mut w);
+        2u32.encode(&mut w);
+
+        let map: HashMap<String, u32> = decode_one(w.get_byte())?;
+        assert_eq!(map.len(), 1);
+        assert_eq!(map["a"], 2);
+
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        r.set_strict(true);
+        match HashMap::<String, u32>::decode(&mut r) {
+            Err(ReadBonErr::Other(s)) => assert_eq!(s, "duplicate map key"),
+            r => panic!("unexpected {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lengthen() -> Result<(), Box<dyn Error>> {
+        let cases = [0, 0x7f, 0x80, 0x3fff, 0x4000, 0x1fffffff];
+        let mut w = WriteBuffer::new();
+        for v in cases {
+            w.write_lengthen(v);
+        }
+        assert_eq!(w.len(), 1 + 1 + 2 + 2 + 4 + 4);
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        for v in cases {
+            assert_eq!(r.read_lengthen()?, v);
+        }
+
+        // 旧版本按本机字节序写入：大端机器上写出的与现在相同，可以读出
+        let w_bytes = |v: u32| {
+            let mut w = WriteBuffer::new();
+            w.write_lengthen(v);
+            w.get_byte().to_vec()
+        };
+        for v in [0x80u32, 0x3fff] {
+            let old = ((0x8000 + v) as u16).to_be_bytes();
+            assert_eq!(w_bytes(v), old);
+            assert_eq!(ReadBuffer::new(&old, 0).read_lengthen()?, v);
+        }
+        for v in [0x4000u32, 0x1fffffff] {
+            let old = (0xC0000000 + v).to_be_bytes();
+            assert_eq!(w_bytes(v), old);
+            assert_eq!(ReadBuffer::new(&old, 0).read_lengthen()?, v);
+        }
+        // 小端机器上写出的标志位不在第一个字节，无法识别为多字节长度，不会读出原值
+        let old = ((0x8000 + 0x100u32) as u16).to_le_bytes();
+        assert_ne!(ReadBuffer::new(&old, 0).read_lengthen()?, 0x100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_container_counted() -> Result<(), Box<dyn Error>> {
+        for count in [1u32, 200] {
+            let arr: Vec<u32> = (0..count).collect();
+            let mut w = WriteBuffer::new();
+            w.write_container_counted(
+                2,
+                count,
+                true,
+                &arr,
+                |w, arr| arr.iter().for_each(|v| w.write_u32(*v)),
+                None,
+            );
+            let (header_len, _) = container_header(w.get_byte())?;
+            // 第一个元素在容器体中的偏移固定为8
+            let mut first = ReadBuffer::new(&w[header_len + 8..], 0);
+            assert_eq!(first.read_u32()?, 0);
+
+            let mut r = ReadBuffer::new(w.get_byte(), 0);
+            let mut c = r.read_container_scoped()?;
+            assert_eq!(c.count(), Some(count));
+            let mut n = 0;
+            while let Some(elem) = c.next_element() {
+                assert_eq!(elem?.read_u32()?, n);
+                n += 1;
+            }
+            assert_eq!(n, count);
+        }
+
+        let mut w = WriteBuffer::new();
+        w.write_container_counted(2, 1, false, &(), |w, _| w.write_u8(0), None);
+        let (header_len, _) = container_header(w.get_byte())?;
+        assert_eq!(w.len(), header_len + 4 + 1 + 1);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "container element count mismatch")]
+    fn test_container_count_mismatch() {
+        let mut w = WriteBuffer::new();
+        // 声明3个元素，实际只写了2个
+        w.write_container_counted(
+            2,
+            3,
+            false,
+            &(),
+            |w, _| {
+                w.write_u32(1);
+                w.write_u32(2);
+            },
+            None,
+        );
+    }
+
+    #[test]
+    fn test_bin_writer() -> Result<(), Box<dyn Error>> {
+        use std::io::Write;
+        for size in [0usize, 3, 64, 65, 300, 70000] {
+            let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let mut w = WriteBuffer::new();
+            w.write_u8(1);
+            {
+                let mut bw = w.bin_writer();
+                for chunk in data.chunks(100) {
+                    bw.write_all(chunk)?;
+                }
+            }
+            w.write_u8(2);
+            assert_eq!(w.tail(), w.len());
+
+            let mut expect = WriteBuffer::new();
+            expect.write_u8(1);
+            expect.write_bin(&data, 0..size);
+            expect.write_u8(2);
+            assert_eq!(w.get_byte(), expect.get_byte());
+
+            let mut r = ReadBuffer::new(w.get_byte(), 0);
+            assert_eq!(r.read_u8()?, 1);
+            assert_eq!(r.read_bin()?, data);
+            assert_eq!(r.read_u8()?, 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sized() -> Result<(), Box<dyn Error>> {
+        let v = vec!["abc".to_string(), "def".to_string()];
+        let mut w = WriteBuffer::new();
+        w.write_sized(&v);
+        w.write_sized(&300u32);
+        w.write_bool(true);
+
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert_eq!(r.read_sized::<Vec<String>>()?, v);
+        assert_eq!(r.read_sized::<u32>()?, 300);
+        assert_eq!(r.read_bool()?, true);
+
+        // 只读取头部即可跳过
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        let (type_id, size) = r.peek_container()?;
+        assert_eq!(type_id, 0);
+        r.read_container_scoped()?.finish();
+        assert_eq!(r.head(), size);
+        assert_eq!(r.read_sized::<u32>()?, 300);
+
+        let mut w = WriteBuffer::new();
+        write_array(&mut w, &vec![1]);
+        assert!(decode_one::<u32>(w.get_byte()).is_err());
+        assert!(ReadBuffer::new(w.get_byte(), 0).read_sized::<u32>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_registry() -> Result<(), ReadBonErr> {
+        let mut w = WriteBuffer::new();
+        w.write_container(
+            &(),
+            |w, _| {
+                w.bytes.extend_from_slice(&77u32.to_le_bytes());
+                w.tail += 4;
+                w.write_u32(1);
+                w.write_utf8("bob");
+            },
+            None,
+        );
+
+        let mut registry = SchemaRegistry::new();
+        registry.insert(77, vec!["id".to_string(), "name".to_string()]);
+        let mut r = ReadBuffer::new(w.get_byte(), 0).with_schema_registry(Arc::new(registry));
+        match r.read_all()?.pop() {
+            Some(EnumValue::Struct(s)) => {
+                assert_eq!(s.hash, 77);
+                assert_eq!(s.fields[0].name, "id");
+                assert_eq!(s.fields[1].name, "name");
+                assert!(matches!(s.fields[1].fvalue, EnumValue::Str(ref v) if v == "bob"));
+            }
+            v => panic!("expect struct, got {:?}", v),
+        }
+
+        // 未注册的结构体，字段名为空
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        match r.read_all()?.pop() {
+            Some(EnumValue::Struct(s)) => assert_eq!(s.fields[0].name, ""),
+            v => panic!("expect struct, got {:?}", v),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_raw() -> Result<(), ReadBonErr> {
+        let mut w = WriteBuffer::new();
+        w.write_utf8("hello");
+        write_array(&mut w, &vec![1, 300, 70000]);
+        w.write_utf8(&"a".repeat(100));
+
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        let mut copy = WriteBuffer::new();
+        let mut codes = Vec::new();
+        while r.bytes.len() > 0 {
+            let (t, payload) = r.read_raw()?;
+            codes.push(t);
+            copy.bytes.push(t);
+            copy.bytes.extend_from_slice(payload);
+            copy.tail += 1 + payload.len();
+        }
+        assert_eq!(codes, vec![47, w.get_byte()[6], 107]);
+        assert_eq!(copy.get_byte(), w.get_byte());
+        assert_eq!(r.head(), w.get_byte().len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tagged_int() -> Result<(), ReadBonErr> {
+        let values = [
+            TaggedInt::Signed(5),
+            TaggedInt::Unsigned(5),
+            TaggedInt::Signed(-300),
+            TaggedInt::Unsigned(70000),
+            TaggedInt::Signed(0),
+            TaggedInt::Unsigned(0),
+        ];
+        let mut w = WriteBuffer::new();
+        for v in values {
+            w.write_tagged_int(v);
+        }
+        w.write_u8(9);
+
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        for v in values {
+            assert_eq!(r.read_tagged_int()?, v);
+        }
+        assert_eq!(r.read_u8()?, 9);
+        assert!(ReadBuffer::new(w.get_byte(), 0).read_i32().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_streaming_hash() {
+        let mut w = WriteBuffer::new();
+        w.set_streaming_hash(true);
+        w.write_utf8("hello");
+        write_array(&mut w, &vec![1, 2, 3]);
+        w.write_sized(&vec!["a".to_string(), "b".to_string()]);
+        w.write_u64(u64::MAX);
+        assert_eq!(w.finish_hash(), hash_bytes(w.get_byte()));
+
+        let mut plain = w.clone();
+        plain.set_streaming_hash(false);
+        assert_eq!(plain.finish_hash(), w.finish_hash());
+
+        let v = vec![1u32, 2, 3];
+        let (bytes, hash) = encode_with_hash(&v);
+        assert_eq!(bytes, encode_to_vec(&v));
+        assert_eq!(hash, hash_bytes(&bytes));
+        assert_ne!(hash, hash_bytes(&encode_to_vec(&vec![1u32, 2])));
+    }
+
+    #[test]
+    fn test_result_infallible() -> Result<(), ReadBonErr> {
+        let ok: Result<u32, Infallible> = Ok(300);
+        let bytes = encode_to_vec(&ok);
+        assert_eq!(bytes, encode_to_vec(&Ok::<u32, String>(300)));
+        assert_eq!(decode_one::<Result<u32, Infallible>>(&bytes)?, ok);
+
+        let err: Result<u32, String> = Err("e".to_string());
+        let bytes = encode_to_vec(&err);
+        assert_eq!(decode_one::<Result<u32, String>>(&bytes)?, err);
+        assert!(decode_one::<Result<u32, Infallible>>(&bytes).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff() -> Result<(), ReadBonErr> {
+        let write = |name: &str, scores: &Vec<u32>| {
+            let mut w = WriteBuffer::new();
+            w.write_utf8("head");
+            w.write_container(
+                &(name, scores),
+                |w, (name, scores)| {
+                    w.bytes.extend_from_slice(&9u32.to_le_bytes());
+                    w.tail += 4;
+                    w.write_utf8(name);
+                    write_array(w, scores);
+                },
+                None,
+            );
+            w
+        };
+        let a = write("bob", &vec![1, 2, 3]);
+        let b = write("bob", &vec![1, 7, 3]);
+        assert!(diff(a.get_byte(), a.get_byte())?.is_none());
+        assert!(diff(a.get_byte(), write("bob", &vec![1, 2, 3]).get_byte())?.is_none());
+
+        let d = diff(a.get_byte(), b.get_byte())?.unwrap();
+        assert_eq!(d.path, vec![1, 1, 1]);
+        assert!(matches!(d.left, Some(EnumValue::U8(2))));
+        assert!(matches!(d.right, Some(EnumValue::U8(7))));
+
+        // 元素数量不同，在数组处报告
+        let c = write("bob", &vec![1, 2]);
+        let d = diff(a.get_byte(), c.get_byte())?.unwrap();
+        assert_eq!(d.path, vec![1, 1]);
+        assert!(matches!(d.right, Some(EnumValue::Arr(ref v)) if v.len() == 2));
+
+        // 其中一个更短
+        let d = diff(a.get_byte(), &a.get_byte()[..5])?.unwrap();
+        assert_eq!(d.path, vec![1]);
+        assert!(d.left.is_some() && d.right.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decimal() -> Result<(), ReadBonErr> {
+        let values = [
+            (12345i128, 2u8),
+            (-12345, 2),
+            (0, 0),
+            (-1, 18),
+            (7, 255),
+            (123_456_789_012_345_678_901_234, 10),
+            (-123_456_789_012_345_678_901_234, 30),
+        ];
+        let mut w = WriteBuffer::new();
+        for (m, s) in values {
+            w.write_decimal(m, s);
+        }
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        for v in values {
+            assert_eq!(r.read_decimal()?, v);
+        }
+        assert_eq!(r.head(), w.get_byte().len());
+
+        let d = Decimal {
+            mantissa: -995,
+            scale: 2,
+        };
+        assert_eq!(decode_one::<Decimal>(&encode_to_vec(&d))?, d);
+        assert!(decode_one::<Decimal>(&encode_to_vec(&vec![1u32, 2])).is_err());
+        Ok(())
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Uuid([u8; 16]);
+
+    #[test]
+    fn test_extension() -> Result<(), ReadBonErr> {
+        let mut registry = CodecRegistry::new();
+        registry.register(
+            0xFFFF0001,
+            |v: &Uuid, w| w.write_bin(&v.0, 0..16),
+            |r| {
+                let bin = r.read_bin()?;
+                let arr = bin
+                    .try_into()
+                    .map_err(|_| ReadBonErr::other("uuid must be 16 bytes".to_string()))?;
+                Ok(Uuid(arr))
+            },
+        );
+        assert_eq!(registry.type_id_of::<Uuid>(), Some(0xFFFF0001));
+
+        let id = Uuid([0xab; 16]);
+        let mut w = WriteBuffer::new();
+        w.write_extension(&registry, &id);
+        w.write_u8(3);
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert_eq!(r.peek_container()?.0, 0xFFFF0001);
+        assert_eq!(r.read_extension::<Uuid>(&registry)?, id);
+        assert_eq!(r.read_u8()?, 3);
+
+        // 类型不匹配或未注册
+        let mut w = WriteBuffer::new();
+        w.write_sized(&1u32);
+        assert!(ReadBuffer::new(w.get_byte(), 0)
+            .read_extension::<Uuid>(&registry)
+            .is_err());
+        assert!(ReadBuffer::new(w.get_byte(), 0)
+            .read_extension::<u32>(&registry)
+            .is_err());
+
+        // 注册表可在线程间共享
+        let registry = Arc::new(registry);
+        let r2 = registry.clone();
+        let bytes = std::thread::spawn(move || {
+            let mut w = WriteBuffer::new();
+            w.write_extension(&r2, &Uuid([7; 16]));
+            w.get_byte().clone()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(
+            ReadBuffer::new(&bytes, 0).read_extension::<Uuid>(&registry)?,
+            Uuid([7; 16])
+        );
+        Ok(())
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Login(String);
+
+    #[derive(Debug, PartialEq)]
+    struct Move(i32, i32);
+
+    impl Encode for Login {
+        fn encode(&self, bb: &mut WriteBuffer) {
+            bb.write_container_typed(100, self, |w, v| w.write_utf8(&v.0), None);
+        }
+    }
+
+    impl Decode for Login {
+        fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+            let mut c = bb.read_container_scoped()?;
+            let v = Login(c.body().read_utf8()?);
+            c.finish();
+            Ok(v)
+        }
+    }
+
+    impl Encode for Move {
+        fn encode(&self, bb: &mut WriteBuffer) {
+            bb.write_container_typed(
+                101,
+                self,
+                |w, v| {
+                    w.write_i32(v.0);
+                    w.write_i32(v.1);
+                },
+                None,
+            );
+        }
+    }
+
+    impl Decode for Move {
+        fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+            let mut c = bb.read_container_scoped()?;
+            let v = Move(c.body().read_i32()?, c.body().read_i32()?);
+            c.finish();
+            Ok(v)
+        }
+    }
+
+    #[test]
+    fn test_read_polymorphic() -> Result<(), ReadBonErr> {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Login>(100);
+        registry.register::<Move>(101);
+
+        let mut w = WriteBuffer::new();
+        Move(3, -4).encode(&mut w);
+        Login("pi".to_string()).encode(&mut w);
+        w.write_container_typed(102, &(), |w, _| w.write_u8(0), None);
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        let v = r.read_polymorphic(&registry)?;
+        assert_eq!(v.downcast_ref::<Move>(), Some(&Move(3, -4)));
+        let v = r.read_polymorphic(&registry)?;
+        assert_eq!(*v.downcast::<Login>().unwrap(), Login("pi".to_string()));
+
+        // 未注册的类型
+        let head = r.head;
+        assert!(r.read_polymorphic(&registry).is_err());
+        assert_eq!(r.head, head);
+        Ok(())
+    }
+
+    #[test]
+    fn test_alloc_budget() -> Result<(), ReadBonErr> {
+        let mut w = WriteBuffer::new();
+        for _ in 0..3 {
+            w.write_utf8(&"a".repeat(100));
+        }
+        let mut r = ReadBuffer::new(w.get_byte(), 0).with_total_alloc_budget(250);
+        r.read_utf8()?;
+        r.read_utf8()?;
+        assert!(r.read_utf8().is_err());
+
+        // 预算在容器内的元素间共享
+        let v = vec![vec![0u8; 60]; 4];
+        let bytes = encode_to_vec(&v);
+        let mut r = ReadBuffer::new(&bytes, 0).with_total_alloc_budget(200);
+        assert!(Vec::<Vec<u8>>::decode(&mut r).is_err());
+        let mut r = ReadBuffer::new(&bytes, 0).with_total_alloc_budget(400);
+        assert_eq!(Vec::<Vec<u8>>::decode(&mut r)?, v);
+
+        // HashMap、HashSet、小map按元素大小扣除预算
+        let map: HashMap<u64, u64> = (0..10).map(|i| (i, i)).collect();
+        let set: HashSet<u64> = (0..10).collect();
+        let mut w = WriteBuffer::new();
+        w.write_small_map(&map);
+        type DecodeMap = fn(&mut ReadBuffer) -> Result<HashMap<u64, u64>, ReadBonErr>;
+        let cases: [(Vec<u8>, DecodeMap); 2] = [
+            (encode_to_vec(&map), HashMap::decode),
+            (w.get_byte().clone(), |r| r.read_small_map()),
+        ];
+        for (bytes, decode) in cases {
+            let mut r = ReadBuffer::new(&bytes, 0).with_total_alloc_budget(150);
+            assert!(decode(&mut r).is_err());
+            let mut r = ReadBuffer::new(&bytes, 0).with_total_alloc_budget(160);
+            assert_eq!(decode(&mut r)?, map);
+        }
+        let bytes = encode_to_vec(&set);
+        let mut r = ReadBuffer::new(&bytes, 0).with_total_alloc_budget(75);
+        assert!(HashSet::<u64>::decode(&mut r).is_err());
+        let mut r = ReadBuffer::new(&bytes, 0).with_total_alloc_budget(80);
+        assert_eq!(HashSet::<u64>::decode(&mut r)?, set);
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical() -> Result<(), ReadBonErr> {
+        let mut w = WriteBuffer::new();
+        // 非最短的编码：64位写的小整数、4字节的数量、8位长度描述的短字符串
+        w.bytes.extend_from_slice(&[40, 7, 0, 0, 0, 0, 0, 0, 0]);
+        w.tail += 9;
+        w.write_container_counted(
+            2,
+            2,
+            true,
+            &(),
+            |w, _| {
+                w.write_f64(1.0);
+                w.bytes.extend_from_slice(&[107, 2, b'h', b'i']);
+                w.tail += 4;
+            },
+            Some(0x10000),
+        );
+        w.write_i32(-300);
+        let mut m = WriteBuffer::new();
+        m.write_container_counted(
+            3,
+            5,
+            false,
+            &(),
+            |w, _| {
+                for k in [300u32, 2, 70000, 0, 25] {
+                    w.write_u32(k);
+                    w.write_bool(true);
+                }
+            },
+            None,
+        );
+
+        let canonical = canonicalize(w.get_byte())?;
+        let mut expect = WriteBuffer::new();
+        expect.write_u8(7);
+        let arr = EnumValue::Arr(Arc::new(vec![
+            EnumValue::F32(1.0),
+            EnumValue::Str("hi".to_string()),
+        ]));
+        expect.write_value(&arr);
+        expect.write_i16(-300);
+        assert_eq!(&canonical, expect.get_byte());
+        assert!(canonical.len() < w.get_byte().len());
+
+        let mut via_value = Vec::new();
+        for v in ReadBuffer::new(w.get_byte(), 0).read_all()? {
+            via_value.extend(v.to_canonical_bytes()?);
+        }
+        assert_eq!(via_value, canonical);
+        assert_eq!(canonicalize(&canonical)?, canonical);
+
+        // map的键值对按键的二进制排序
+        let raw_map = canonicalize(m.get_byte())?;
+        assert_eq!(canonicalize(&raw_map)?, raw_map);
+        let mut keys = Vec::new();
+        let mut r = ReadBuffer::new(&raw_map, 0);
+        let mut c = r.read_container_scoped()?;
+        assert_eq!(c.count(), Some(5));
+        while let Some(k) = c.next_element() {
+            keys.push(k?.bytes.to_vec());
+            c.next_element();
+        }
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+
+        // 数据中的元素数量与实际的元素不符，返回Err而不是断言失败
+        assert!(canonicalize(&[187, 3, 0, 0, 0, 20, 18, 21]).is_err());
+        assert!(canonicalize(&[186, 2, 0, 0, 0, 3, 18]).is_err());
+        assert!(content_hash(&[187, 3, 0, 0, 0, 20, 18, 21]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_variant() -> Result<(), ReadBonErr> {
+        let mut w = WriteBuffer::new();
+        w.write_variant(1, |w| w.write_u32(300));
+        w.write_variant(2, |w| {
+            w.write_utf8("name");
+            w.write_bool(true);
+        });
+        w.write_variant(3, |w| w.write_utf8(&"x".repeat(100)));
+        w.write_u8(9);
+        assert_eq!(w.tail(), w.get_byte().len());
+
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            let (tag, mut payload) = r.read_variant()?;
+            match tag {
+                1 => assert_eq!(payload.read_u32()?, 300),
+                2 => {
+                    assert_eq!(payload.read_utf8()?, "name");
+                    assert!(payload.read_bool()?);
+                }
+                3 => assert_eq!(payload.read_utf8()?.len(), 100),
+                _ => panic!("unknown tag {}", tag),
+            }
+            assert!(payload.bytes.is_empty());
+            seen.push(tag);
+        }
+        assert_eq!(seen, vec![1, 2, 3]);
+        assert_eq!(r.read_u8()?, 9);
+        assert!(r.read_variant().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_utf16() -> Result<(), ReadBonErr> {
+        let s = "a中😀𝄞z";
+        let wide: Vec<u16> = s.encode_utf16().collect();
+        assert!(wide.len() > s.chars().count());
+
+        let mut w = WriteBuffer::new();
+        w.write_utf16(&wide);
+        let mut expect = WriteBuffer::new();
+        expect.write_utf8(s);
+        assert_eq!(w.get_byte(), expect.get_byte());
+
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert_eq!(r.read_utf16()?, wide);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_str_bytes() -> Result<(), ReadBonErr> {
+        let long = "长".repeat(100);
+        let mut w = WriteBuffer::new();
+        w.write_utf8("hello");
+        w.write_utf8(&long);
+        w.write_utf8("");
+        w.write_u32(7);
+
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert_eq!(r.read_str_bytes()?, b"hello");
+        assert_eq!(r.read_str_bytes()?, long.as_bytes());
+        assert_eq!(r.read_str_bytes()?, b"");
+        assert!(r.read_str_bytes().is_err());
+        assert_eq!(r.read_u32()?, 7);
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serializer() -> Result<(), ReadBonErr> {
+        use serde::{Serialize, Serializer};
+
+        #[derive(Serialize)]
+        struct Point {
+            x: i32,
+            y: Option<String>,
+        }
+        #[derive(Serialize)]
+        enum Shape {
+            Empty,
+            Circle(u32),
+            Line { from: Point, to: Point },
+        }
+
+        let p = Point {
+            x: -3,
+            y: Some("p".to_string()),
+        };
+        let mut wb = WriteBuffer::new();
+        p.serialize(&mut BonSerializer::new(&mut wb)).unwrap();
+        let mut r = ReadBuffer::new(wb.get_byte(), 0);
+        let mut c = r.read_container_scoped()?;
+        assert_eq!(c.type_id(), type_hash("Point"));
+        assert_eq!(c.next_element().unwrap()?.read_i32()?, -3);
+        assert_eq!(c.next_element().unwrap()?.read_utf8()?, "p");
+        assert!(c.next_element().is_none());
+
+        // 序列、map带元素数量；数量未知的序列回填数量
+        let mut wb = WriteBuffer::new();
+        let mut ser = BonSerializer::new(&mut wb);
+        vec![1u32, 2, 3].serialize(&mut ser).unwrap();
+        let mut m = HashMap::new();
+        m.insert("k".to_string(), 1u8);
+        m.serialize(&mut ser).unwrap();
+        (&mut ser)
+            .collect_seq((0..70u32).filter(|v| v % 2 == 0))
+            .unwrap();
+        (Shape::Empty, Shape::Circle(5))
+            .serialize(&mut ser)
+            .unwrap();
+        Shape::Line {
+            from: Point { x: 0, y: None },
+            to: Point { x: 1, y: None },
+        }
+        .serialize(&mut ser)
+        .unwrap();
+
+        let mut r = ReadBuffer::new(wb.get_byte(), 0);
+        let mut c = r.read_container_scoped()?;
+        assert_eq!((c.type_id(), c.count()), (2, Some(3)));
+        c.finish();
+        let mut c = r.read_container_scoped()?;
+        assert_eq!((c.type_id(), c.count()), (3, Some(1)));
+        c.finish();
+        let mut c = r.read_container_scoped()?;
+        assert_eq!((c.type_id(), c.count()), (2, Some(35)));
+        let mut n = 0;
+        while let Some(e) = c.next_element() {
+            assert_eq!(e?.read_u32()?, n * 2);
+            n += 1;
+        }
+        assert_eq!(n, 35);
+        c.finish();
+        let mut c = r.read_container_scoped()?;
+        assert_eq!(c.next_element().unwrap()?.read_u32()?, 0);
+        let mut elem = c.next_element().unwrap()?;
+        let mut circle = elem.read_container_scoped()?;
+        assert_eq!(circle.type_id(), type_hash("Shape"));
+        assert_eq!(circle.next_element().unwrap()?.read_u32()?, 1);
+        assert_eq!(circle.next_element().unwrap()?.read_u32()?, 5);
+        c.finish();
+        let values = r.read_all()?;
+        assert_eq!(values.len(), 1);
+        assert!(r.bytes.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmp_with_defaults() {
+        let write = |fields: &[u32]| {
+            let mut w = WriteBuffer::new();
+            w.write_container(
+                &fields,
+                |w, fields| {
+                    w.bytes.extend_from_slice(&9u32.to_le_bytes());
+                    w.tail += 4;
+                    for f in fields.iter() {
+                        w.write_u32(*f);
+                    }
+                },
+                None,
+            );
+            w.unwrap()
+        };
+        let mut defaults = WriteBuffer::new();
+        for _ in 0..4 {
+            defaults.write_u32(0);
+        }
+        let defaults = defaults.unwrap();
+
+        let short = write(&[1, 2, 3]);
+        assert_eq!(
+            cmp_with_defaults(&short, &write(&[1, 2, 3, 0]), &defaults),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            cmp_with_defaults(&write(&[1, 2, 3, 0]), &short, &defaults),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            cmp_with_defaults(&short, &write(&[1, 2, 3, 5]), &defaults),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            cmp_with_defaults(&short, &write(&[1, 3]), &defaults),
+            Some(Ordering::Less)
+        );
+        // 没有默认值时，较短的一方较小
+        assert_eq!(
+            cmp_with_defaults(&short, &write(&[1, 2, 3, 0]), &[]),
+            Some(Ordering::Less)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserializer() -> Result<(), ReadBonErr> {
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Point {
+            x: i32,
+            y: Option<String>,
+        }
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        enum Shape {
+            Empty,
+            Circle(u32),
+            Rect(u32, u32),
+            Line { from: Point, to: Point },
+        }
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Scene<'a> {
+            name: &'a str,
+            id: (u64, i128, char),
+            shapes: Vec<Shape>,
+            tags: HashMap<String, f64>,
+            #[serde(with = "serde_bytes_vec")]
+            raw: Vec<u8>,
+            unit: (),
+        }
+        mod serde_bytes_vec {
+            use serde::de::{Deserializer, Visitor};
+
+            pub fn serialize<S: serde::Serializer>(v: &Vec<u8>, s: S) -> Result<S::Ok, S::Error> {
+                s.serialize_bytes(v)
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+                struct BytesVisitor;
+                impl<'de> Visitor<'de> for BytesVisitor {
+                    type Value = Vec<u8>;
+                    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                        f.write_str("bytes")
+                    }
+                    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+                        Ok(v)
+                    }
+                }
+                d.deserialize_byte_buf(BytesVisitor)
+            }
+        }
+
+        let mut tags = HashMap::new();
+        tags.insert("w".to_string(), 0.5);
+        tags.insert("h".to_string(), 1.0);
+        let scene = Scene {
+            name: "scene",
+            id: (u64::MAX, -5, '中'),
+            shapes: vec![
+                Shape::Empty,
+                Shape::Circle(3),
+                Shape::Rect(1, 2),
+                Shape::Line {
+                    from: Point { x: -1, y: None },
+                    to: Point {
+                        x: 300,
+                        y: Some("end".to_string()),
+                    },
+                },
+            ],
+            tags,
+            raw: vec![1, 2, 3],
+            unit: (),
+        };
+        let mut wb = WriteBuffer::new();
+        scene.serialize(&mut BonSerializer::new(&mut wb)).unwrap();
+        wb.write_u8(7);
+
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        let v = Scene::deserialize(&mut BonDeserializer::new(&mut rb))?;
+        assert_eq!(v, scene);
+        assert_eq!(rb.read_u8()?, 7);
+
+        // deserialize_any读取各种浮点数，包括16位浮点数
+        #[derive(Deserialize, Debug, PartialEq)]
+        #[serde(untagged)]
+        enum Num {
+            F(f32),
+        }
+        for (bin, v) in [
+            (&[3u8][..], 0.0f32),
+            (&[4], 1.0),
+            (&[5, 0x00, 0x38], 0.5),
+            (&[6, 0, 0, 0xc0, 0x3f], 1.5),
+        ] {
+            let mut rb = ReadBuffer::new(bin, 0);
+            assert_eq!(
+                Num::deserialize(&mut BonDeserializer::new(&mut rb))?,
+                Num::F(v)
+            );
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive() -> Result<(), ReadBonErr> {
+        #[derive(Debug, PartialEq, Encode, Decode)]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+        #[derive(Debug, PartialEq, Encode, Decode)]
+        struct Pair<T>(T, T);
+        #[derive(Debug, PartialEq, Encode, Decode)]
+        struct Unit;
+        #[derive(Debug, PartialEq, Encode, Decode)]
+        enum Shape<T> {
+            Empty,
+            Circle(Point, u32),
+            Line { from: Point, to: Point, tag: T },
+        }
+
+        let shapes = vec![
+            Shape::Empty,
+            Shape::Circle(Point { x: 1, y: -2 }, 5),
+            Shape::Line {
+                from: Point { x: 0, y: 0 },
+                to: Point { x: 3, y: 4 },
+                tag: "l".to_string(),
+            },
+        ];
+        let mut wb = WriteBuffer::new();
+        shapes.encode(&mut wb);
+        Pair(1.5f64, -2.5).encode(&mut wb);
+        Unit.encode(&mut wb);
+        wb.write_u8(7);
+
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(Vec::<Shape<String>>::decode(&mut rb)?, shapes);
+        assert_eq!(Pair::<f64>::decode(&mut rb)?, Pair(1.5, -2.5));
+        assert_eq!(Unit::decode(&mut rb)?, Unit);
+        assert_eq!(rb.read_u8()?, 7);
+
+        // 类型hash不同时，解码失败
+        let mut wb = WriteBuffer::new();
+        Point { x: 1, y: 2 }.encode(&mut wb);
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(rb.peek_container()?.0, type_hash("Point{x,y}"));
+        assert!(Pair::<i32>::decode(&mut rb).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_repr_int() -> Result<(), ReadBonErr> {
+        #[derive(Debug, Clone, Copy, PartialEq, Encode, Decode)]
+        #[bon(repr = "int")]
+        enum Compass {
+            East,
+            South,
+            West = 10,
+            North,
+        }
+        let cases = [
+            (Compass::East, Direction::East),
+            (Compass::South, Direction::South),
+            (Compass::West, Direction::West),
+            (Compass::North, Direction::North),
+        ];
+        for (v, d) in cases {
+            // 与impl_int_enum!的编码相同，只占一个字节
+            let bytes = encode_to_vec(&v);
+            assert_eq!(bytes.len(), 1);
+            assert_eq!(bytes, encode_to_vec(&d));
+            assert_eq!(decode_one::<Compass>(&bytes)?, v);
+        }
+        assert!(decode_one::<Compass>(&encode_to_vec(&2u32)).is_err());
+
+        // 写入声明的判别值，调整变体的顺序不改变编码
+        mod reordered {
+            use crate::{Decode, Encode};
+            #[derive(Debug, Clone, Copy, PartialEq, Encode, Decode)]
+            #[bon(repr = "int")]
+            pub enum Grade {
+                High = 300,
+                Low = 10,
+                Mid = 20,
+            }
+        }
+        #[derive(Debug, Clone, Copy, PartialEq, Encode, Decode)]
+        #[bon(repr = "int")]
+        enum Grade {
+            Low = 10,
+            Mid = 20,
+            High = 300,
+        }
+        let cases = [
+            (Grade::Low, reordered::Grade::Low, 10),
+            (Grade::Mid, reordered::Grade::Mid, 20),
+            (Grade::High, reordered::Grade::High, 300),
+        ];
+        for (v, reordered, d) in cases {
+            let bytes = encode_to_vec(&v);
+            assert_eq!(decode_one::<u32>(&bytes)?, d);
+            assert_eq!(encode_to_vec(&reordered), bytes);
+            assert_eq!(decode_one::<reordered::Grade>(&bytes)?, reordered);
+            assert_eq!(decode_one::<Grade>(&bytes)?, v);
+        }
+        assert!(decode_one::<Grade>(&encode_to_vec(&11u32)).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_version() -> Result<(), ReadBonErr> {
+        mod v1 {
+            use crate::Encode;
+
+            #[derive(Encode)]
+            #[bon(version = 1)]
+            pub struct Player {
+                pub name: String,
+            }
+        }
+        #[derive(Debug, PartialEq, Encode, Decode)]
+        #[bon(version = 2, migrate = |version, r| match version {
+            1 => Ok(Player { name: r.read_utf8()?, level: 1 }),
+            v => Err(ReadBonErr::other(format!("unknown player version {}", v))),
+        })]
+        struct Player {
+            name: String,
+            level: u32,
+        }
+        #[derive(Debug, PartialEq, Encode, Decode)]
+        #[bon(version = 3)]
+        struct Item(u32);
+
+        let mut wb = WriteBuffer::new();
+        v1::Player {
+            name: "pi".to_string(),
+        }
+        .encode(&mut wb);
+        let current = Player {
+            name: "bon".to_string(),
+            level: 9,
+        };
+        current.encode(&mut wb);
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(
+            Player::decode(&mut rb)?,
+            Player {
+                name: "pi".to_string(),
+                level: 1
+            }
+        );
+        assert_eq!(Player::decode(&mut rb)?, current);
+
+        // 没有迁移函数时，版本不同返回Err
+        let hash = ReadBuffer::new(&encode_to_vec(&Item(5)), 0)
+            .peek_container()?
+            .0;
+        let mut wb = WriteBuffer::new();
+        wb.write_container_typed(
+            hash,
+            &(),
+            |w, _| {
+                w.write_u32(2);
+                w.write_u32(5);
+            },
+            None,
+        );
+        Item(5).encode(&mut wb);
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert!(Item::decode(&mut rb.clone()).is_err());
+        rb.skip_value()?;
+        assert_eq!(Item::decode(&mut rb)?, Item(5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_slice() -> Result<(), ReadBonErr> {
+        let arr = [1u32, 300, 70000, u32::MAX];
+        let slice: &[u32] = &arr[1..];
+        let mut wb = WriteBuffer::new();
+        slice.encode(&mut wb);
+        assert_eq!(wb.get_byte().as_slice(), encode_to_vec(&slice.to_vec()));
+
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(Vec::<u32>::decode(&mut rb)?, vec![300, 70000, u32::MAX]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_strict() -> Result<(), ReadBonErr> {
+        let mut wb = WriteBuffer::new();
+        wb.write_i32(-300);
+        wb.write_i32(-1);
+        wb.write_u32(300);
+        wb.write_u64(u32::MAX as u64 + 1);
+        wb.write_i64(i32::MIN as i64);
+        wb.write_i64(i32::MAX as i64 + 1);
+        let bin = wb.get_byte();
+
+        let mut rb = ReadBuffer::new(bin, 0);
+        match rb.read_u32_strict() {
+            Err(ReadBonErr::TypeNoMatch { act_type, .. }) => assert_eq!(act_type.1, 10),
+            r => panic!("{:?}", r),
+        }
+        // 出错时不消耗数据，宽松读取仍可转换
+        assert_eq!(rb.read_i32()?, -300);
+        assert!(rb.read_u32_strict().is_err());
+        rb.read_i32()?;
+        assert_eq!(rb.read_u32_strict()?, 300);
+        // 超出范围时同样不消耗数据
+        let head = rb.head;
+        assert!(rb.read_u32_strict().is_err());
+        assert_eq!(rb.head, head);
+        assert_eq!(rb.read_u64()?, u32::MAX as u64 + 1);
+        assert_eq!(rb.read_i32_strict()?, i32::MIN);
+        let head = rb.head;
+        assert!(rb.read_i32_strict().is_err());
+        assert_eq!(rb.head, head);
+        assert_eq!(rb.read_i64()?, i32::MAX as i64 + 1);
+        let mut rb = ReadBuffer::new(&[40, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], 0);
+        assert!(rb.read_u32_strict().is_err());
+        assert_eq!((rb.head, rb.len()), (0, 9));
+        assert_eq!(rb.read_u64()?, u64::MAX);
+        Ok(())
+    }
+
+    #[test]
+    fn test_f16() -> Result<(), ReadBonErr> {
+        // 1.0, -2.0, 65504（最大值）, 2^-24（最小的非规格化数）, 无穷大
+        let cases = [
+            (0x3c00u16, 1.0f32),
+            (0xc000, -2.0),
+            (0x7bff, 65504.0),
+            (0x0001, 5.9604645e-8),
+            (0x7c00, f32::INFINITY),
+        ];
+        for (bits, v) in cases {
+            let bin = [5, bits as u8, (bits >> 8) as u8];
+            assert_eq!(ReadBuffer::new(&bin, 0).read_f32()?, v);
+            assert_eq!(ReadBuffer::new(&bin, 0).read_f64()?, v as f64);
+            match ReadBuffer::new(&bin, 0).read()? {
+                EnumValue::F32(r) => assert_eq!(r, v),
+                r => panic!("{:?}", r),
+            }
+            assert_eq!(value_len(&bin)?, 3);
+        }
+        assert!(ReadBuffer::new(&[5, 0, 0x7e], 0).read_f32()?.is_nan());
+
+        // 16位浮点数可以和其它数字比较
+        let half = [5u8, 0x00, 0x38];
+        let mut w = WriteBuffer::new();
+        w.write_f32(1.0);
+        w.write_f64(0.5);
+        let bin = w.get_byte();
+        let mut b1 = ReadBuffer::new(&half, 0);
+        let mut b2 = ReadBuffer::new(bin, 0);
+        assert_eq!(partial_cmp(&mut b1, &mut b2), Some(Ordering::Less));
+        let mut b1 = ReadBuffer::new(&half, 0);
+        assert_eq!(partial_cmp(&mut b2, &mut b1), Some(Ordering::Equal));
+
+        #[cfg(feature = "half")]
+        {
+            let mut w = WriteBuffer::new();
+            for v in [0.0f32, 1.0, -0.375, 1000.0] {
+                w.write_f16(half::f16::from_f32(v));
+            }
+            let mut r = ReadBuffer::new(w.get_byte(), 0);
+            for v in [0.0f32, 1.0, -0.375, 1000.0] {
+                assert_eq!(r.read_f16()?.to_f32(), v);
+            }
+            assert_eq!(w.get_byte()[2], 5);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_ref_writer() -> Result<(), ReadBonErr> {
+        let shared = Arc::new(vec!["a".to_string(), "long shared value".to_string()]);
+        let other = Arc::new(vec!["b".to_string()]);
+        let mut wb = WriteBuffer::new();
+        wb.write_u8(1);
+        let mut rw = RefWriter::new(&mut wb);
+        rw.buffer().write_utf8("p1");
+        rw.write_arc(&shared);
+        rw.buffer().write_utf8("p2");
+        rw.write_arc(&shared);
+        rw.write_arc(&other);
+        let bin = wb.get_byte();
+        // 共享的值只写入一次
+        let once = encode_to_vec(shared.as_ref());
+        assert_eq!(
+            bin.windows(once.len()).filter(|w| *w == &once[..]).count(),
+            1
+        );
+
+        let mut rb = ReadBuffer::new(bin, 0);
+        assert_eq!(rb.read_u8()?, 1);
+        let mut rr = RefReader::new(&bin[1..]);
+        assert_eq!(rr.buffer().read_utf8()?, "p1");
+        let v1 = rr.read_arc::<Vec<String>>()?;
+        assert_eq!(rr.buffer().read_utf8()?, "p2");
+        let v2 = rr.read_arc::<Vec<String>>()?;
+        assert_eq!(v1, shared);
+        assert!(Arc::ptr_eq(&v1, &v2));
+        assert_eq!(rr.read_arc::<Vec<String>>()?, other);
+
+        // 临时的Arc释放后，地址可能被之后的Arc复用，不能被当作同一个值
+        let mut wb = WriteBuffer::new();
+        let mut rw = RefWriter::new(&mut wb);
+        for i in 0..10u32 {
+            rw.write_arc(&Arc::new(i));
+        }
+        let mut rr = RefReader::new(wb.get_byte());
+        for i in 0..10u32 {
+            assert_eq!(*rr.read_arc::<u32>()?, i);
+        }
+
+        // 开启调试对齐时，共享的值仍只解码一次
+        for align in [7, 16, 100] {
+            let mut wb = WriteBuffer::new();
+            wb.set_debug_align(align);
+            wb.write_u8(1);
+            let mut rw = RefWriter::new(&mut wb);
+            rw.write_arc(&shared);
+            rw.buffer().write_utf8("p");
+            rw.write_arc(&shared);
+            let mut rb = ReadBuffer::new(wb.get_byte(), 0).with_debug_padding();
+            assert_eq!(rb.read_u8()?, 1);
+            let mut rr = RefReader::with_reader(rb);
+            let v1 = rr.read_arc::<Vec<String>>()?;
+            assert_eq!(rr.buffer().read_utf8()?, "p");
+            let v2 = rr.read_arc::<Vec<String>>()?;
+            assert_eq!(v1, shared);
+            assert!(Arc::ptr_eq(&v1, &v2));
+        }
+
+        // 引用的值同样受分配预算限制
+        let mut bin = vec![252, 5, 0, 0, 0];
+        bin.extend_from_slice(&encode_to_vec(&"a".repeat(100)));
+        let rb = ReadBuffer::new(&bin, 0).with_total_alloc_budget(50);
+        assert!(RefReader::with_reader(rb).read_arc::<String>().is_err());
+        assert_eq!(*RefReader::new(&bin).read_arc::<String>()?, "a".repeat(100));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_128() -> Result<(), ReadBonErr> {
+        let values = [u64::MAX as u128 + 1, u128::MAX, 1 << 100];
+        let mut wb = WriteBuffer::new();
+        for v in values {
+            wb.write_u128(v);
+            wb.write_u8(7);
+        }
+        wb.write_i128(-(1 << 100));
+        wb.write_i128(i128::MAX);
+        wb.write_u8(8);
+        let bin = wb.get_byte();
+
+        let mut rb = ReadBuffer::new(bin, 0);
+        for v in values {
+            assert_eq!(rb.read_u128()?, v);
+            assert_eq!(rb.read_u8()?, 7);
+        }
+        assert_eq!(rb.read_i128()?, -(1 << 100));
+        assert_eq!(rb.read_i128()?, i128::MAX);
+        assert_eq!(rb.read_u8()?, 8);
+        assert_eq!(rb.head, bin.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmp_explained() -> Result<(), ReadBonErr> {
+        let write = |scores: &[&Vec<u32>]| {
+            let mut w = WriteBuffer::new();
+            w.write_utf8("head");
+            w.write_container(
+                &scores,
+                |w, scores| {
+                    w.bytes.extend_from_slice(&9u32.to_le_bytes());
+                    w.tail += 4;
+                    w.write_utf8("bob");
+                    for s in scores.iter() {
+                        write_array(w, s);
+                    }
+                },
+                None,
+            );
+            w
+        };
+        let a = write(&[&vec![1, 2], &vec![1, 2, 3]]);
+        let b = write(&[&vec![1, 2], &vec![1, 7, 3]]);
+        assert_eq!(
+            cmp_explained(a.get_byte(), a.get_byte())?,
+            (Ordering::Equal, vec![])
+        );
+        assert_eq!(
+            cmp_explained(a.get_byte(), b.get_byte())?,
+            (Ordering::Less, vec![1, 2, 1])
+        );
+        assert_eq!(
+            cmp_explained(b.get_byte(), a.get_byte())?,
+            (Ordering::Greater, vec![1, 2, 1])
+        );
+
+        // 前面的元素都相等时，元素较少的更小
+        let c = write(&[&vec![1, 2], &vec![1, 2]]);
+        assert_eq!(
+            cmp_explained(c.get_byte(), a.get_byte())?,
+            (Ordering::Less, vec![1, 2, 2])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_container_cmp() {
+        // struct xxx { x: bool, y: [u32], z: bool }，只有y中的元素不同
+        let write = |v: &Vec<u32>| {
+            let mut w = WriteBuffer::new();
+            w.write_container(
+                v,
+                |w, v| {
+                    w.bytes.extend_from_slice(&0x12345678u32.to_le_bytes());
+                    w.tail += 4;
+                    w.write_bool(true);
+                    write_array(w, v);
+                    w.write_bool(false);
+                },
+                None,
+            );
+            w
+        };
+        let w1 = write(&vec![1, 2, 3, 4, 100]);
+        let w2 = write(&vec![1, 2, 3, 4, 99]);
+        assert!(w1 > w2);
+        assert!(w1 != w2);
+        assert_eq!(w1.cmp(&write(&vec![1, 2, 3, 4, 100])), Ordering::Equal);
+
+        // 前面的元素都相等时，元素较少的更小
+        let w3 = write(&vec![1, 2, 3, 4]);
+        assert!(w3 < w1);
+        assert!(w1 > w3);
+
+        // 作为BTreeMap的键
+        let mut map = std::collections::BTreeMap::new();
+        map.insert(w1.clone(), 1);
+        map.insert(w2.clone(), 2);
+        map.insert(w3.clone(), 3);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.values().copied().collect::<Vec<_>>(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_base_type_len() -> Result<(), ReadBonErr> {
+        let mut w = WriteBuffer::new();
+        w.write_utf8("hello");
+        w.write_f64(1.5);
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert_eq!(base_type_len(&r, 47)?, 6);
+        r.read_utf8()?;
+        assert_eq!(base_type_len(&r, 7)?, 9);
+        // 类型不符、128位浮点数、保留类型、不完整的长度描述都不会panic
+        assert!(base_type_len(&r, 6).is_err());
+        assert_eq!(base_type_len(&ReadBuffer::new(&[8], 0), 8)?, 17);
+        assert!(base_type_len(&ReadBuffer::new(&[253], 0), 253).is_err());
+        assert!(base_type_len(&ReadBuffer::new(&[108, 1], 0), 108).is_err());
+        assert!(base_type_len(&ReadBuffer::new(&[], 0), 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_big_endian() -> Result<(), ReadBonErr> {
+        let write = |endian: Endian| {
+            let mut w = WriteBuffer::new().with_endianness(endian);
+            w.write_u16(0x1234);
+            w.write_i32(-0x123456);
+            w.write_u64(0x123456789a);
+            w.write_i64(-0x123456789abcde);
+            w.write_u128(u128::MAX - 1);
+            w.write_f32(1.5);
+            w.write_f64(-2.25);
+            w.write_utf8("bon");
+            w
+        };
+        let big = write(Endian::Big);
+        let little = write(Endian::Little);
+        assert_eq!(&big.get_byte()[..3], &[37, 0x12, 0x34]);
+        assert_eq!(&little.get_byte()[..3], &[37, 0x34, 0x12]);
+        // 类型和长度不变，只有数值的字节序不同
+        assert_eq!(big.get_byte().len(), little.get_byte().len());
+
+        let mut r = ReadBuffer::new(big.get_byte(), 0).with_endianness(Endian::Big);
+        assert_eq!(r.read_u16()?, 0x1234);
+        assert_eq!(r.read_i32()?, -0x123456);
+        assert_eq!(r.read_u64()?, 0x123456789a);
+        assert_eq!(r.read_i64()?, -0x123456789abcde);
+        assert_eq!(r.read_u128()?, u128::MAX - 1);
+        assert_eq!(r.read_f32()?, 1.5);
+        assert!(matches!(r.read()?, EnumValue::F64(v) if v == -2.25));
+        assert_eq!(r.read_utf8()?, "bon");
+        assert_eq!(r.head, big.get_byte().len());
+
+        // 字节序不一致时，读出的数值错误
+        let mut r = ReadBuffer::new(big.get_byte(), 0);
+        assert_eq!(r.read_u16()?, 0x3412);
+        assert_ne!(r.read_i32()?, -0x123456);
+        let mut r = ReadBuffer::new(little.get_byte(), 0).with_endianness(Endian::Big);
+        assert_eq!(r.read_u16()?, 0x3412);
+
+        // read_any_int、比较、最短编码和哈希都按各自的字节序读取数值
+        let mut r = ReadBuffer::new(big.get_byte(), 0).with_endianness(Endian::Big);
+        assert_eq!(r.read_any_int()?, 0x1234);
+        assert_eq!(r.read_any_int()?, -0x123456);
+        assert!(big == little);
+        let (mut b1, mut b2) = (
+            WriteBuffer::new().with_endianness(Endian::Big),
+            WriteBuffer::new().with_endianness(Endian::Big),
+        );
+        b1.write_u32(0x01020304);
+        b2.write_u32(0x04030201);
+        assert_eq!(b1.partial_cmp(&b2), Some(Ordering::Less));
+        assert_eq!(b1.cmp(&b2), Ordering::Less);
+        assert_eq!(
+            ReadBuffer::new(b1.get_byte(), 0)
+                .with_endianness(Endian::Big)
+                .read_any_int()?,
+            0x01020304
+        );
+        let mut values = vec![vec![0x01020304u32, 7]];
+        let mut b1 = WriteBuffer::new().with_endianness(Endian::Big);
+        b1.write_sized(&values);
+        let mut l1 = WriteBuffer::new();
+        l1.write_sized(&values);
+        assert_eq!(
+            b1.hash_with::<std::collections::hash_map::DefaultHasher>()?,
+            l1.hash_with::<std::collections::hash_map::DefaultHasher>()?
+        );
+        let (bv, bh) = ReadBuffer::new(b1.get_byte(), 0)
+            .with_endianness(Endian::Big)
+            .read_and_hash()?;
+        let (lv, lh) = ReadBuffer::new(l1.get_byte(), 0).read_and_hash()?;
+        assert_eq!(bh, lh);
+        assert_eq!(bh, content_hash(l1.get_byte())?);
+        assert_eq!(format!("{:?}", bv), format!("{:?}", lv));
+        values[0][0] = 0x04030201;
+        let mut b2 = WriteBuffer::new().with_endianness(Endian::Big);
+        b2.write_sized(&values);
+        assert_ne!(
+            b1.hash_with::<std::collections::hash_map::DefaultHasher>()?,
+            b2.hash_with::<std::collections::hash_map::DefaultHasher>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_container_48bit_header() -> Result<(), ReadBonErr> {
+        // 超过0xffffffff的容器使用48位长度
+        assert_eq!(container_len_code(0xffffffff), (247, 4));
+        assert_eq!(container_len_code(0x1_0000_0000), (248, 6));
+        assert_eq!(container_len_code(0xffffffffffff), (248, 6));
+        let header = [248, 0, 0, 0, 0, 1, 0];
+        assert_eq!(container_header(&header)?, (7, 0x1_0000_0000));
+
+        // 模拟预留了48位长度的容器（预估大小超过0xffffffff），容器体在改写头部后应保持完整
+        let mut w = WriteBuffer::new();
+        w.write_u8(7);
+        w.mark_value();
+        let start = w.bytes.len();
+        w.bytes.resize(start + 7, 0);
+        w.tail += 7;
+        w.depth += 1;
+        w.bytes.extend_from_slice(&2u32.to_le_bytes());
+        w.tail += 4;
+        w.write_lengthen(300);
+        for i in 0..300u32 {
+            w.write_u32(i);
+        }
+        w.end_container((start, 6));
+        w.write_u8(8);
+
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert_eq!(r.read_u8()?, 7);
+        assert_eq!(r.bytes[0], 246);
+        let mut c = r.read_container_scoped()?;
+        assert_eq!(c.count(), Some(300));
+        let mut i = 0;
+        while let Some(e) = c.next_element() {
+            assert_eq!(e?.read_u32()?, i);
+            i += 1;
+        }
+        assert_eq!(i, 300);
+        c.finish();
+        assert_eq!(r.read_u8()?, 8);
+        assert_eq!(w.tail(), w.get_byte().len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_collection() -> Result<(), ReadBonErr> {
+        let mut wb = WriteBuffer::new();
+        vec![3u32, 1, 2, 3, 300].encode(&mut wb);
+        let bin = wb.get_byte();
+
+        let v: Vec<u32> = ReadBuffer::new(bin, 0).read_collection()?;
+        assert_eq!(v, vec![3, 1, 2, 3, 300]);
+        let set: std::collections::HashSet<u32> = ReadBuffer::new(bin, 0).read_collection()?;
+        assert_eq!(set, std::collections::HashSet::from([1, 2, 3, 300]));
+        let set: std::collections::BTreeSet<u32> = ReadBuffer::new(bin, 0).read_collection()?;
+        assert_eq!(set.into_iter().collect::<Vec<_>>(), vec![1, 2, 3, 300]);
+        let v: std::collections::VecDeque<u32> = ReadBuffer::new(bin, 0).read_collection()?;
+        assert_eq!(v.back(), Some(&300));
+
+        // 元素解码失败时返回Err
+        let mut wb = WriteBuffer::new();
+        wb.write_u8(2);
+        wb.write_u32(1);
+        wb.write_utf8("2");
+        assert!(ReadBuffer::new(wb.get_byte(), 0)
+            .read_collection::<Vec<u32>, u32>()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bigint_cmp() {
+        let big = |words: &[(usize, u32)]| {
+            let mut bin = vec![249u8; 33];
+            bin[1..].fill(0);
+            for (i, w) in words {
+                bin[1 + i * 4..5 + i * 4].copy_from_slice(&w.to_le_bytes());
+            }
+            bin
+        };
+        // 低位的字很大，高位的字很小，只有按权重累加时，b才更大
+        let a = big(&[(0, u32::MAX), (1, u32::MAX)]);
+        let b = big(&[(7, 1)]);
+        let c = big(&[(0, 1), (7, 1)]);
+        let cmp = |x: &Vec<u8>, y: &Vec<u8>| {
+            partial_cmp(&mut ReadBuffer::new(x, 0), &mut ReadBuffer::new(y, 0))
+        };
+        assert_eq!(cmp(&a, &b), Some(Ordering::Less));
+        assert_eq!(cmp(&b, &a), Some(Ordering::Greater));
+        assert_eq!(cmp(&b, &c), Some(Ordering::Less));
+        assert_eq!(cmp(&c, &c), Some(Ordering::Equal));
+
+        let mut rb = ReadBuffer::new(&c, 0);
+        let n = to_bigint(&mut rb);
+        assert_eq!(n, (BigInt::from(1) << 224) + 1);
+        assert_eq!(rb.head, 33);
+        assert!(rb.bytes.is_empty());
+    }
+
+    #[test]
+    fn test_read_with_code() -> Result<(), ReadBonErr> {
+        let mut wb = WriteBuffer::new();
+        wb.write_u32(5);
+        wb.write_u32(70000);
+        wb.write_i32(-1);
+        wb.write_utf8("ab");
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert!(matches!(rb.read_with_code()?, (21, EnumValue::U8(5))));
+        assert!(matches!(rb.read_with_code()?, (38, EnumValue::U32(70000))));
+        assert!(matches!(rb.read_with_code()?, (15, EnumValue::I8(-1))));
+        assert!(matches!(rb.read_with_code()?, (44, EnumValue::Str(s)) if s == "ab"));
+        assert!(rb.read_with_code().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_clear() -> Result<(), ReadBonErr> {
+        let mut wb = WriteBuffer::new();
+        wb.write_utf8("first message");
+        wb.write_u32(70000);
+        let capacity = wb.get_byte().capacity();
+        wb.clear();
+        assert!(wb.get_byte().is_empty());
+        assert_eq!(wb.tail(), 0);
+        assert_eq!(wb.get_byte().capacity(), capacity);
+
+        wb.write_u8(7);
+        assert_eq!(wb.get_byte().len(), wb.tail());
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(rb.read_u8()?, 7);
+        assert!(rb.bytes.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_value() -> Result<(), ReadBonErr> {
+        // 新版本的结构体在已知字段之间、之后增加了字段
+        let mut wb = WriteBuffer::new();
+        wb.write_container(
+            &(),
+            |w, _| {
+                w.bytes.extend_from_slice(&9u32.to_le_bytes());
+                w.tail += 4;
+                w.write_u32(1);
+                w.write_f64(0.5);
+                w.write_utf8(&"x".repeat(300));
+                write_array(w, &(0..100).collect());
+                w.write_utf8("known");
+                w.write_bin(&[1, 2, 3], 0..3);
+                w.write_u128(u128::MAX);
+                w.write_nil();
+            },
+            None,
+        );
+        wb.write_u8(7);
+        let bin = wb.get_byte();
+
+        let mut rb = ReadBuffer::new(bin, 0);
+        let mut c = rb.read_container_scoped()?;
+        let body = c.body();
+        assert_eq!(body.read_u32()?, 1);
+        body.skip_value()?;
+        body.skip_value()?;
+        body.skip_value()?;
+        assert_eq!(body.read_utf8()?, "known");
+        while !body.bytes.is_empty() {
+            body.skip_value()?;
+        }
+        assert!(body.skip_value().is_err());
+        c.finish();
+
+        // 整个容器也可以跳过
+        let mut rb = ReadBuffer::new(bin, 0);
+        rb.skip_value()?;
+        assert_eq!(rb.read_u8()?, 7);
+        assert_eq!(rb.head, bin.len());
+
+        // 数据不完整时返回Err，不移动读指针
+        let mut rb = ReadBuffer::new(&bin[..bin.len() - 2], 0);
+        assert!(rb.skip_value().is_err());
+        assert_eq!(rb.head, 0);
+        Ok(())
+    }
+
+    #[cfg(feature = "subtle")]
+    #[test]
+    fn test_read_bin_ct_eq() -> Result<(), ReadBonErr> {
+        let mac = [7u8; 32];
+        let long = vec![9u8; 300];
+        let mut wb = WriteBuffer::new();
+        wb.write_bin(&mac, 0..32);
+        wb.write_bin(&mac, 0..32);
+        wb.write_bin(&mac, 0..32);
+        wb.write_bin(&long, 0..300);
+        wb.write_utf8("mac");
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert!(rb.read_bin_ct_eq(&mac)?);
+        let mut other = mac;
+        other[31] = 8;
+        assert!(!rb.read_bin_ct_eq(&other)?);
+        assert!(!rb.read_bin_ct_eq(&mac[..31])?);
+        assert!(rb.read_bin_ct_eq(&long)?);
+        // 不是二进制数据时返回Err，不移动读指针
+        assert!(rb.read_bin_ct_eq(b"mac").is_err());
+        assert_eq!(rb.read_utf8()?, "mac");
+        Ok(())
+    }
+
+    #[test]
+    fn test_peek_type() -> Result<(), ReadBonErr> {
+        let mut wb = WriteBuffer::new();
+        wb.write_nil();
+        wb.write_bool(true);
+        wb.write_u32(5);
+        wb.write_u32(70000);
+        wb.write_i16(-300);
+        wb.write_u128(u128::MAX);
+        wb.write_f64(0.5);
+        wb.write_utf8(&"x".repeat(100));
+        wb.write_bin(&[1, 2, 3], 0..3);
+        write_array(&mut wb, &vec![1, 2, 3]);
+        wb.write_container(
+            &(),
+            |w, _| {
+                w.bytes.extend_from_slice(&9u32.to_le_bytes());
+                w.tail += 4;
+                w.write_u8(1);
+            },
+            None,
+        );
+        let bin = wb.get_byte();
+
+        let mut rb = ReadBuffer::new(bin, 0);
+        let expect = [
+            EnumType::Void,
+            EnumType::Bool,
+            EnumType::U8,
+            EnumType::U32,
+            EnumType::I32,
+            EnumType::U128,
+            EnumType::F64,
+            EnumType::Str(100),
+            EnumType::Bin(3),
+            EnumType::Arr(3, 9),
+            EnumType::Struct(9),
+        ];
+        for ty in expect {
+            let (bytes, head) = (rb.bytes, rb.head);
+            assert_eq!(rb.peek_type()?, ty);
+            // 不移动读指针
+            assert_eq!((rb.bytes, rb.head), (bytes, head));
+            rb.skip_value()?;
+        }
+        assert!(rb.peek_type().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_frame() -> Result<(), ReadBonErr> {
+        let mut stream = Vec::new();
+        let mut wb = WriteBuffer::new();
+        wb.write_utf8("first");
+        wb.write_u32(1);
+        stream.extend(wb.finish_framed());
+        let mut wb = WriteBuffer::new();
+        vec![1u32, 2, 3].encode(&mut wb);
+        stream.extend(wb.finish_framed());
+
+        let (mut rb, end) = read_frame(&stream)?;
+        assert_eq!(rb.read_utf8()?, "first");
+        assert_eq!(rb.read_u32()?, 1);
+        assert!(rb.bytes.is_empty());
+        let (mut rb, next) = read_frame(&stream[end..])?;
+        assert_eq!(Vec::<u32>::decode(&mut rb)?, vec![1, 2, 3]);
+        assert_eq!(end + next, stream.len());
+
+        // 帧不完整时返回Err
+        assert!(read_frame(&stream[..end - 1]).is_err());
+        assert!(read_frame(&stream[..3]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rel_instant() -> Result<(), ReadBonErr> {
+        let epoch = Instant::now();
+        let instant = epoch + Duration::from_millis(1500) + Duration::from_nanos(7);
+        let rel = RelInstant::from_instant(instant, epoch);
+        assert_eq!(rel.since_epoch_nanos, 1_500_000_007);
+
+        let bin = encode_to_vec(&rel);
+        let decoded: RelInstant = decode_one(&bin)?;
+        assert_eq!(decoded, rel);
+        assert_eq!(decoded.to_instant(epoch), instant);
+
+        // 早于起点的时间为0
+        let before = RelInstant::from_instant(epoch, instant);
+        assert_eq!(before.to_instant(epoch), epoch);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_str() -> Result<(), ReadBonErr> {
+        let long = "字符串".repeat(100);
+        let mut wb = WriteBuffer::new();
+        wb.write_utf8("abc");
+        wb.write_utf8(&long);
+        wb.write_bin(&[0xff, 0xfe], 0..2);
+        let bin = wb.get_byte();
+
+        let mut rb = ReadBuffer::new(bin, 0);
+        let s = rb.read_str()?;
+        assert_eq!(s, "abc");
+        // 借用原始的二进制
+        assert_eq!(s.as_ptr(), bin[1..].as_ptr());
+        assert_eq!(rb.read_str()?, long);
+        assert!(rb.read_str().is_err());
+
+        // 不合法的UTF-8返回Err，不移动读指针
+        let invalid = [44u8, 0xff, 0xfe, 7];
+        let mut rb = ReadBuffer::new(&invalid, 0);
+        assert!(rb.read_str().is_err());
+        assert_eq!(rb.head, 0);
+        assert_eq!(rb.read_str_bytes()?, &[0xff, 0xfe]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_utf8_strict() -> Result<(), ReadBonErr> {
+        let mut wb = WriteBuffer::new();
+        wb.write_utf8("中文");
+        assert_eq!(
+            ReadBuffer::new(wb.get_byte(), 0).read_utf8_strict()?,
+            "中文"
+        );
+
+        // "中"的3字节编码被截断为2字节
+        let truncated = [44u8, 0xe4, 0xb8];
+        let mut rb = ReadBuffer::new(&truncated, 0);
+        assert!(rb.read_utf8_strict().is_err());
+        assert_eq!(rb.head, 0);
+        assert_eq!(rb.read_utf8()?, "\u{FFFD}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_entries() -> Result<(), ReadBonErr> {
+        let mut wb = WriteBuffer::new();
+        wb.write_container_counted(
+            3,
+            3,
+            false,
+            &(),
+            |w, _| {
+                w.write_utf8("a");
+                w.write_u32(1);
+                w.write_utf8("b");
+                write_array(w, &vec![2, 3]);
+                w.write_u32(3);
+                w.write_utf8("c");
+            },
+            None,
+        );
+        wb.write_u8(7);
+        let bin = wb.get_byte();
+
+        let mut rb = ReadBuffer::new(bin, 0);
+        let entries = rb.map_entries()?;
+        assert_eq!(entries.len(), 3);
+        assert_eq!(rb.read_u8()?, 7);
+
+        // 比较键的二进制，只解码匹配的值
+        let key = encode_to_vec(&"b".to_string());
+        let key = ReadBuffer::new(&key, 0);
+        let (_, mut value) = entries.into_iter().find(|(k, _)| *k == key).unwrap();
+        let mut c = value.read_container_scoped()?;
+        assert_eq!(c.count(), Some(2));
+        assert_eq!(c.body().read_u32()?, 2);
+
+        // 不是map时返回Err
+        let mut wb = WriteBuffer::new();
+        write_array(&mut wb, &vec![1, 2]);
+        assert!(ReadBuffer::new(wb.get_byte(), 0).map_entries().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_bin_ref() -> Result<(), ReadBonErr> {
+        let big = vec![7u8; 1000];
+        let mut wb = WriteBuffer::new();
+        wb.write_bin(&[1, 2, 3], 0..3);
+        wb.write_bin(&big, 0..1000);
+        wb.write_u8(9);
+        let bin = wb.get_byte();
+
+        let mut rb = ReadBuffer::new(bin, 0);
+        let small = rb.read_bin_ref()?;
+        assert_eq!(small, &[1, 2, 3]);
+        // 借用原始的二进制
+        assert_eq!(small.as_ptr(), bin[1..].as_ptr());
+        assert_eq!(rb.head, 4);
+        assert_eq!(rb.read_bin_ref()?, &big[..]);
+        assert_eq!(rb.head, bin.len() - 1);
+        assert!(rb.read_bin_ref().is_err());
+        assert_eq!(rb.read_u8()?, 9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tuple() -> Result<(), ReadBonErr> {
+        let t = (7u32, "pi".to_string(), true);
+        let v: Vec<(u16, String)> = vec![(1, "a".to_string()), (300, "bc".to_string())];
+        let mut wb = WriteBuffer::new();
+        t.encode(&mut wb);
+        v.encode(&mut wb);
+        (
+            1u8, 2u8, 3u8, 4u8, 5u8, 6u8, 7u8, 8u8, 9u8, 10u8, 11u8, 12u8,
+        )
+            .encode(&mut wb);
+
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(<(u32, String, bool)>::decode(&mut rb)?, t);
+        assert_eq!(Vec::<(u16, String)>::decode(&mut rb)?, v);
+        assert_eq!(
+            <(u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8)>::decode(&mut rb)?,
+            (1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12)
+        );
+        assert!(rb.bytes.is_empty());
+
+        // 与依次写入各元素相同
+        let mut w2 = WriteBuffer::new();
+        w2.write_u32(7);
+        w2.write_utf8("pi");
+        w2.write_bool(true);
+        assert_eq!(&wb.get_byte()[..w2.len()], w2.get_byte());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_u64_hint() -> Result<(), ReadBonErr> {
+        for v in [
+            0u64,
+            19,
+            20,
+            255,
+            256,
+            0xFFFF,
+            0xFFFFFFFF,
+            0x1_0000_0000,
+            u64::MAX,
+        ] {
+            let mut w = WriteBuffer::new();
+            w.write_u64(v);
+            for likely_small in [true, false] {
+                let mut h = WriteBuffer::new();
+                h.write_u64_hint(v, likely_small);
+                assert_eq!(h.get_byte(), w.get_byte());
+                assert_eq!(ReadBuffer::new(h.get_byte(), 0).read_u64()?, v);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixed_array() -> Result<(), ReadBonErr> {
+        let hash = [0xabu8; 16];
+        let arr = [1u32, 300, 70000, u32::MAX];
+        let mut wb = WriteBuffer::new();
+        hash.encode(&mut wb);
+        arr.encode(&mut wb);
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(<[u8; 16]>::decode(&mut rb)?, hash);
+        assert_eq!(<[u32; 4]>::decode(&mut rb)?, arr);
+
+        // 与Vec<T>互相解码
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(Vec::<u8>::decode(&mut rb)?, hash.to_vec());
+        let mut wb = WriteBuffer::new();
+        vec![1u32, 2, 3].encode(&mut wb);
+        assert!(<[u32; 4]>::decode(&mut ReadBuffer::new(wb.get_byte(), 0)).is_err());
+        assert_eq!(
+            <[u32; 3]>::decode(&mut ReadBuffer::new(wb.get_byte(), 0))?,
+            [1, 2, 3]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_struct_value() -> Result<(), ReadBonErr> {
+        let mut wb = WriteBuffer::new();
+        wb.write_container_typed(
+            77,
+            &(),
+            |w, _| {
+                w.write_u32(7);
+                w.write_utf8("pi");
+                w.write_bool(true);
+            },
+            None,
+        );
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        let st = rb.read_struct_value(&["id", "name"])?;
+        assert_eq!(st.hash, 77);
+        assert_eq!(st.fields.len(), 3);
+        assert!(matches!(st.get("id"), Some(EnumValue::U8(7))));
+        assert!(matches!(st.get("name"), Some(EnumValue::Str(s)) if s == "pi"));
+        assert!(st.get("age").is_none());
+        // 多出的元素名字为空
+        assert!(matches!(st.get(""), Some(EnumValue::Bool(true))));
+        assert!(rb.bytes.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_set() -> Result<(), ReadBonErr> {
+        let mut rng = thread_rng();
+        let set: HashSet<u32> = (0..100).map(|_| rng.gen()).collect();
+        let mut wb = WriteBuffer::new();
+        set.encode(&mut wb);
+        assert_eq!(
+            HashSet::<u32>::decode(&mut ReadBuffer::new(wb.get_byte(), 0))?,
+            set
+        );
+
+        // 重复的元素，默认合并，严格模式下返回Err
+        let mut wb = WriteBuffer::new();
+        vec![1u32, 2, 1].encode(&mut wb);
+        let dup = HashSet::<u32>::decode(&mut ReadBuffer::new(wb.get_byte(), 0))?;
+        assert_eq!(dup, HashSet::from([1, 2]));
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        rb.set_strict(true);
+        assert!(HashSet::<u32>::decode(&mut rb).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_container_padding() -> Result<(), ReadBonErr> {
+        let mut wb = WriteBuffer::new();
+        wb.write_container_counted(
+            2,
+            2,
+            false,
+            &(),
+            |w, _| {
+                w.write_u32(1);
+                w.write_u32(2);
+            },
+            None,
+        );
+        let mut outer = WriteBuffer::new();
+        outer.write_container_counted(2, 1, false, &(), |w, _| w.write_raw(wb.get_byte()), None);
+        assert!(validate(wb.get_byte()).is_ok());
+        assert!(validate(outer.get_byte()).is_ok());
+
+        // 声明的长度比元素实际占用的多2个字节
+        let mut padded = wb.get_byte().to_vec();
+        padded[0] += 2;
+        padded.extend_from_slice(&[0xde, 0xad]);
+        let padding = |r: Result<(), ReadBonErr>| matches!(r, Err(ReadBonErr::Other(s)) if s == "container has trailing padding");
+        assert!(padding(validate(&padded)));
+        let mut outer = WriteBuffer::new();
+        outer.write_container_typed(77, &(), |w, _| w.write_raw(&padded), None);
+        assert!(padding(validate(outer.get_byte())));
+
+        // 非严格模式下跳过填充，严格模式下返回Err
+        let mut rb = ReadBuffer::new(&padded, 0);
+        assert!(rb.read_container_scoped().is_ok());
+        rb.set_strict(true);
+        assert!(padding(rb.read_container_scoped().map(|_| ())));
+        Ok(())
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Node {
+        v: u32,
+        next: Option<Box<Node>>,
+    }
+
+    impl Encode for Node {
+        fn encode(&self, bb: &mut WriteBuffer) {
+            self.v.encode(bb);
+            self.next.encode(bb);
+        }
+    }
+
+    impl Decode for Node {
+        fn decode(bb: &mut ReadBuffer) -> Result<Self, ReadBonErr> {
+            Ok(Node {
+                v: u32::decode(bb)?,
+                next: Option::decode(bb)?,
+            })
+        }
+    }
+
+    #[test]
+    fn test_smart_pointer() -> Result<(), ReadBonErr> {
+        let b = Box::new("pi".to_string());
+        let a = Arc::new(vec![1u32, 300, 70000]);
+        let r = Rc::new(7i8);
+        let mut wb = WriteBuffer::new();
+        b.encode(&mut wb);
+        a.encode(&mut wb);
+        r.encode(&mut wb);
+        // 与直接写入内部的值相同
+        let mut plain = WriteBuffer::new();
+        plain.write_utf8("pi");
+        a.as_slice().encode(&mut plain);
+        7i8.encode(&mut plain);
+        assert_eq!(wb.get_byte(), plain.get_byte());
+
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(Box::<String>::decode(&mut rb)?, b);
+        assert_eq!(Arc::<Vec<u32>>::decode(&mut rb)?, a);
+        assert_eq!(Rc::<i8>::decode(&mut rb)?, r);
+
+        let list = Node {
+            v: 1,
+            next: Some(Box::new(Node {
+                v: 2,
+                next: Some(Box::new(Node { v: 3, next: None })),
+            })),
+        };
+        let mut wb = WriteBuffer::new();
+        list.encode(&mut wb);
+        // 每个节点只有一个值和下一个节点（或None），没有多余的标记
+        assert_eq!(wb.get_byte(), &[17, 18, 19, 0]);
+        assert_eq!(Node::decode(&mut ReadBuffer::new(wb.get_byte(), 0))?, list);
+        Ok(())
+    }
+
+    #[test]
+    fn test_coords() -> Result<(), ReadBonErr> {
+        let mut rng = thread_rng();
+        let coords: Vec<[f64; 2]> = (0..10000)
+            .map(|_| [rng.gen_range(-180.0..180.0), rng.gen_range(-90.0..90.0)])
+            .collect();
+        let mut wb = WriteBuffer::new();
+        wb.write_coords(&coords);
+        // 数量3字节 + 二进制头5字节 + 每个坐标16字节
+        assert_eq!(wb.len(), 3 + 5 + 10000 * 16);
+        let mut generic = WriteBuffer::new();
+        coords.encode(&mut generic);
+        assert!(generic.len() > wb.len());
+
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(rb.read_coords()?, coords);
+        assert!(rb.bytes.is_empty());
+
+        // 数量与数据长度不符
+        let mut wb = WriteBuffer::new();
+        wb.write_u64(2);
+        wb.write_bin(&[0; 16], 0..16);
+        assert!(ReadBuffer::new(wb.get_byte(), 0).read_coords().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmp_to_value() -> Result<(), ReadBonErr> {
+        let mut wb = WriteBuffer::new();
+        wb.write_u8(7);
+        wb.write_u16(300);
+        wb.write_u64(u64::MAX);
+        wb.write_u128(u128::MAX);
+        wb.write_i32(-1);
+        wb.write_i64(i64::MIN + 1);
+        wb.write_i128(i128::MIN + 1);
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(cmp_to_u64(&mut rb, 7)?, Ordering::Equal);
+        assert_eq!(cmp_to_u64(&mut rb, 255)?, Ordering::Greater);
+        assert_eq!(cmp_to_u64(&mut rb, u64::MAX)?, Ordering::Equal);
+        assert_eq!(cmp_to_u64(&mut rb, u64::MAX)?, Ordering::Greater);
+        assert_eq!(cmp_to_u64(&mut rb, 0)?, Ordering::Less);
+        assert_eq!(cmp_to_u64(&mut rb, 0)?, Ordering::Less);
+        assert_eq!(cmp_to_u64(&mut rb, 0)?, Ordering::Less);
+
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(cmp_to_i64(&mut rb, -7)?, Ordering::Greater);
+        assert_eq!(cmp_to_i64(&mut rb, 301)?, Ordering::Less);
+        assert_eq!(cmp_to_i64(&mut rb, i64::MAX)?, Ordering::Greater);
+        assert_eq!(cmp_to_i64(&mut rb, i64::MAX)?, Ordering::Greater);
+        assert_eq!(cmp_to_i64(&mut rb, -1)?, Ordering::Equal);
+        assert_eq!(cmp_to_i64(&mut rb, i64::MIN + 1)?, Ordering::Equal);
+        assert_eq!(cmp_to_i64(&mut rb, i64::MIN)?, Ordering::Less);
+
+        let mut wb = WriteBuffer::new();
+        wb.write_f32(1.5);
+        wb.write_f64(-2.25);
+        wb.write_f64(0.0);
+        wb.write_i16(-3);
+        wb.write_f64(f64::NAN);
+        wb.write_utf8("abc");
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(cmp_to_f64(&mut rb, 1.5)?, Ordering::Equal);
+        assert_eq!(cmp_to_f64(&mut rb, -2.0)?, Ordering::Less);
+        assert_eq!(cmp_to_f64(&mut rb, -0.5)?, Ordering::Greater);
+        assert_eq!(cmp_to_f64(&mut rb, -2.5)?, Ordering::Less);
+        assert!(cmp_to_f64(&mut rb, 0.0).is_err());
+        // 类型不符
+        assert!(cmp_to_u64(&mut rb.clone(), 0).is_err());
+        assert_eq!(cmp_to_str(&mut rb.clone(), "abc")?, Ordering::Equal);
+        assert_eq!(cmp_to_str(&mut rb.clone(), "abd")?, Ordering::Less);
+        assert_eq!(cmp_to_str(&mut rb.clone(), "ab")?, Ordering::Greater);
+        Ok(())
+    }
+
+    #[test]
+    fn test_char() -> Result<(), ReadBonErr> {
+        let mut wb = WriteBuffer::new();
+        'a'.encode(&mut wb);
+        '🦀'.encode(&mut wb);
+        wb.write_u32(0xD800);
+        wb.write_u32(0x110000);
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(char::decode(&mut rb)?, 'a');
+        assert_eq!(char::decode(&mut rb)?, '🦀');
+        assert!(char::decode(&mut rb).is_err());
+        assert!(char::decode(&mut rb).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_duration() -> Result<(), ReadBonErr> {
+        let durations = [
+            Duration::ZERO,
+            Duration::from_nanos(1),
+            Duration::from_millis(250),
+            Duration::new(90, 5),
+            Duration::new(u64::MAX, 999_999_999),
+        ];
+        let mut wb = WriteBuffer::new();
+        for d in durations.iter() {
+            d.encode(&mut wb);
+        }
+        wb.write_u64(1);
+        wb.write_u32(1_000_000_000);
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        for d in durations.iter() {
+            assert_eq!(Duration::decode(&mut rb)?, *d);
+        }
+        assert!(Duration::decode(&mut rb).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_enum_value_map() -> Result<(), ReadBonErr> {
+        // 键不按顺序写入
+        let mut wb = WriteBuffer::new();
+        wb.write_container_counted(
+            3,
+            3,
+            false,
+            &(),
+            |w, _| {
+                for (k, v) in [("b", 2u32), ("c", 3), ("a", 1)] {
+                    w.write_utf8(k);
+                    w.write_u32(v);
+                }
+            },
+            None,
+        );
+        let value = ReadBuffer::new(wb.get_byte(), 0).read_all()?.pop().unwrap();
+        let keys: Vec<&str> = match &value {
+            EnumValue::Map(entries) => entries
+                .iter()
+                .map(|(k, _)| match k {
+                    EnumValue::Str(s) => s.as_str(),
+                    _ => panic!("key is not string"),
+                })
+                .collect(),
+            _ => panic!("not a map"),
+        };
+        assert_eq!(keys, ["b", "c", "a"]);
+
+        let map = value.to_hashmap().unwrap();
+        let key = EnumValue::Str("c".to_string()).to_canonical_bytes()?;
+        assert!(matches!(map.get(&key), Some(EnumValue::U8(3))));
+        assert!(EnumValue::Void.to_hashmap().is_none());
+
+        // 最短编码按键排序，排序后的map读出再写入，结果不变
+        let canonical = value.to_canonical_bytes()?;
+        let value = ReadBuffer::new(&canonical, 0).read_all()?.pop().unwrap();
+        assert_eq!(value.to_canonical_bytes()?, canonical);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_typed() -> Result<(), ReadBonErr> {
+        let mut wb = WriteBuffer::new();
+        wb.write_nil();
+        wb.write_bool(true);
+        wb.write_f32(1.5);
+        wb.write_i64(-300);
+        wb.write_u128(u128::MAX);
+        wb.write_utf8("pi");
+        wb.write_bin(&[1, 2], 0..2);
+        wb.write_container_counted(
+            2,
+            2,
+            false,
+            &(),
+            |w, _| {
+                w.write_u8(7);
+                w.write_utf8("x");
+            },
+            None,
+        );
+        wb.write_u8(9);
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert!(matches!(rb.read_typed()?, BonType::Null));
+        assert!(matches!(rb.read_typed()?, BonType::Bool(true)));
+        assert!(matches!(rb.read_typed()?, BonType::Float(f) if f == 1.5));
+        assert!(matches!(rb.read_typed()?, BonType::Int(-300)));
+        assert!(matches!(rb.read_typed()?, BonType::UInt(u128::MAX)));
+        assert!(matches!(rb.read_typed()?, BonType::Str("pi")));
+        assert!(matches!(rb.read_typed()?, BonType::Bin(&[1, 2])));
+        match rb.read_typed()? {
+            BonType::Container {
+                type_id,
+                count,
+                len,
+                mut body,
+            } => {
+                assert_eq!((type_id, count, len), (2, Some(2), 8));
+                assert!(matches!(body.read_typed()?, BonType::Int(7)));
+                assert!(matches!(body.read_typed()?, BonType::Str("x")));
+                assert!(body.bytes.is_empty());
+            }
+            v => panic!("not a container: {:?}", v),
+        }
+        assert!(matches!(rb.read_typed()?, BonType::Int(9)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_at_end() -> Result<(), ReadBonErr> {
+        let mut wb = WriteBuffer::new();
+        wb.write_utf8("front");
+        let front_len = wb.len();
+        wb.write_u16(300);
+        // 尾部为定长的小端u32，记录第二个值的位置
+        wb.bytes
+            .extend_from_slice(&(front_len as u32).to_le_bytes());
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        let offset = rb.read_u32_at_end()? as usize;
+        assert_eq!(offset, front_len);
+        assert_eq!(rb.read_at(offset, 3)?, &[37, 44, 1]);
+        assert_eq!(rb.read_utf8()?, "front");
+
+        // 已读过的部分和越界
+        assert!(rb.read_at(0, 1).is_err());
+        assert!(rb.read_at(offset, 8).is_err());
+        assert!(rb.read_at(offset, usize::MAX).is_err());
+        assert_eq!(rb.read_u16()?, 300);
+        assert_eq!(rb.read_u32_at_end()? as usize, front_len);
+        assert!(ReadBuffer::new(&[1, 2, 3], 0).read_u32_at_end().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_enum_value() -> Result<(), ReadBonErr> {
+        let mut w = WriteBuffer::new();
+        w.write_container_typed(
+            77,
+            &(),
+            |w, _| {
+                w.write_u64(70000);
+                w.write_i16(-3);
+                w.write_f32(1.5);
+                w.write_utf8("pi");
+                w.write_bin(&[1, 2, 3], 0..3);
+                write_array(w, &vec![1, 2]);
+                w.write_container_counted(
+                    3,
+                    2,
+                    false,
+                    &(),
+                    |w, _| {
+                        w.write_utf8("a");
+                        w.write_bool(true);
+                        w.write_utf8("b");
+                        w.write_nil();
+                    },
+                    None,
+                );
+            },
+            None,
+        );
+        let value = EnumValue::decode(&mut ReadBuffer::new(w.get_byte(), 0))?;
+        let mut copy = WriteBuffer::new();
+        value.encode(&mut copy);
+        assert_eq!(
+            ReadBuffer::new(w.get_byte(), 0).partial_cmp(&ReadBuffer::new(copy.get_byte(), 0)),
+            Some(Ordering::Equal)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserved_type() {
+        let bytes = [253u8, 1, 2];
+        let reserved = |r: Result<(), ReadBonErr>| matches!(r, Err(ReadBonErr::ReservedType(253)));
+        assert!(reserved(ReadBuffer::new(&bytes, 0).read().map(|_| ())));
+        assert!(reserved(validate(&bytes)));
+        assert!(reserved(ReadBuffer::new(&bytes, 0).skip_value()));
+        // 已分配但read不支持的类型不是保留类型
+        assert!(matches!(
+            ReadBuffer::new(&[252u8, 0, 0, 0, 0], 0).read(),
+            Err(ReadBonErr::TypeNoMatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_enum_value_builder() -> Result<(), ReadBonErr> {
+        let value = EnumValue::structure(
+            77,
+            vec![
+                ("id", EnumValue::uint(7)),
+                (
+                    "tags",
+                    EnumValue::array(vec![EnumValue::string("a"), EnumValue::int(-300)]),
+                ),
+                (
+                    "attrs",
+                    EnumValue::map(vec![(EnumValue::string("hp"), EnumValue::float(2.5))]),
+                ),
+                ("raw", EnumValue::bin([1u8, 2])),
+                ("none", EnumValue::null()),
+            ],
+        );
+        let mut w = WriteBuffer::new();
+        w.write_value(&value);
+
+        let mut expect = WriteBuffer::new();
+        expect.write_container_typed(
+            77,
+            &(),
+            |w, _| {
+                w.write_u8(7);
+                w.write_container_counted(
+                    2,
+                    2,
+                    false,
+                    &(),
+                    |w, _| {
+                        w.write_utf8("a");
+                        w.write_i16(-300);
+                    },
+                    None,
+                );
+                w.write_container_counted(
+                    3,
+                    1,
+                    false,
+                    &(),
+                    |w, _| {
+                        w.write_utf8("hp");
+                        w.write_f64(2.5);
+                    },
+                    None,
+                );
+                w.write_bin(&[1, 2], 0..2);
+                w.write_nil();
+            },
+            None,
+        );
+        assert_eq!(w.get_byte(), expect.get_byte());
+        Ok(())
+    }
+
+    #[test]
+    fn test_array_iter() -> Result<(), ReadBonErr> {
+        let arr = [1i32, -2, 300, -70000, i32::MAX];
+        let mut w = WriteBuffer::new();
+        w.write_container_counted(
+            2,
+            arr.len() as u32,
+            false,
+            &arr,
+            |w, arr| arr.iter().for_each(|v| w.write_i32(*v)),
+            None,
+        );
+        w.write_u8(9);
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        let mut n = 0;
+        for v in r.array_iter()? {
+            let v = match v? {
+                EnumValue::I16(v) => v as i32,
+                EnumValue::I32(v) => v,
+                EnumValue::I64(v) => v as i32,
+                EnumValue::U8(v) => v as i32,
+                EnumValue::U16(v) => v as i32,
+                EnumValue::U32(v) => v as i32,
+                v => panic!("not an integer: {:?}", v),
+            };
+            assert_eq!(v, arr[n]);
+            n += 1;
+        }
+        assert_eq!(n, 5);
+        assert_eq!(r.read_u8()?, 9);
+
+        // 未读完时drop，也移动到容器之后
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        assert_eq!(r.array_iter()?.take(2).count(), 2);
+        assert_eq!(r.read_u8()?, 9);
+
+        // 不是通用数组
+        let mut r = ReadBuffer::new(&w.get_byte()[w.len() - 1..], 0);
+        assert!(r.array_iter().is_err());
+        assert_eq!(r.read_u8()?, 9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_iter() -> Result<(), ReadBonErr> {
+        let map: HashMap<String, u32> = [("a", 1u32), ("bb", 300), ("ccc", 70000)]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        // HashMap的Encode不使用容器，这里将其键值对写入通用map
+        let mut w = WriteBuffer::new();
+        w.write_container_counted(
+            3,
+            map.len() as u32,
+            false,
+            &map,
+            |w, map| {
+                for (k, v) in map.iter() {
+                    k.encode(w);
+                    v.encode(w);
+                }
+            },
+            None,
+        );
+        w.write_u8(9);
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        let mut read = HashMap::new();
+        for entry in r.map_iter()? {
+            let (k, v) = match entry? {
+                (EnumValue::Str(k), EnumValue::U8(v)) => (k, v as u32),
+                (EnumValue::Str(k), EnumValue::U16(v)) => (k, v as u32),
+                (EnumValue::Str(k), EnumValue::U32(v)) => (k, v),
+                e => panic!("unexpected entry: {:?}", e),
+            };
+            read.insert(k, v);
+        }
+        assert_eq!(read, map);
+        assert_eq!(r.read_u8()?, 9);
+
+        // 键值对不完整
+        let mut w = WriteBuffer::new();
+        w.write_container_typed(
+            3,
+            &(),
+            |w, _| {
+                w.write_lengthen(1);
+                w.write_utf8("a");
+            },
+            None,
+        );
+        let mut r = ReadBuffer::new(w.get_byte(), 0);
+        let entries: Vec<_> = r.map_iter()?.collect();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].is_err());
+        assert!(r.bytes.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_hash() -> Result<(), ReadBonErr> {
+        // 非最短的编码：64位写的小整数、4字节长度的容器、键无序的map
+        let mut w = WriteBuffer::new();
+        w.bytes.extend_from_slice(&[40, 7, 0, 0, 0, 0, 0, 0, 0]);
+        w.tail += 9;
+        w.write_container_counted(
+            2,
+            2,
+            true,
+            &(),
+            |w, _| {
+                w.write_f64(1.5);
+                w.write_container_counted(
+                    3,
+                    2,
+                    false,
+                    &(),
+                    |w, _| {
+                        w.write_u32(300);
+                        w.write_nil();
+                        w.write_u32(2);
+                        w.write_bool(true);
+                    },
+                    None,
+                );
+            },
+            Some(0x10000),
+        );
+        let bytes = w.get_byte();
+        let mut r = ReadBuffer::new(bytes, 0);
+        let mut hashes = Vec::new();
+        while !r.bytes.is_empty() {
+            let (v, h) = r.read_and_hash()?;
+            assert_eq!(h, hash_bytes(&v.to_canonical_bytes()?));
+            hashes.push(h);
+        }
+        assert_eq!(hashes[0], content_hash(&bytes[..9])?);
+        assert_eq!(hashes[1], content_hash(&bytes[9..])?);
+        assert_ne!(hashes[1], hash_bytes(&bytes[9..]));
+
+        // 小map和BigInt，read不支持，哈希与content_hash相同
+        let mut w = WriteBuffer::new();
+        let entries = [(1u8, "a".to_string()), (2, "b".to_string())];
+        w.write_small_map(entries.iter().map(|(k, v)| (k, v)));
+        let small = w.get_byte().to_vec();
+        let (v, h) = ReadBuffer::new(&small, 0).read_and_hash()?;
+        assert_eq!(h, content_hash(&small)?);
+        let expect = EnumValue::Map(vec![
+            (EnumValue::U8(1), EnumValue::Str("a".to_string())),
+            (EnumValue::U8(2), EnumValue::Str("b".to_string())),
+        ]);
+        assert_eq!(format!("{:?}", v), format!("{:?}", expect));
+        let mut big = vec![249];
+        big.extend_from_slice(&[0x12; 16]);
+        big.extend_from_slice(&[0; 16]);
+        let (v, h) = ReadBuffer::new(&big, 0).read_and_hash()?;
+        assert_eq!(h, content_hash(&big)?);
+        let n = u128::from_le_bytes([0x12; 16]);
+        assert_eq!(format!("{:?}", v), format!("{:?}", EnumValue::U128(n)));
+        big[0] = 250;
+        let (v, h) = ReadBuffer::new(&big, 0).read_and_hash()?;
+        assert_eq!(h, content_hash(&big)?);
+        assert_eq!(
+            format!("{:?}", v),
+            format!("{:?}", EnumValue::I128(-(n as i128)))
+        );
+        // 在容器中
+        let mut w = WriteBuffer::new();
+        w.write_container_counted(
+            2,
+            2,
+            true,
+            &(),
+            |w, _| {
+                w.write_raw(&big);
+                w.write_raw(&small);
+            },
+            None,
+        );
+        let (_, h) = ReadBuffer::new(w.get_byte(), 0).read_and_hash()?;
+        assert_eq!(h, content_hash(w.get_byte())?);
+        // 超出128位
+        big[20] = 1;
+        assert!(content_hash(&big).is_ok());
+        assert!(ReadBuffer::new(&big, 0).read_and_hash().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_align() {
+        let write = |bb: &mut WriteBuffer| {
+            bb.write_u8(7);
+            bb.write_utf8("hello");
+            bb.write_nil();
+            bb.write_container_typed(
+                0x1234,
+                &vec![1u32, 2, 3],
+                |w, v| {
+                    for i in v.iter() {
+                        w.write_u32(*i);
+                    }
+                },
+                None,
+            );
+            bb.write_f64(1.5);
+        };
+        let mut plain = WriteBuffer::new();
+        write(&mut plain);
+        let expect = ReadBuffer::new(plain.get_byte(), 0).read_all().unwrap();
+        for align in [1, 4, 6, 8, 16, 100, 1000] {
+            let mut bb = WriteBuffer::new();
+            bb.set_debug_align(align);
+            bb.set_track_offsets(true);
+            write(&mut bb);
+            for offset in bb.value_offsets() {
+                assert_eq!(offset % align, 0);
+            }
+            let bytes = bb.get_byte();
+            let values = ReadBuffer::new(bytes, 0)
+                .with_debug_padding()
+                .read_all()
+                .unwrap();
+            assert_eq!(format!("{:?}", values), format!("{:?}", expect));
+            let mut rb = ReadBuffer::new(bytes, 0).with_debug_padding();
+            for _ in 0..expect.len() {
+                rb.skip_value().unwrap();
+            }
+            assert!(rb.bytes.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_debug_align_decode() -> Result<(), ReadBonErr> {
+        // 填充会出现在Vec、Duration、元组等由多个部分组成的值内部，类型化的读取需要跳过
+        let v = vec![1u32, 2, 3];
+        let d = Duration::new(5, 300);
+        let t = ("pi_bon".to_string(), 70000u32, Some(1.5f64));
+        let m: HashMap<u8, Vec<u8>> = [(1, vec![9u8]), (2, vec![])].into_iter().collect();
+        for align in [1, 7, 16, 300] {
+            let mut wb = WriteBuffer::new();
+            wb.set_debug_align(align);
+            v.encode(&mut wb);
+            d.encode(&mut wb);
+            t.encode(&mut wb);
+            m.encode(&mut wb);
+            wb.write_bool(true);
+            wb.write_bin(&[1, 2, 3], 0..3);
+            let mut rb = ReadBuffer::new(wb.get_byte(), 0).with_debug_padding();
+            assert_eq!(Vec::<u32>::decode(&mut rb)?, v);
+            assert_eq!(Duration::decode(&mut rb)?, d);
+            assert_eq!(<(String, u32, Option<f64>)>::decode(&mut rb)?, t);
+            assert_eq!(HashMap::<u8, Vec<u8>>::decode(&mut rb)?, m);
+            assert!(rb.read_bool()?);
+            assert_eq!(rb.read_bin()?, vec![1, 2, 3]);
+            assert!(rb.bytes.is_empty());
+        }
+
+        // 未开启with_debug_padding时，write_sized写入的多个nil不会被当作填充跳过
+        let mut wb = WriteBuffer::new();
+        wb.write_sized(&(None::<u8>, None::<u8>));
+        wb.write_sized(&(None::<u8>, None::<u8>, None::<u8>, None::<u8>));
+        wb.write_u8(7);
+        assert_eq!(wb.get_byte()[..6], [186, 0, 0, 0, 0, 0]);
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(rb.read_sized::<(Option<u8>, Option<u8>)>()?, (None, None));
+        assert_eq!(
+            rb.read_sized::<(Option<u8>, Option<u8>, Option<u8>, Option<u8>)>()?,
+            (None, None, None, None)
+        );
+        assert_eq!(rb.read_u8()?, 7);
+        let values = ReadBuffer::new(wb.get_byte(), 0).read_all()?;
+        assert_eq!(values.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock() -> Result<(), ReadBonErr> {
+        let m = Mutex::new(70000u32);
+        let l = RwLock::new("pi_bon".to_string());
+        let mut wb = WriteBuffer::new();
+        m.encode(&mut wb);
+        l.encode(&mut wb);
+        let mut plain = WriteBuffer::new();
+        plain.write_u32(70000);
+        plain.write_utf8("pi_bon");
+        assert_eq!(wb.get_byte(), plain.get_byte());
+
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(Mutex::<u32>::decode(&mut rb)?.into_inner().unwrap(), 70000);
+        assert_eq!(
+            RwLock::<String>::decode(&mut rb)?.into_inner().unwrap(),
+            "pi_bon"
+        );
+
+        // 中毒的锁仍写入其中的数据
+        let m = Arc::new(Mutex::new(5u32));
+        let m2 = m.clone();
+        let _ = std::thread::spawn(move || {
+            let _g = m2.lock().unwrap();
+            panic!("poison");
+        })
+        .join();
+        assert!(m.is_poisoned());
+        let mut wb = WriteBuffer::new();
+        m.encode(&mut wb);
+        assert_eq!(ReadBuffer::new(wb.get_byte(), 0).read_u32()?, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_common_number_canonical() {
+        let bytes = |f: &dyn Fn(&mut WriteBuffer)| {
+            let mut wb = WriteBuffer::new();
+            f(&mut wb);
+            wb.get_byte().clone()
+        };
+        for v in -1i64..=20 {
+            let expect = bytes(&|w| w.write_i64(v));
+            if v < 20 {
+                // -1~19使用单字节的常用数字编码
+                assert_eq!(expect, vec![(v + 16) as u8]);
+            }
+            let mut all: Vec<(&str, Vec<u8>)> = vec![
+                ("i8", bytes(&|w| w.write_i8(v as i8))),
+                ("i16", bytes(&|w| w.write_i16(v as i16))),
+                ("i32", bytes(&|w| w.write_i32(v as i32))),
+                ("i128", bytes(&|w| w.write_i128(v as i128))),
+            ];
+            if v >= 0 {
+                all.push(("u8", bytes(&|w| w.write_u8(v as u8))));
+                all.push(("u16", bytes(&|w| w.write_u16(v as u16))));
+                all.push(("u32", bytes(&|w| w.write_u32(v as u32))));
+                all.push(("u64", bytes(&|w| w.write_u64(v as u64))));
+                all.push(("u128", bytes(&|w| w.write_u128(v as u128))));
+                all.push(("u64 small", bytes(&|w| w.write_u64_hint(v as u64, true))));
+                all.push(("u64 large", bytes(&|w| w.write_u64_hint(v as u64, false))));
+            }
+            for (name, b) in all {
+                assert_eq!(b, expect, "write_{} of {}", name, v);
+            }
+            assert_eq!(ReadBuffer::new(&expect, 0).read_i64().unwrap(), v);
+        }
+    }
+
+    #[test]
+    fn test_move_part() {
+        // 向后移动，超出长度
+        let mut bytes = vec![1, 2, 3, 4, 5];
+        move_part(&mut bytes, 1..5, 3);
+        assert_eq!(bytes, vec![1, 2, 3, 2, 3, 4, 5]);
+        // 向前移动
+        let mut bytes = vec![1, 2, 3, 4, 5];
+        move_part(&mut bytes, 2..5, 0);
+        assert_eq!(bytes, vec![3, 4, 5, 4, 5]);
+
+        // 预留的容器头不够，需要扩展并后移容器体
+        let body: Vec<u8> = (0..100).collect();
+        let mut wb = WriteBuffer::new();
+        wb.write_container(
+            &body,
+            |w, v| {
+                w.write_raw(&0x1234u32.to_le_bytes());
+                w.write_raw(v);
+            },
+            Some(4),
+        );
+        let mut expect = vec![245, 104, 0x34, 0x12, 0, 0];
+        expect.extend_from_slice(&body);
+        assert_eq!(wb.get_byte(), &expect);
+        // 预留的容器头过多，需要前移容器体
+        let mut wb = WriteBuffer::new();
+        wb.write_container(
+            &body[..10].to_vec(),
+            |w, v| {
+                w.write_raw(&0x1234u32.to_le_bytes());
+                w.write_raw(v);
+            },
+            Some(100000),
+        );
+        let mut expect = vec![194, 0x34, 0x12, 0, 0];
+        expect.extend_from_slice(&body[..10]);
+        assert_eq!(wb.get_byte(), &expect);
+    }
 
-        let mut read_buf = ReadBuffer::new(buf.get_byte(), 0);
-        assert_eq!(read_buf.read_bin()?, arr);
+    #[test]
+    fn test_hash_with() -> Result<(), ReadBonErr> {
+        // 记录送入的数据
+        #[derive(Default)]
+        struct Recorder(Vec<Vec<u8>>);
+        impl ContentHasher for Recorder {
+            fn update(&mut self, bytes: &[u8]) {
+                self.0.push(bytes.to_vec());
+            }
+            fn finish(self) -> Vec<u8> {
+                self.0.concat()
+            }
+        }
 
+        let mut wb = WriteBuffer::new();
+        wb.write_u64_hint(7, false);
+        wb.write_utf8("pi");
+        wb.write_container_counted(
+            1,
+            2,
+            true,
+            &(),
+            |w, _| {
+                w.write_u32(1);
+                w.write_u32(300);
+            },
+            None,
+        );
+        let canonical = canonicalize(wb.get_byte())?;
+        let seen = wb.hash_with::<Recorder>()?;
+        assert_eq!(seen, canonical);
+        // 内容相同的值哈希相同
+        let mut plain = WriteBuffer::new();
+        plain.write_u8(7);
+        plain.write_utf8("pi");
+        plain.write_container_counted(
+            1,
+            2,
+            false,
+            &(),
+            |w, _| {
+                w.write_u32(1);
+                w.write_u32(300);
+            },
+            None,
+        );
+        assert_eq!(plain.get_byte(), &canonical);
+        use std::collections::hash_map::DefaultHasher;
+        assert_eq!(
+            wb.hash_with::<DefaultHasher>()?,
+            plain.hash_with::<DefaultHasher>()?
+        );
+        #[cfg(feature = "blake3")]
+        assert_eq!(
+            wb.hash_with::<blake3::Hasher>()?,
+            blake3::hash(&canonical).as_bytes().to_vec()
+        );
+        #[cfg(feature = "sha2")]
+        assert_eq!(
+            wb.hash_with::<sha2::Sha256>()?,
+            <sha2::Sha256 as sha2::Digest>::digest(&canonical).to_vec()
+        );
         Ok(())
     }
 
-    //测试大小比较
     #[test]
-    fn test_ord() {
-        let mut buf1 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf2 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf3 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf4 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf5 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf6 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf7 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf8 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf9 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf10 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf11 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf12 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf13 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf14 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf15 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf16 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf17 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf18 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf19 = WriteBuffer::with_bytes(Vec::new(), 0);
-        let mut buf20 = WriteBuffer::with_bytes(Vec::new(), 0);
-        buf1.write_nil();
-        buf2.write_bool(false);
-        buf3.write_bool(true);
-        buf4.write_f32(0.0);
-        buf5.write_f32(1.0);
-        buf6.write_f32(5.1);
-        buf7.write_f32(5.6);
-        buf8.write_f64(7.5);
-        buf9.write_f64(3.4);
-        buf10.write_i8(-1);
-        buf11.write_i8(-1);
-        buf12.write_i8(120);
-        buf13.write_u32(10);
-        buf14.write_i32(5);
-        buf15.write_utf8("abcdefg");
-        buf16.write_utf8("abcdefgh");
-        buf17.write_utf8("abcddfgh");
-        buf18.write_bin(&[5; 10], 0..10);
-        buf19.write_bin(&[6; 5], 0..5);
-        buf20.write_bin(&[6; 5], 0..5);
-        let read_buf1 = ReadBuffer::new(buf1.get_byte(), 0);
-        let read_buf2 = ReadBuffer::new(buf2.get_byte(), 0);
-        let read_buf3 = ReadBuffer::new(buf3.get_byte(), 0);
-        let read_buf4 = ReadBuffer::new(buf4.get_byte(), 0);
-        let read_buf5 = ReadBuffer::new(buf5.get_byte(), 0);
-        let read_buf6 = ReadBuffer::new(buf6.get_byte(), 0);
-        let read_buf7 = ReadBuffer::new(buf7.get_byte(), 0);
-        let read_buf8 = ReadBuffer::new(buf8.get_byte(), 0);
-        let read_buf9 = ReadBuffer::new(buf9.get_byte(), 0);
-        let read_buf10 = ReadBuffer::new(buf10.get_byte(), 0);
-        let read_buf11 = ReadBuffer::new(buf11.get_byte(), 0);
-        let read_buf12 = ReadBuffer::new(buf12.get_byte(), 0);
-        let read_buf13 = ReadBuffer::new(buf13.get_byte(), 0);
-        let read_buf14 = ReadBuffer::new(buf14.get_byte(), 0);
-        let read_buf15 = ReadBuffer::new(buf15.get_byte(), 0);
-        let read_buf16 = ReadBuffer::new(buf16.get_byte(), 0);
-        let read_buf17 = ReadBuffer::new(buf17.get_byte(), 0);
-        let read_buf18 = ReadBuffer::new(buf18.get_byte(), 0);
-        let read_buf19 = ReadBuffer::new(buf19.get_byte(), 0);
-        let read_buf20 = ReadBuffer::new(buf20.get_byte(), 0);
-        assert_eq!(read_buf1 < read_buf2, true); //测试null, false
-        assert_eq!(read_buf2 < read_buf3, true); //测试false, true
-        assert_eq!(read_buf2 < read_buf4, true); //测试false, 0.0
-        assert_eq!(read_buf4 < read_buf5, true); //测试0.0, 1.0
-        assert_eq!(read_buf9 < read_buf7, true); //测试3.4, 5.6
-        assert_eq!(read_buf6 < read_buf7, true); //测试5.1, 5.6
-        assert_eq!(read_buf7 < read_buf8, true); //测试5.6, 7.5
-        assert_eq!(read_buf10 < read_buf6, true); //测试-1, 5.6
-        assert_eq!(read_buf10 == read_buf11, true); //测试-1, -1
-        assert_eq!(read_buf11 < read_buf12, true); //测试-1, 200
-        assert_eq!(read_buf12 > read_buf13, true); //测试 120, 10
-        assert_eq!(read_buf13 > read_buf14, true); //测试 10, 5
-        assert_eq!(read_buf1 < read_buf15, true); //测试 null, "abcdefg"
-        assert_eq!(read_buf4 < read_buf15, true); //测试 0.0, "abcdefg"
-        assert_eq!(read_buf6 < read_buf15, true); //测试 5.1, "abcdefg"
-        assert_eq!(read_buf12 < read_buf15, true); //测试 200, "abcdefg"
-        assert_eq!(read_buf13 < read_buf15, true); //测试 10, "abcdefg"
-        assert_eq!(read_buf15 < read_buf16, true); //测试 "abcdefg", "abcdefgh"
-        assert_eq!(read_buf15 > read_buf17, true); //测试 "abcdefg", "abcddfgh"
-        assert_eq!(read_buf13 < read_buf18, true); //测试 10, &[5;10]
-        assert_eq!(read_buf6 < read_buf18, true); //测试 5.1, &[5;10]
-        assert_eq!(read_buf3 < read_buf18, true); //测试 true, &[5;10]
-        assert_eq!(read_buf15 < read_buf18, true); //测试 "abcdefg", &[5;10]
-        assert_eq!(read_buf18 < read_buf19, true); //测试 &[5;10], &[6;5]
-        assert_eq!(read_buf19 == read_buf20, true); //测试 &[6;5], &[6;5]
-        let mut buf1 = WriteBuffer::with_bytes(Vec::new(), 0);
-        buf1.write_nil(); //null
-        buf1.write_bool(false); //false
-        buf1.write_bool(true); //true
-        buf1.write_f32(0.0); //0.0
-        buf1.write_f32(1.0); //1.0
-        buf1.write_f32(5.0); //1.0
-        buf1.write_u8(5);
-        buf1.write_utf8("abc");
-        let mut buf2 = WriteBuffer::with_bytes(Vec::new(), 0);
-        buf2.write_utf8("acc");
-        buf2.write_u8(5);
-        assert_eq!(
-            ReadBuffer::new(buf1.get_byte(), 0) < ReadBuffer::new(buf2.get_byte(), 0),
-            true
-        ); //测试 abc,5 < acc,5
+    fn test_truncated_no_panic() {
+        // 用write写入一个值，截断为每种长度后用read读取，应返回错误而不是panic
+        fn check<T: fmt::Debug>(
+            name: &str,
+            write: impl Fn(&mut WriteBuffer),
+            read: impl Fn(&mut ReadBuffer) -> Result<T, ReadBonErr>,
+        ) {
+            let mut wb = WriteBuffer::new();
+            write(&mut wb);
+            let bytes = wb.get_byte();
+            assert!(read(&mut ReadBuffer::new(bytes, 0)).is_ok(), "{}", name);
+            for len in 0..bytes.len() {
+                let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    read(&mut ReadBuffer::new(&bytes[..len], 0)).is_err()
+                }));
+                match r {
+                    Ok(is_err) => assert!(is_err, "{} truncated to {} is ok", name, len),
+                    Err(_) => panic!("{} truncated to {} panicked", name, len),
+                }
+            }
+        }
+        check("bool", |w| w.write_bool(true), |r| r.read_bool());
+        check("u8", |w| w.write_u8(200), |r| r.read_u8());
+        check("u16", |w| w.write_u16(60000), |r| r.read_u16());
+        check("u32", |w| w.write_u32(u32::MAX), |r| r.read_u32());
+        check(
+            "u32 strict",
+            |w| w.write_u32(70000),
+            |r| r.read_u32_strict(),
+        );
+        check("u64", |w| w.write_u64(u64::MAX), |r| r.read_u64());
+        check("u64 6", |w| w.write_u64(1 << 44), |r| r.read_u64());
+        check("usize", |w| w.write_u64(1 << 40), |r| r.read_usize());
+        check("u128", |w| w.write_u128(u128::MAX), |r| r.read_u128());
+        check("i8", |w| w.write_i8(-100), |r| r.read_i8());
+        check("i16", |w| w.write_i16(-30000), |r| r.read_i16());
+        check("i32", |w| w.write_i32(i32::MIN + 1), |r| r.read_i32());
+        check(
+            "i32 strict",
+            |w| w.write_i32(-70000),
+            |r| r.read_i32_strict(),
+        );
+        check("i64", |w| w.write_i64(i64::MIN + 1), |r| r.read_i64());
+        check("isize", |w| w.write_i64(-(1 << 40)), |r| r.read_isize());
+        check("i128", |w| w.write_i128(i128::MIN + 1), |r| r.read_i128());
+        check(
+            "any int",
+            |w| w.write_i128(i128::MIN + 1),
+            |r| r.read_any_int(),
+        );
+        check("f32", |w| w.write_f32(1.5), |r| r.read_f32());
+        check("f64", |w| w.write_f64(1.1), |r| r.read_f64());
+        check("f64 from f32", |w| w.write_f32(1.1), |r| r.read_f64());
+        check(
+            "lengthen",
+            |w| w.write_lengthen(0x1FFFFFFF),
+            |r| r.read_lengthen(),
+        );
+        let long = "a".repeat(300);
+        check("utf8", |w| w.write_utf8("abc"), |r| r.read_utf8());
+        check("utf8 long", |w| w.write_utf8(&long), |r| r.read_utf8());
+        check(
+            "utf8 strict",
+            |w| w.write_utf8(&long),
+            |r| r.read_utf8_strict(),
+        );
+        check(
+            "str",
+            |w| w.write_utf8(&long),
+            |r| r.read_str().map(|s| s.len()),
+        );
+        check(
+            "str bytes",
+            |w| w.write_utf8(&long),
+            |r| r.read_str_bytes().map(|s| s.len()),
+        );
+        check("utf16", |w| w.write_utf16(&[1, 2, 3]), |r| r.read_utf16());
+        check(
+            "bin",
+            |w| w.write_bin(long.as_bytes(), 0..300),
+            |r| r.read_bin(),
+        );
+        check(
+            "bin ref",
+            |w| w.write_bin(&[1, 2, 3], 0..3),
+            |r| r.read_bin_ref().map(|b| b.len()),
+        );
+        check(
+            "coords",
+            |w| w.write_coords(&[[1.0, 2.0]]),
+            |r| r.read_coords(),
+        );
+        check(
+            "tagged",
+            |w| w.write_tagged_int(TaggedInt::Signed(-5)),
+            |r| r.read_tagged_int(),
+        );
+        check(
+            "decimal",
+            |w| w.write_decimal(-12345, 2),
+            |r| r.read_decimal(),
+        );
+        check(
+            "sized",
+            |w| w.write_sized(&70000u32),
+            |r| r.read_sized::<u32>(),
+        );
+        check(
+            "vec",
+            |w| vec![1u32, 70000].encode(w),
+            |r| Vec::<u32>::decode(r),
+        );
+        check(
+            "value",
+            |w| {
+                w.write_value(&EnumValue::array(vec![
+                    EnumValue::int(-300),
+                    EnumValue::string("pi"),
+                ]))
+            },
+            |r| r.read(),
+        );
+        check(
+            "typed",
+            |w| w.write_u64(1 << 40),
+            |r| r.read_typed().map(|_| ()),
+        );
+        check(
+            "raw",
+            |w| w.write_u64(1 << 40),
+            |r| r.read_raw().map(|_| ()),
+        );
+        check("atomic", |w| w.write_i64(-(1 << 40)), |r| r.read_atomic());
+        check("skip", |w| w.write_utf8(&long), |r| r.skip_value());
+        check(
+            "bin ref long",
+            |w| w.write_bin(long.as_bytes(), 0..300),
+            |r| r.read_bin_ref().map(|b| b.len()),
+        );
+        check(
+            "typed bin",
+            |w| w.write_bin(long.as_bytes(), 0..300),
+            |r| r.read_typed().map(|_| ()),
+        );
+        let coords = vec![[1.0, 2.0]; 20];
+        check(
+            "coords long",
+            |w| w.write_coords(&coords),
+            |r| r.read_coords(),
+        );
+
+        // 手工构造的截断数据和超大的6字节长度
+        let bad: [&[u8]; 6] = [
+            &[178, 24],
+            &[29, 179],
+            &[179, 0, 0, 0, 0, 1, 0],
+            &[110, 0, 0, 0, 0, 1, 0],
+            &[29, 179, 0, 0, 0, 0, 1, 0],
+            &[248, 0, 0, 0, 0, 1, 0],
+        ];
+        for bytes in bad.iter() {
+            assert!(ReadBuffer::new(bytes, 0).read_coords().is_err());
+            if bytes[0] == 29 {
+                // 坐标数量之后是截断的二进制
+                continue;
+            }
+            assert!(ReadBuffer::new(bytes, 0).read_bin_ref().is_err());
+            assert!(ReadBuffer::new(bytes, 0).read_typed().is_err());
+            assert!(ReadBuffer::new(bytes, 0).read_utf8().is_err());
+            assert!(ReadBuffer::new(bytes, 0).read().is_err());
+            assert!(ReadBuffer::new(bytes, 0)
+                .read_container(|_, _, _| Ok(()))
+                .is_err());
+        }
     }
 
     #[test]
-    fn test_container_cmp() {
-        // struct xxx { x: bool, y: [&i32]}
-        let mut w1 = WriteBuffer::new();
-        let v = vec![1, 2, 3, 4, 100];
-        w1.write_container(
-            &v,
-            |w1, e| {
-                let hash = 0x12345678u32.to_le_bytes();
-                w1.bytes.extend_from_slice(&hash);
-                w1.tail += 4;
-                w1.write_bool(true);
-                for elem in e {
-                    w1.write_i32(elem.clone());
-                }
+    fn test_sparse_array() -> Result<(), ReadBonErr> {
+        let mut arr = vec![0.0f32; 10000];
+        for (i, v) in [
+            (0, 1.5),
+            (17, -2.0),
+            (4096, 0.25),
+            (7777, 3.0),
+            (9999, 100.0),
+        ] {
+            arr[i] = v;
+        }
+        let mut wb = WriteBuffer::new();
+        wb.write_sparse_array(&arr);
+        // 只写入5个非默认值的元素，普通数组需要上万字节
+        assert!(wb.len() <= 50, "sparse array len {}", wb.len());
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(rb.read_sparse_array::<f32>()?, arr);
+        assert!(rb.bytes.is_empty());
+
+        let names = vec![String::new(), "pi".to_string(), String::new()];
+        let mut wb = WriteBuffer::new();
+        wb.write_sparse_array(&names);
+        wb.write_sparse_array::<u32>(&[]);
+        let mut rb = ReadBuffer::new(wb.get_byte(), 0);
+        assert_eq!(rb.read_sparse_array::<String>()?, names);
+        assert_eq!(rb.read_sparse_array::<u32>()?, Vec::<u32>::new());
+
+        // 序号不递增
+        let mut wb = WriteBuffer::new();
+        wb.write_container_typed(
+            6,
+            &(),
+            |w, _| {
+                w.write_u32(10);
+                w.write_u32(5);
+                w.write_u32(1);
+                w.write_u32(3);
+                w.write_u32(2);
             },
             None,
         );
-
-        let mut w2 = WriteBuffer::new();
-        let v2 = vec![1, 2, 3, 4, 99];
-        w2.write_container(
-            &v2,
-            |w2, e| {
-                let hash = 0x12345678u32.to_le_bytes();
-                w2.bytes.extend_from_slice(&hash);
-                w2.tail += 4;
-                w2.write_bool(false);
-                for elem in e {
-                    w2.write_i32(elem.clone());
-                }
+        assert!(ReadBuffer::new(wb.get_byte(), 0)
+            .read_sparse_array::<u32>()
+            .is_err());
+        // 不是稀疏数组
+        let mut wb = WriteBuffer::new();
+        wb.write_tagged_int(TaggedInt::Signed(1));
+        assert!(ReadBuffer::new(wb.get_byte(), 0)
+            .read_sparse_array::<u32>()
+            .is_err());
+        // 数据中的长度无法分配，返回Err而不是abort
+        let bytes = [187, 6, 0, 0, 0, 10, 18, 21];
+        assert!(ReadBuffer::new(&bytes, 0)
+            .read_sparse_array::<u32>()
+            .is_err());
+        let mut wb = WriteBuffer::new();
+        wb.write_container_typed(
+            6,
+            &(),
+            |w, _| {
+                w.write_u64(u64::MAX >> 4);
             },
             None,
         );
+        assert!(ReadBuffer::new(wb.get_byte(), 0)
+            .read_sparse_array::<u64>()
+            .is_err());
+        Ok(())
+    }
 
-        assert!(w1 > w2);
+    #[test]
+    fn test_partial_cmp_malformed() {
+        let mut values = Vec::new();
+        let mut wb = WriteBuffer::new();
+        wb.write_u64(1 << 40);
+        values.push(wb.unwrap());
+        let mut wb = WriteBuffer::new();
+        wb.write_i64(-(1 << 40));
+        values.push(wb.unwrap());
+        let mut wb = WriteBuffer::new();
+        wb.write_f64(1.1);
+        values.push(wb.unwrap());
+        let mut wb = WriteBuffer::new();
+        wb.write_utf8(&"a".repeat(100));
+        values.push(wb.unwrap());
+        let mut wb = WriteBuffer::new();
+        wb.write_bin(&[7; 100], 0..100);
+        values.push(wb.unwrap());
+        let mut wb = WriteBuffer::new();
+        wb.write_value(&EnumValue::array(vec![
+            EnumValue::int(-300),
+            EnumValue::string("pi"),
+        ]));
+        values.push(wb.unwrap());
+
+        let mut bad: Vec<Vec<u8>> = vec![vec![], vec![8], vec![253], vec![255, 1], vec![250]];
+        for v in values.iter() {
+            for len in 1..v.len() {
+                bad.push(v[..len].to_vec());
+            }
+        }
+        for b in bad.iter() {
+            for v in values.iter() {
+                let (rb, rv) = (ReadBuffer::new(b, 0), ReadBuffer::new(v, 0));
+                assert_eq!(rb.partial_cmp(&rv), None, "{:?} <=> {:?}", b, v);
+                assert_eq!(rv.partial_cmp(&rb), None, "{:?} <=> {:?}", v, b);
+                let (mut r1, mut r2) = (ReadBuffer::new(b, 0), ReadBuffer::new(v, 0));
+                assert_eq!(partial_cmp_with_epsilon(&mut r1, &mut r2, 0.1), None);
+            }
+        }
+        // 完整的值之间仍可比较
+        for v1 in values.iter() {
+            for v2 in values.iter() {
+                assert!(ReadBuffer::new(v1, 0)
+                    .partial_cmp(&ReadBuffer::new(v2, 0))
+                    .is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_try_cmp() {
+        let mut w1 = WriteBuffer::new();
+        w1.write_u32(300);
+        let mut w2 = WriteBuffer::new();
+        w2.write_utf8("pi");
+        let (r1, r2) = (
+            ReadBuffer::new(w1.get_byte(), 0),
+            ReadBuffer::new(w2.get_byte(), 0),
+        );
+        assert_eq!(r1.try_cmp(&r2).unwrap(), Ordering::Less);
+        assert_eq!(r2.try_cmp(&r1).unwrap(), Ordering::Greater);
+
+        // 正负大整数按带符号的值比较
+        let bigint = |t: u8, low: u8| {
+            let mut v = vec![t, low];
+            v.extend_from_slice(&[1; 31]);
+            v
+        };
+        let cases = [
+            (bigint(249, 1), bigint(250, 1), Ordering::Greater),
+            (bigint(250, 1), bigint(249, 1), Ordering::Less),
+            (bigint(250, 1), bigint(250, 2), Ordering::Greater),
+            (bigint(249, 1), bigint(249, 2), Ordering::Less),
+            (bigint(250, 2), bigint(250, 2), Ordering::Equal),
+        ];
+        for (a, b, o) in cases.iter() {
+            let (ra, rb) = (ReadBuffer::new(a, 0), ReadBuffer::new(b, 0));
+            assert_eq!(ra.try_cmp(&rb).unwrap(), *o);
+            assert_eq!(ra.partial_cmp(&rb), Some(*o));
+        }
+
+        // 128位浮点数
+        let mut f128 = vec![8];
+        f128.extend_from_slice(&[0; 16]);
+        let e = ReadBuffer::new(&f128, 0).try_cmp(&r1).unwrap_err();
+        assert!(e.to_string().contains("128 bit float"), "{}", e);
+        // 不完整、保留的类型值
+        assert!(matches!(
+            ReadBuffer::new(&w1.get_byte()[..2], 0).try_cmp(&r1),
+            Err(ReadBonErr::Overflow { .. })
+        ));
+        assert!(matches!(
+            ReadBuffer::new(&[254], 0).try_cmp(&r1),
+            Err(ReadBonErr::ReservedType(254))
+        ));
+    }
+
+    #[test]
+    fn test_is_single_value() {
+        let mut wb = WriteBuffer::new();
+        wb.write_utf8("pi_bon");
+        assert!(is_single_value(wb.get_byte()).unwrap());
+        // Vec不使用容器包装，写为数量和各个元素
+        let mut wb = WriteBuffer::new();
+        vec![1u32, 300, 70000].encode(&mut wb);
+        assert!(!is_single_value(wb.get_byte()).unwrap());
+        let mut wb = WriteBuffer::new();
+        wb.write_value(&EnumValue::array(vec![
+            EnumValue::int(1),
+            EnumValue::string("pi"),
+        ]));
+        assert!(is_single_value(wb.get_byte()).unwrap());
+        // 两个值
+        wb.write_u8(1);
+        assert!(!is_single_value(wb.get_byte()).unwrap());
+        // 不完整
+        let bytes = wb.get_byte();
+        assert!(matches!(
+            is_single_value(&bytes[..bytes.len() - 3]),
+            Err(ReadBonErr::Overflow { .. })
+        ));
+        assert!(is_single_value(&[]).is_err());
+    }
+
+    #[test]
+    fn test_cmp_unsupported_float() {
+        let mut f128 = [0u8; 17];
+        f128[0] = 8;
+        let mut w = WriteBuffer::new();
+        w.write_f32(1.0);
+        let one = w.get_byte().clone();
+
+        {
+            let bin = &f128[..];
+            let mut b1 = ReadBuffer::new(bin, 0);
+            let mut b2 = ReadBuffer::new(&one, 0);
+            assert_eq!(partial_cmp(&mut b1, &mut b2), None);
+            let mut b1 = ReadBuffer::new(bin, 0);
+            let mut b2 = ReadBuffer::new(&one, 0);
+            assert_eq!(partial_cmp(&mut b2, &mut b1), None);
+            let mut b1 = ReadBuffer::new(bin, 0);
+            let mut b2 = ReadBuffer::new(&one, 0);
+            assert_eq!(partial_cmp_with_epsilon(&mut b1, &mut b2, 0.1), None);
+            assert_eq!(
+                ReadBuffer::new(bin, 0).partial_cmp(&ReadBuffer::new(&one, 0)),
+                None
+            );
+            assert!(ReadBuffer::new(bin, 0).read().is_err());
+            assert!(ReadBuffer::new(bin, 0).read_f32().is_err());
+            assert!(ReadBuffer::new(bin, 0).read_f64().is_err());
+        }
     }
 
     macro_rules! bench_container_cmp {
         ($size: expr, $func: ident) => {
+            #[cfg(feature = "bench")]
             #[bench]
             fn $func(b: &mut Bencher) {
                 let mut sort = vec![];
@@ -2663,6 +10015,7 @@ mod tests {
 
     macro_rules! bench_number {
         ($x:ty, $func:ident, $r: ident, $w: ident) => {
+            #[cfg(feature = "bench")]
             #[bench]
             fn $func(b: &mut Bencher) {
                 let v: $x = random();
@@ -2690,8 +10043,46 @@ mod tests {
     bench_number!(f32, bench_f32, read_f32, write_f32);
     bench_number!(f64, bench_f64, read_f64, write_f64);
 
+    // 只比较u64的写入，values生成1024个值：uniform为任意u64，small为90%小于256，large为90%大于32位
+    macro_rules! bench_write_u64 {
+        ($dist: ident, $func: ident, $hint: expr) => {
+            #[cfg(feature = "bench")]
+            #[bench]
+            fn $func(b: &mut Bencher) {
+                let mut rng = thread_rng();
+                let values: Vec<u64> = (0..1024)
+                    .map(|_| {
+                        let common = rng.gen_range(0..10) != 0;
+                        match stringify!($dist) {
+                            "small" if common => rng.gen_range(0..256),
+                            "large" if common => rng.gen_range(0x100000000..u64::MAX),
+                            _ => rng.gen(),
+                        }
+                    })
+                    .collect();
+                let mut buf = WriteBuffer::new();
+                b.iter(|| {
+                    buf.clear();
+                    for v in values.iter() {
+                        match $hint {
+                            Some(likely_small) => buf.write_u64_hint(*v, likely_small),
+                            None => buf.write_u64(*v),
+                        }
+                    }
+                });
+            }
+        };
+    }
+
+    bench_write_u64!(uniform, bench_write_u64_uniform, None::<bool>);
+    bench_write_u64!(small, bench_write_u64_small, None::<bool>);
+    bench_write_u64!(small, bench_write_u64_small_hint, Some(true));
+    bench_write_u64!(large, bench_write_u64_large, None::<bool>);
+    bench_write_u64!(large, bench_write_u64_large_hint, Some(false));
+
     macro_rules! bench_utf8 {
         ($size: expr, $func: ident) => {
+            #[cfg(feature = "bench")]
             #[bench]
             fn $func(b: &mut Bencher) {
                 let mut s = String::new();
@@ -2706,7 +10097,76 @@ mod tests {
         };
     }
 
+    // 短字符串（如字段名）的写入
+    #[cfg(feature = "bench")]
+    #[bench]
+    fn bench_utf8_tiny(b: &mut Bencher) {
+        let mut buf = WriteBuffer::new();
+        b.iter(|| {
+            buf.clear();
+            for _ in 0..16 {
+                buf.write_utf8("field_id");
+            }
+        });
+    }
+
+    #[cfg(feature = "bench")]
+    #[bench]
+    fn bench_bin_tiny(b: &mut Bencher) {
+        let mut buf = WriteBuffer::new();
+        let bin = [7u8; 8];
+        b.iter(|| {
+            buf.clear();
+            for _ in 0..16 {
+                buf.write_bin(&bin, 0..8);
+            }
+        });
+    }
+
     bench_utf8!(200, bench_utf8_small);
     bench_utf8!(2000, bench_utf8_median);
     bench_utf8!(20000, bench_utf8_large);
+
+    // 只比较读取，read_str借用底层二进制，read_utf8需要分配String
+    macro_rules! bench_read_str {
+        ($size: expr, $func: ident, $read: ident) => {
+            #[cfg(feature = "bench")]
+            #[bench]
+            fn $func(b: &mut Bencher) {
+                let mut s = String::new();
+                (0..$size).for_each(|_| s.push(thread_rng().gen::<char>()));
+                let mut buf = WriteBuffer::new();
+                buf.write_utf8(&s);
+                b.iter(|| {
+                    let mut read_buf = ReadBuffer::new(buf.get_byte(), 0);
+                    test::black_box(read_buf.$read().unwrap());
+                });
+            }
+        };
+    }
+
+    // 1MB的二进制，read_bin_ref借用底层二进制，read_bin需要复制
+    macro_rules! bench_read_bin {
+        ($func: ident, $read: ident) => {
+            #[cfg(feature = "bench")]
+            #[bench]
+            fn $func(b: &mut Bencher) {
+                let bin = vec![7u8; 1 << 20];
+                let mut buf = WriteBuffer::new();
+                buf.write_bin(&bin, 0..bin.len());
+                b.iter(|| {
+                    let mut read_buf = ReadBuffer::new(buf.get_byte(), 0);
+                    test::black_box(read_buf.$read().unwrap());
+                });
+            }
+        };
+    }
+
+    bench_read_bin!(bench_read_bin_1m, read_bin);
+    bench_read_bin!(bench_read_bin_ref_1m, read_bin_ref);
+
+    bench_read_str!(2000, bench_read_utf8_median, read_utf8);
+    bench_read_str!(2000, bench_read_str_median, read_str);
+    bench_read_str!(20000, bench_read_utf8_large, read_utf8);
+    bench_read_str!(20000, bench_read_str_large, read_str);
 }
@@ -0,0 +1,396 @@
+//! 为WriteBuffer实现serde::Serializer，需开启serde特性
+//!
+//! 映射规则：
+//! 基础类型使用对应的write_*方法，char写为字符串，None、()、单元结构体写为nil，Some(v)、新类型结构体写为内部的值
+//! 序列、元组写为通用数组（类型2），map写为通用map（类型3）
+//! 结构体、元组结构体写为类型为type_hash(结构体名)的容器，字段依次排列，没有元素数量
+//! 单元枚举值写为序号（u32），其它枚举值写为类型为type_hash(枚举名)的容器，第一个元素为序号，之后依次为字段
+
+use std::error::Error;
+use std::fmt;
+
+use serde::ser::{self, Serialize};
+
+use crate::{type_hash, WriteBuffer};
+
+/// 序列化时的错误，只由被序列化类型的Serialize实现产生
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerializeErr(pub String);
+
+impl fmt::Display for SerializeErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SerializeErr {:?}", self.0)
+    }
+}
+
+impl Error for SerializeErr {}
+
+impl ser::Error for SerializeErr {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerializeErr(msg.to_string())
+    }
+}
+
+/// serde序列化器，将值写入WriteBuffer
+///
+/// ```ignore
+/// let mut wb = WriteBuffer::new();
+/// value.serialize(&mut BonSerializer::new(&mut wb))?;
+/// ```
+pub struct BonSerializer<'w> {
+    buf: &'w mut WriteBuffer,
+}
+
+impl<'w> BonSerializer<'w> {
+    pub fn new(buf: &'w mut WriteBuffer) -> Self {
+        BonSerializer { buf }
+    }
+
+    // 开始写一个容器，写入类型，count为Some时写入元素数量，Some(None)表示数量未知，结束时回填
+    fn begin<'s>(&'s mut self, type_id: u32, count: Option<Option<usize>>) -> Compound<'s, 'w> {
+        let header = self.buf.begin_container(None);
+        self.buf.bytes.extend_from_slice(&type_id.to_le_bytes());
+        self.buf.tail += 4;
+        let count_pos = match count {
+            Some(Some(len)) => {
+                self.buf.write_lengthen(len as u32);
+                None
+            }
+            Some(None) => {
+                let pos = self.buf.bytes.len();
+                self.buf.write_lengthen_fixed(0);
+                Some(pos)
+            }
+            None => None,
+        };
+        Compound {
+            ser: self,
+            header,
+            count_pos,
+            count: 0,
+        }
+    }
+}
+
+/// 序列化容器的中间状态
+pub struct Compound<'s, 'w> {
+    ser: &'s mut BonSerializer<'w>,
+    header: (usize, usize),
+    // 数量未知时，数量的位置
+    count_pos: Option<usize>,
+    count: u32,
+}
+
+impl<'s, 'w> Compound<'s, 'w> {
+    fn element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerializeErr> {
+        self.count += 1;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<(), SerializeErr> {
+        let buf = &mut *self.ser.buf;
+        if let Some(pos) = self.count_pos {
+            buf.bytes[pos..pos + 4].copy_from_slice(&(0xC0000000 + self.count).to_be_bytes());
+        }
+        buf.end_container(self.header);
+        Ok(())
+    }
+}
+
+impl<'s, 'w> ser::Serializer for &'s mut BonSerializer<'w> {
+    type Ok = ();
+    type Error = SerializeErr;
+    type SerializeSeq = Compound<'s, 'w>;
+    type SerializeTuple = Compound<'s, 'w>;
+    type SerializeTupleStruct = Compound<'s, 'w>;
+    type SerializeTupleVariant = Compound<'s, 'w>;
+    type SerializeMap = Compound<'s, 'w>;
+    type SerializeStruct = Compound<'s, 'w>;
+    type SerializeStructVariant = Compound<'s, 'w>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), SerializeErr> {
+        self.buf.write_bool(v);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), SerializeErr> {
+        self.buf.write_i8(v);
+        Ok(())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), SerializeErr> {
+        self.buf.write_i16(v);
+        Ok(())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), SerializeErr> {
+        self.buf.write_i32(v);
+        Ok(())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), SerializeErr> {
+        self.buf.write_i64(v);
+        Ok(())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), SerializeErr> {
+        self.buf.write_i128(v);
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), SerializeErr> {
+        self.buf.write_u8(v);
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), SerializeErr> {
+        self.buf.write_u16(v);
+        Ok(())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), SerializeErr> {
+        self.buf.write_u32(v);
+        Ok(())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), SerializeErr> {
+        self.buf.write_u64(v);
+        Ok(())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), SerializeErr> {
+        self.buf.write_u128(v);
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), SerializeErr> {
+        self.buf.write_f32(v);
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), SerializeErr> {
+        self.buf.write_f64(v);
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), SerializeErr> {
+        self.buf.write_utf8(v.encode_utf8(&mut [0; 4]));
+        Ok(())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), SerializeErr> {
+        self.buf.write_utf8(v);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), SerializeErr> {
+        self.buf.write_bin(v, 0..v.len());
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), SerializeErr> {
+        self.buf.write_nil();
+        Ok(())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), SerializeErr> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), SerializeErr> {
+        self.buf.write_nil();
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), SerializeErr> {
+        self.buf.write_nil();
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), SerializeErr> {
+        self.buf.write_u32(variant_index);
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), SerializeErr> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), SerializeErr> {
+        let mut c = self.begin(type_hash(name), None);
+        c.element(&variant_index)?;
+        c.element(value)?;
+        c.end()
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Compound<'s, 'w>, SerializeErr> {
+        Ok(self.begin(2, Some(len)))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'s, 'w>, SerializeErr> {
+        Ok(self.begin(2, Some(Some(len))))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'s, 'w>, SerializeErr> {
+        Ok(self.begin(type_hash(name), None))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'s, 'w>, SerializeErr> {
+        let mut c = self.begin(type_hash(name), None);
+        c.element(&variant_index)?;
+        Ok(c)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound<'s, 'w>, SerializeErr> {
+        Ok(self.begin(3, Some(len)))
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'s, 'w>, SerializeErr> {
+        Ok(self.begin(type_hash(name), None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'s, 'w>, SerializeErr> {
+        let mut c = self.begin(type_hash(name), None);
+        c.element(&variant_index)?;
+        Ok(c)
+    }
+}
+
+impl<'s, 'w> ser::SerializeSeq for Compound<'s, 'w> {
+    type Ok = ();
+    type Error = SerializeErr;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerializeErr> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), SerializeErr> {
+        Compound::end(self)
+    }
+}
+
+impl<'s, 'w> ser::SerializeTuple for Compound<'s, 'w> {
+    type Ok = ();
+    type Error = SerializeErr;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerializeErr> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), SerializeErr> {
+        Compound::end(self)
+    }
+}
+
+impl<'s, 'w> ser::SerializeTupleStruct for Compound<'s, 'w> {
+    type Ok = ();
+    type Error = SerializeErr;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerializeErr> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), SerializeErr> {
+        Compound::end(self)
+    }
+}
+
+impl<'s, 'w> ser::SerializeTupleVariant for Compound<'s, 'w> {
+    type Ok = ();
+    type Error = SerializeErr;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerializeErr> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), SerializeErr> {
+        Compound::end(self)
+    }
+}
+
+impl<'s, 'w> ser::SerializeMap for Compound<'s, 'w> {
+    type Ok = ();
+    type Error = SerializeErr;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), SerializeErr> {
+        // map的数量为键值对的数量，只在写键时计数
+        self.element(key)
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), SerializeErr> {
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<(), SerializeErr> {
+        Compound::end(self)
+    }
+}
+
+impl<'s, 'w> ser::SerializeStruct for Compound<'s, 'w> {
+    type Ok = ();
+    type Error = SerializeErr;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), SerializeErr> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), SerializeErr> {
+        Compound::end(self)
+    }
+}
+
+impl<'s, 'w> ser::SerializeStructVariant for Compound<'s, 'w> {
+    type Ok = ();
+    type Error = SerializeErr;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), SerializeErr> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), SerializeErr> {
+        Compound::end(self)
+    }
+}